@@ -0,0 +1,1555 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::gamepad::GamepadButton;
+use crate::net::{AccessList, IpRule};
+
+/// 按消息类型过滤逐条事件日志
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// 只记录这些类型（为空表示不限制）
+    include: HashSet<String>,
+    /// 不记录这些类型（优先于 include）
+    exclude: HashSet<String>,
+}
+
+impl LogFilter {
+    /// 检查某类消息是否需要输出日志
+    pub fn allows(&self, kind: &str) -> bool {
+        if self.exclude.contains(kind) {
+            return false;
+        }
+        self.include.is_empty() || self.include.contains(kind)
+    }
+}
+
+/// 同时按住的按键数量达到上限后的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HeldKeyPolicy {
+    /// 拒绝新的按下
+    #[default]
+    Refuse,
+    /// 释放最早按下的按键，为新按键腾出位置
+    ReleaseOldest,
+}
+
+/// 摇杆输出模式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum JoystickMode {
+    /// 映射为方向键（WASD）
+    #[default]
+    Keys,
+    /// 速度控制：偏移量决定鼠标持续移动的速度，适合地图平移和精细瞄准
+    Velocity,
+    /// 滚轮控制：偏移量决定持续滚动的速度，适合阅读和浏览长页面
+    Scroll,
+    /// 虚拟手柄左摇杆（模拟量输出，需平台支持）
+    Gamepad,
+}
+
+/// 输入传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Transport {
+    /// 只用 UDP（`--tcp` 时另开只收关键事件的 TCP 可靠通道）
+    #[default]
+    Udp,
+    /// 只用 TCP，适合屏蔽了 UDP 的公司/访客网络
+    Tcp,
+    /// UDP 和 TCP 同时可用，TCP 通道接受所有消息
+    Both,
+}
+
+impl Transport {
+    pub fn name(self) -> &'static str {
+        match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Both => "both",
+        }
+    }
+}
+
+/// 监听的地址族
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IpMode {
+    /// 只监听 IPv4
+    #[default]
+    V4,
+    /// 只监听 IPv6
+    V6,
+    /// 双栈：监听 `[::]` 并同时接受 IPv4 客户端
+    Dual,
+}
+
+/// 摇杆 8 个方向的名称（命令行参数使用），顺序：上、右上、右、右下、下、左下、左、左上
+pub const DIRECTION_NAMES: [&str; 8] = [
+    "up", "up_right", "right", "down_right", "down", "down_left", "left", "up_left",
+];
+/// 摇杆 8 个方向的显示名称
+pub const DIRECTION_LABELS: [&str; 8] = ["上", "右上", "右", "右下", "下", "左下", "左", "左上"];
+
+/// 技能拖拽平滑的插值曲线
+///
+/// 平滑系数越大越跟手，越小越平滑但延迟越明显；缓出曲线远离目标时按平滑系数快速跟进，
+/// 接近目标时减速，在不牺牲大幅移动响应的前提下让落点更稳
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SmoothCurve {
+    /// 每个周期固定靠近剩余距离的 smooth_factor 比例
+    #[default]
+    Linear,
+    /// 比例随剩余距离缩小，接近目标时逐渐减速
+    EaseOut,
+}
+
+/// 摇杆死区的判定方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeadzoneMode {
+    /// 每个轴单独判断
+    #[default]
+    PerAxis,
+    /// 按偏移量的模长判断，斜向推动更顺滑
+    Radial,
+}
+
+/// 摇杆按键映射与死区
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoystickConfig {
+    /// 8 个方向各自需要同时按住的按键列表（顺序同 DIRECTION_NAMES）
+    pub directions: [Vec<String>; 8],
+    /// 方向键按下阈值（超过才按下）
+    pub enter: f32,
+    /// 方向键松开阈值，即死区（回落到此以下才松开），不大于按下阈值
+    pub exit: f32,
+    pub deadzone_mode: DeadzoneMode,
+    /// 跑步阈值：偏移量模长超过此值时额外按住跑步键（None 表示不启用）
+    pub run_threshold: Option<f32>,
+    pub run_key: String,
+}
+
+impl JoystickConfig {
+    /// 由上、左、下、右四个方向键生成 8 方向映射（斜向同时按两个键）
+    pub fn set_keys(&mut self, up: &str, left: &str, down: &str, right: &str) {
+        self.directions = [
+            vec![up],
+            vec![up, right],
+            vec![right],
+            vec![down, right],
+            vec![down],
+            vec![down, left],
+            vec![left],
+            vec![up, left],
+        ]
+        .map(|keys| keys.into_iter().map(String::from).collect());
+    }
+
+    /// 设置死区，按下阈值随之调整为死区加上滞回宽度
+    pub fn set_deadzone(&mut self, deadzone: f32) {
+        self.exit = deadzone;
+        self.enter = (deadzone + JOYSTICK_HYSTERESIS).min(0.99);
+    }
+}
+
+impl Default for JoystickConfig {
+    fn default() -> Self {
+        let mut config = Self {
+            directions: Default::default(),
+            enter: 0.0,
+            exit: 0.0,
+            deadzone_mode: DeadzoneMode::default(),
+            run_threshold: None,
+            run_key: "shift".to_string(),
+        };
+        config.set_keys("w", "a", "s", "d");
+        config.set_deadzone(crate::DEADZONE);
+        config
+    }
+}
+
+/// 默认同时按住的按键上限
+const DEFAULT_MAX_HELD_KEYS: usize = 16;
+/// 摇杆滞回宽度：按下阈值比松开阈值（死区）高出的量
+pub const JOYSTICK_HYSTERESIS: f32 = 0.05;
+/// 速度模式下摇杆推满时的默认鼠标速度（像素/秒）
+const DEFAULT_JOYSTICK_MAX_RATE: f32 = 1200.0;
+/// 滚轮模式下摇杆推满时的默认滚动速度（格/秒）
+const DEFAULT_JOYSTICK_SCROLL_RATE: f32 = 20.0;
+/// 滚轮按键每次触发的默认滚动格数
+const DEFAULT_SCROLL_STEP: i32 = 2;
+/// 滚轮按键和 scroll 消息单次滚动的上限（格），防止失控滚动
+pub const MAX_SCROLL_AMOUNT: i32 = 50;
+/// 默认技能拖拽最大处理频率（次/秒），与常见显示器刷新率一致
+const DEFAULT_SKILL_DRAG_MAX_RATE: u32 = 120;
+/// 每个客户端每秒最多处理的数据包数，摇杆 + 技能拖拽 + 心跳正常远低于此值
+const DEFAULT_RATE_LIMIT: u32 = 500;
+/// 默认最大数据包大小（字节），足够容纳最长的文本消息（4096 字节）及 JSON 开销
+const DEFAULT_MAX_PACKET_SIZE: usize = 8192;
+/// 最大数据包大小的允许范围：下限保证最短的消息可用，上限为 UDP 数据报的最大载荷
+const MAX_PACKET_SIZE_RANGE: std::ops::RangeInclusive<usize> = 64..=65507;
+/// 技能确认点击的最短按下时间，过短时游戏可能识别不到点击
+const MIN_SKILL_CLICK_HOLD_MS: u64 = 5;
+/// 配置文件名，未指定 `--config` 时依次在当前目录和系统配置目录下的 touch-server/ 中查找
+const CONFIG_FILE_NAME: &str = "config.toml";
+/// 默认 Prometheus 指标端点地址（仅本机）
+const DEFAULT_METRICS_BIND: &str = "127.0.0.1:9528";
+
+/// 服务端运行配置
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// UDP 监听端口（同时用于 mDNS 广播）
+    pub port: u16,
+    pub log_filter: LogFilter,
+    /// TCP 可靠通道端口（None 表示不启用）
+    pub tcp_port: Option<u16>,
+    pub transport: Transport,
+    /// 同时按住的按键上限（不含修饰键）
+    pub max_held_keys: usize,
+    pub held_key_policy: HeldKeyPolicy,
+    /// Prometheus 指标端点监听地址（None 表示不启用）
+    pub metrics_bind: Option<String>,
+    pub joystick: JoystickConfig,
+    /// 技能释放后忽略左键点击的时长（毫秒，0 表示不启用）
+    pub skill_click_suppress_ms: u64,
+    pub joystick_mode: JoystickMode,
+    /// 速度模式下摇杆推满时的鼠标速度（像素/秒）
+    pub joystick_max_rate: f32,
+    /// 速度模式下摇杆回中后，光标在此时长内自动移回摇杆推动前的位置（None 表示不回中）
+    pub joystick_recenter: Option<Duration>,
+    /// 滚轮模式下摇杆推满时的滚动速度（格/秒）
+    pub joystick_scroll_rate: f32,
+    /// 心跳超时：每个客户端单独计算，超时的客户端被移除；
+    /// 最后一个活跃客户端离开时释放所有按键（不区分按键由哪个客户端按下）
+    pub heartbeat_timeout: Duration,
+    /// 空闲断开：超过此时长没有实际输入（心跳不算）则释放按键并结束会话
+    pub idle_timeout: Option<Duration>,
+    /// 技能超时：技能开始后超过此时长没有拖拽/释放/取消则自动取消（客户端崩溃时避免鼠标停在中心），默认不启用
+    pub skill_timeout: Option<Duration>,
+    /// 允许/拒绝连接的客户端地址
+    pub access: AccessList,
+    /// 服务端保活：客户端超过此时长没有输入时，服务端主动发送保活包，保持 NAT/防火墙映射
+    pub keepalive: Option<Duration>,
+    /// 在 JSON Pong 中附带单向延迟估算（需要客户端在能力协商时上报时钟偏移）
+    pub one_way_delay: bool,
+    /// 技能拖拽最大处理频率（次/秒，0 表示不限制）
+    pub skill_drag_max_rate: u32,
+    /// 每个来源 IP（TCP 为每个连接）每秒最多处理的数据包数（0 表示不限制），超出部分优先丢弃非输入消息
+    pub rate_limit: u32,
+    /// 单个 UDP 数据包的最大字节数（含加密、CRC 开销），超过的数据包会被截断，直接丢弃
+    pub max_packet_size: usize,
+    /// 按键重映射（源按键为小写），可在键盘键与鼠标键/滚轮之间互相映射
+    pub key_remap: HashMap<String, String>,
+    /// 按键输出路由（按键为小写）：表中的按键由虚拟手柄的对应按键输出，其余按键默认由键盘鼠标输出
+    pub gamepad_keys: HashMap<String, GamepadButton>,
+    /// 向客户端推送前台窗口标题和进程名（涉及隐私，默认关闭）
+    pub report_foreground: bool,
+    /// 相对鼠标移动的灵敏度倍数
+    pub mouse_sensitivity: f32,
+    /// 配对 PIN（None 表示不需要认证）
+    pub pin: Option<String>,
+    /// 加密载荷（密钥取自 psk，未指定时取自 pin）
+    pub encrypt: bool,
+    /// 加密用的预共享密钥
+    pub psk: Option<String>,
+    /// 按技能键配置的鼠标半径，未配置的技能使用默认半径
+    pub skill_radius: HashMap<String, i32>,
+    /// 默认技能鼠标半径（像素）
+    pub default_skill_radius: i32,
+    /// 技能拖拽平滑系数：每个定时周期向目标靠近的比例
+    pub smooth_factor: f32,
+    pub smooth_curve: SmoothCurve,
+    /// 按技能键配置的确认动作（mouse_left / mouse_right / mouse_middle / none）
+    pub skill_click: HashMap<String, String>,
+    /// 自施法/瞬发技能（小写）：开始时只按键，忽略拖拽和释放
+    pub self_cast: HashSet<String>,
+    /// 技能释放时鼠标移动后的点击延迟（也用于点击后回中前的等待）
+    pub skill_click_delay: Duration,
+    /// 技能确认点击的按下保持时间
+    pub skill_click_hold: Duration,
+    /// 带修饰键的按键：修饰键按下后等待多久再按主键（松开时同理），0 表示不等待
+    pub modifier_delay: Duration,
+    /// 二进制协议收发时附带 CRC8 校验和，丢弃校验失败的数据包
+    pub binary_crc: bool,
+    /// scroll_up/scroll_down 等滚轮按键每次滚动的格数
+    pub scroll_step: i32,
+    /// 是否通过 mDNS 广播服务（关闭后客户端需手动输入 IP）
+    pub mdns: bool,
+    /// UDP/TCP 监听及 mDNS 广播使用的地址族
+    pub ip_mode: IpMode,
+    /// 监听地址（None 表示所有网卡），同时决定 mDNS 广播的地址
+    pub bind: Option<IpAddr>,
+    /// 安静模式：只输出警告和错误
+    pub quiet: bool,
+    /// 只输出所有可用的按键名称后退出
+    pub list_keys: bool,
+    /// 日志同时写入此文件（按大小轮转）
+    pub log_file: Option<PathBuf>,
+    /// 已加载的配置文件（用于热加载）
+    pub config_file: Option<PathBuf>,
+    /// 配置文件中 `[profiles.<name>]` 定义的配置方案，客户端可在运行时切换
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: crate::PORT,
+            log_filter: LogFilter::default(),
+            tcp_port: None,
+            transport: Transport::default(),
+            max_held_keys: DEFAULT_MAX_HELD_KEYS,
+            held_key_policy: HeldKeyPolicy::default(),
+            metrics_bind: None,
+            joystick: JoystickConfig::default(),
+            skill_click_suppress_ms: 0,
+            joystick_mode: JoystickMode::default(),
+            joystick_max_rate: DEFAULT_JOYSTICK_MAX_RATE,
+            joystick_recenter: None,
+            joystick_scroll_rate: DEFAULT_JOYSTICK_SCROLL_RATE,
+            heartbeat_timeout: Duration::from_secs(crate::HEARTBEAT_TIMEOUT_SECS),
+            idle_timeout: None,
+            skill_timeout: None,
+            keepalive: None,
+            access: AccessList::default(),
+            one_way_delay: false,
+            skill_drag_max_rate: DEFAULT_SKILL_DRAG_MAX_RATE,
+            rate_limit: DEFAULT_RATE_LIMIT,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            key_remap: HashMap::new(),
+            gamepad_keys: HashMap::new(),
+            report_foreground: false,
+            mouse_sensitivity: 1.0,
+            pin: None,
+            encrypt: false,
+            psk: None,
+            skill_radius: HashMap::new(),
+            default_skill_radius: crate::SKILL_MOUSE_RADIUS,
+            smooth_factor: crate::SMOOTH_FACTOR,
+            smooth_curve: SmoothCurve::default(),
+            skill_click: HashMap::new(),
+            self_cast: HashSet::new(),
+            skill_click_delay: Duration::from_millis(crate::SKILL_CLICK_DELAY_MS),
+            skill_click_hold: Duration::from_millis(crate::SKILL_CLICK_HOLD_MS),
+            modifier_delay: Duration::from_millis(crate::MODIFIER_DELAY_MS),
+            binary_crc: false,
+            scroll_step: DEFAULT_SCROLL_STEP,
+            mdns: true,
+            ip_mode: IpMode::default(),
+            bind: None,
+            quiet: false,
+            list_keys: false,
+            log_file: None,
+            config_file: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+/// 生成随机 6 位 PIN
+fn generate_pin() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    // 标准库的 RandomState 每次使用随机种子，足够用于局域网配对码
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    );
+    format!("{:06}", hasher.finish() % 1_000_000)
+}
+
+/// 解析逗号分隔的消息类型列表，如 "skill_start,skill_release"
+fn parse_kind_list(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// 解析用 + 连接的按键列表，如 "w+d+shift"
+fn parse_key_list(value: &str) -> Vec<String> {
+    value
+        .split('+')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl Config {
+    /// 两次技能拖拽之间的最小间隔
+    pub fn skill_drag_interval(&self) -> Duration {
+        match self.skill_drag_max_rate {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs(1) / rate,
+        }
+    }
+
+    /// 从配置文件、环境变量和命令行参数构建配置
+    ///
+    /// 优先级：命令行参数 > 环境变量 > 配置文件 > 默认值。配置文件为 TOML，
+    /// 键与命令行参数一一对应（见 [`FileConfig`]），由 `--config <path>` 指定，
+    /// 否则依次查找 `./config.toml` 和系统配置目录下的 `touch-server/config.toml`
+    ///
+    /// 端口优先级：`--port` > 环境变量 `TOUCH_PORT` > 配置文件 > 默认 9527
+    ///
+    /// 设置环境变量 `TOUCH_NO_MDNS`（任意值）等同于 `--no-mdns`
+    ///
+    /// 支持的参数：
+    /// - `--config <path>` 配置文件路径
+    /// - `--port <port>` UDP 监听端口
+    /// - `--no-mdns` 不通过 mDNS 广播服务，客户端手动输入 IP 连接
+    /// - `--ip-mode <ipv4|ipv6|dual>` 监听的地址族（默认 ipv4）
+    /// - `--bind <addr>` 只在指定地址上监听（默认所有网卡），如 `192.168.1.20`
+    /// - `--quiet` 安静模式，只输出警告和错误
+    /// - `--list-keys` 输出所有可用的按键名称后退出
+    /// - `--log-file <path>` 日志同时写入文件（超过 10MB 轮转，保留 3 个旧文件）
+    /// - `--log-include <types>` 只记录指定类型的事件日志（逐条事件日志为 debug 级别，需 `RUST_LOG=touch_server=debug`）
+    /// - `--log-exclude <types>` 不记录指定类型的事件日志
+    /// - `--tcp` 在与 UDP 相同的端口上启用 TCP 可靠通道
+    /// - `--tcp-port <port>` 在指定端口上启用 TCP 可靠通道
+    /// - `--transport <udp|tcp|both>` 传输方式（默认 udp）；tcp/both 时 TCP 通道接受所有消息，
+    ///   未指定 `--tcp-port` 时与 UDP 使用相同端口
+    /// - `--max-held-keys <n>` 同时按住的按键上限
+    /// - `--held-key-policy <refuse|release-oldest>` 达到上限后的处理策略
+    /// - `--metrics` 在默认地址启用 Prometheus 指标端点
+    /// - `--metrics-bind <addr>` 在指定地址启用 Prometheus 指标端点
+    /// - `--deadzone <0..1>` 摇杆死区（同时设置松开阈值，按下阈值为死区 +0.05）
+    /// - `--deadzone-mode <axis|radial>` 死区按单轴还是按模长判断
+    /// - `--run-threshold <0..1>` 摇杆推过此深度时额外按住跑步键
+    /// - `--run-key <key>` 跑步键（默认 shift）
+    /// - `--joystick-keys <上,左,下,右>` 摇杆四个方向的按键，如 `up,left,down,right`
+    /// - `--joystick-enter <0..1>` 摇杆方向键按下阈值
+    /// - `--joystick-exit <0..1>` 摇杆方向键松开阈值
+    /// - `--skill-click-suppress-ms <ms>` 技能释放后忽略左键点击的时长
+    /// - `--joystick-mode <keys|velocity|scroll|gamepad>` 摇杆输出模式
+    /// - `--joystick-max-rate <px/s>` 速度模式下推满时的鼠标速度
+    /// - `--joystick-recenter-ms <ms>` 速度模式下摇杆回中后光标移回原位的时间（0 表示不回中，默认）
+    /// - `--joystick-scroll-rate <格/s>` 滚轮模式下推满时的滚动速度
+    /// - `--heartbeat-timeout <secs>` 多久没收到心跳视为客户端断开（高延迟网络可调大）
+    /// - `--idle-timeout <secs>` 没有实际输入多久后断开会话（0 表示不启用）
+    /// - `--skill-timeout <secs>` 技能无后续消息多久后自动取消（0 表示不启用，默认）；
+    ///   按住技能不动瞄准时客户端不发拖拽，需留足瞄准时间
+    /// - `--allow <ips>` / `--deny <ips>` 允许/拒绝的客户端地址，逗号分隔，支持网段（如 `192.168.1.0/24`）
+    /// - `--keepalive <secs>` 客户端无输入时服务端主动发送保活包的间隔（0 表示不启用，默认）
+    /// - `--joystick-dir <方向>=<键1+键2...>` 设置某个方向的按键，如 `up_right=w+d+shift`
+    /// - `--one-way-delay` 在 Pong 中附带单向延迟估算
+    /// - `--skill-drag-max-rate <次/秒>` 技能拖拽最大处理频率（0 表示不限制）
+    /// - `--rate-limit <包/秒>` 每个来源 IP（TCP 为每个连接）的最大收包频率（0 表示不限制）
+    /// - `--max-packet-size <字节>` 单个 UDP 数据包的最大大小（默认 8192，64~65507），超过则丢弃
+    /// - `--remap <按键>=<按键>` 按键重映射，如 `mouse_back=escape`、`q=mouse_left`
+    /// - `--gamepad-key <按键>=<pad_按键>` 该按键改由虚拟手柄输出，如 `space=pad_a`（需 `--joystick-mode gamepad`）
+    /// - `--report-foreground` 前台窗口变化时通知客户端
+    /// - `--mouse-sensitivity <倍数>` 相对鼠标移动的灵敏度
+    /// - `--skill-click <技能键>=<left|right|middle|none>` 单个技能释放时的点击按钮，none 表示不点击
+    /// - `--self-cast <技能键,...>` 自施法/瞬发技能，按下即生效，不移动鼠标、不点击确认
+    /// - `--skill-click-delay-ms <ms>` 技能释放时移动后等待多久再点击
+    /// - `--skill-click-hold-ms <ms>` 技能确认点击的按下保持时间（至少 5ms）
+    /// - `--modifier-delay-ms <ms>` 修饰键与主键之间的等待时间（默认 10，0 表示不等待）
+    /// - `--scroll-step <格>` 滚轮按键每次滚动的格数
+    /// - `--binary-crc` 二进制消息末尾附带 CRC8 校验和（客户端需同时支持）
+    /// - `--auth` 启动时生成随机 6 位 PIN，客户端需认证后才能输入
+    /// - `--pin <pin>` 使用指定的 PIN 进行认证
+    /// - `--encrypt` 用 ChaCha20-Poly1305 加密所有收发的数据（密钥取自 `--psk`，未指定时取自 PIN）
+    /// - `--psk <secret>` 加密用的预共享密钥（隐含 `--encrypt`）
+    /// - `--skill-radius <技能键>=<px>` 单个技能的鼠标半径，可重复，如 `--skill-radius q=1200 --skill-radius e=500`
+    /// - `--skill-radius-default <px>` 未单独配置的技能使用的鼠标半径
+    /// - `--smooth-factor <0..1>` 技能拖拽平滑系数，越大跟手越快
+    /// - `--smooth-curve <linear|ease-out>` 技能拖拽平滑曲线，ease-out 接近目标时减速
+    pub fn from_args() -> Self {
+        let cli: Vec<String> = std::env::args().skip(1).collect();
+        let explicit = cli.iter().position(|a| a == "--config").and_then(|i| cli.get(i + 1));
+        let config_file = find_config_file(explicit.map(String::as_str));
+        let file = match &config_file {
+            Some(path) => match load_config_file(path) {
+                Ok(args) => {
+                    info!("[配置] 已加载配置文件: {}", path.display());
+                    args
+                }
+                Err(e) => {
+                    warn!("[配置] 读取配置文件 {} 失败，使用默认配置: {}", path.display(), e);
+                    FileConfig::default()
+                }
+            },
+            None => FileConfig::default(),
+        };
+        let mut config = Self::build(file, cli);
+        config.config_file = config_file;
+        config
+    }
+
+    /// 重新读取配置文件（命令行参数和环境变量不变，仍优先于配置文件）；文件无法解析时返回错误
+    pub fn reload(&self) -> Result<Config, String> {
+        let path = self.config_file.as_deref().ok_or("未使用配置文件")?;
+        let mut config = Self::build(load_config_file(path)?, std::env::args().skip(1).collect());
+        config.config_file = self.config_file.clone();
+        Ok(config)
+    }
+
+    /// 构建基础配置及各个配置方案（方案 = 基础配置 + 方案中的设置，方案设置优先于命令行参数）
+    fn build(file: FileConfig, cli: Vec<String>) -> Self {
+        let mut config = Self::build_one(&file, &cli, None);
+        for (name, profile) in file.profiles.iter().flatten() {
+            config.profiles.insert(name.clone(), Self::build_one(&file, &cli, Some(profile)));
+        }
+        config
+    }
+
+    /// 依次应用配置文件、环境变量、命令行参数和配置方案
+    fn build_one(file: &FileConfig, cli: &[String], profile: Option<&FileConfig>) -> Self {
+        let mut config = Config::default();
+        let mut tcp_same_port = file.apply(&mut config);
+
+        if let Ok(value) = std::env::var("TOUCH_PORT") {
+            match value.trim().parse() {
+                Ok(port) => config.port = port,
+                Err(_) => warn!("[配置] 环境变量 TOUCH_PORT 不是有效的端口号: {}", value),
+            }
+        }
+        if std::env::var_os("TOUCH_NO_MDNS").is_some() {
+            config.mdns = false;
+        }
+
+        tcp_same_port |= config.apply_args(cli.to_vec());
+        if let Some(profile) = profile {
+            tcp_same_port |= profile.apply(&mut config);
+        }
+
+        // --tcp 使用最终确定的 UDP 端口（--tcp-port 优先）
+        let tcp_same_port = tcp_same_port || config.transport != Transport::Udp;
+        if tcp_same_port && config.tcp_port.is_none() {
+            config.tcp_port = Some(config.port);
+        }
+
+        if config.joystick.exit > config.joystick.enter {
+            warn!("[配置] 摇杆松开阈值不能大于按下阈值，已调整为 {:.2}", config.joystick.enter);
+            config.joystick.exit = config.joystick.enter;
+        }
+
+        config
+    }
+
+    /// 依次应用参数，返回是否出现了 `--tcp`（需在端口最终确定后处理）
+    fn apply_args(&mut self, args: Vec<String>) -> bool {
+        let mut args = args.into_iter();
+        let mut tcp_same_port = false;
+        while let Some(arg) = args.next() {
+            let source = format!("参数 {}", arg);
+            match arg.as_str() {
+                "--log-include" | "--log-exclude" => {
+                    let Some(value) = args.next() else {
+                        warn!("[配置] 参数 {} 缺少取值", arg);
+                        continue;
+                    };
+                    let kinds = parse_kind_list(&value);
+                    if arg == "--log-include" {
+                        self.log_filter.include.extend(kinds);
+                    } else {
+                        self.log_filter.exclude.extend(kinds);
+                    }
+                }
+                "--port" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(port) => self.port = port,
+                    None => warn!("[配置] 参数 --port 需要有效的端口号"),
+                },
+                "--tcp" => tcp_same_port = true,
+                "--transport" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_transport(&value));
+                    }
+                }
+                "--tcp-port" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(port) => self.tcp_port = Some(port),
+                    None => warn!("[配置] 参数 --tcp-port 需要有效的端口号"),
+                },
+                "--max-held-keys" => {
+                    if let Some(n) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_max_held_keys(n));
+                    }
+                }
+                "--held-key-policy" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_held_key_policy(&value));
+                    }
+                }
+                "--metrics" => {
+                    self.metrics_bind.get_or_insert_with(|| DEFAULT_METRICS_BIND.to_string());
+                }
+                "--metrics-bind" => match args.next() {
+                    Some(addr) => self.metrics_bind = Some(addr),
+                    None => warn!("[配置] 参数 --metrics-bind 缺少取值"),
+                },
+                "--joystick-enter" => {
+                    if let Some(v) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_joystick_enter(v));
+                    }
+                }
+                "--joystick-exit" => {
+                    if let Some(v) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_joystick_exit(v));
+                    }
+                }
+                "--deadzone" => {
+                    if let Some(v) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_deadzone(v));
+                    }
+                }
+                "--deadzone-mode" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_deadzone_mode(&value));
+                    }
+                }
+                "--run-threshold" => {
+                    if let Some(v) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_run_threshold(v));
+                    }
+                }
+                "--run-key" => {
+                    if let Some(key) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_run_key(&key));
+                    }
+                }
+                "--joystick-keys" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        let keys: Vec<String> = value.split(',').map(String::from).collect();
+                        warn_invalid(&source, self.set_joystick_keys(&keys));
+                    }
+                }
+                "--skill-click-suppress-ms" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(ms) => self.skill_click_suppress_ms = ms,
+                    None => warn!("[配置] 参数 --skill-click-suppress-ms 需要毫秒数"),
+                },
+                "--joystick-mode" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_joystick_mode(&value));
+                    }
+                }
+                "--joystick-max-rate" => {
+                    if let Some(rate) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_joystick_max_rate(rate));
+                    }
+                }
+                "--joystick-recenter-ms" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(ms) => self.joystick_recenter = nonzero_millis(ms),
+                    None => warn!("[配置] 参数 --joystick-recenter-ms 需要毫秒数"),
+                },
+                "--joystick-scroll-rate" => {
+                    if let Some(rate) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_joystick_scroll_rate(rate));
+                    }
+                }
+                "--heartbeat-timeout" => {
+                    if let Some(secs) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_heartbeat_timeout(secs));
+                    }
+                }
+                "--idle-timeout" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(secs) => self.idle_timeout = nonzero_secs(secs),
+                    None => warn!("[配置] 参数 --idle-timeout 需要秒数"),
+                },
+                "--skill-timeout" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(secs) => self.skill_timeout = nonzero_secs(secs),
+                    None => warn!("[配置] 参数 --skill-timeout 需要秒数"),
+                },
+                "--allow" | "--deny" => {
+                    let Some(value) = next_arg(&source, &mut args) else {
+                        continue;
+                    };
+                    for item in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        warn_invalid(&source, self.add_access_rule(arg == "--allow", item));
+                    }
+                }
+                "--keepalive" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(secs) => self.keepalive = nonzero_secs(secs),
+                    None => warn!("[配置] 参数 --keepalive 需要秒数"),
+                },
+                "--joystick-dir" => {
+                    let Some(value) = next_arg(&source, &mut args) else {
+                        continue;
+                    };
+                    let result = match value.split_once('=') {
+                        Some((name, keys)) => self.set_joystick_dir(name, &parse_key_list(keys)),
+                        None => Err(joystick_dir_hint()),
+                    };
+                    warn_invalid(&source, result);
+                }
+                "--one-way-delay" => self.one_way_delay = true,
+                "--skill-drag-max-rate" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(rate) => self.skill_drag_max_rate = rate,
+                    None => warn!("[配置] 参数 --skill-drag-max-rate 需要整数"),
+                },
+                "--max-packet-size" => {
+                    if let Some(size) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_max_packet_size(size));
+                    }
+                }
+                "--rate-limit" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(rate) => self.rate_limit = rate,
+                    None => warn!("[配置] 参数 --rate-limit 需要整数"),
+                },
+                "--remap" => {
+                    let Some(value) = next_arg(&source, &mut args) else {
+                        continue;
+                    };
+                    let result = match value.split_once('=') {
+                        Some((from, to)) => self.add_remap(from, to),
+                        None => Err(format!("格式应为 <按键>=<按键>: {}", value)),
+                    };
+                    warn_invalid(&source, result);
+                }
+                "--gamepad-key" => {
+                    let Some(value) = next_arg(&source, &mut args) else {
+                        continue;
+                    };
+                    let result = match value.split_once('=') {
+                        Some((key, pad)) => self.add_gamepad_key(key, pad),
+                        None => Err(format!("格式应为 <按键>=<pad_按键>: {}", value)),
+                    };
+                    warn_invalid(&source, result);
+                }
+                "--report-foreground" => self.report_foreground = true,
+                "--no-mdns" => self.mdns = false,
+                "--quiet" => self.quiet = true,
+                "--list-keys" => self.list_keys = true,
+                "--log-file" => {
+                    if let Some(path) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_log_file(&path));
+                    }
+                }
+                "--ip-mode" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_ip_mode(&value));
+                    }
+                }
+                "--bind" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_bind(&value));
+                    }
+                }
+                "--skill-radius" => {
+                    let parsed = args.next().and_then(|v| {
+                        let (key, radius) = v.split_once('=')?;
+                        Some((key.to_string(), radius.trim().parse::<i32>().ok()?))
+                    });
+                    match parsed {
+                        Some((key, radius)) => warn_invalid(&source, self.add_skill_radius(&key, radius)),
+                        None => warn!("[配置] 参数 --skill-radius 格式应为 <技能键>=<正整数像素>"),
+                    }
+                }
+                "--skill-radius-default" => {
+                    if let Some(radius) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_default_skill_radius(radius));
+                    }
+                }
+                "--smooth-factor" => {
+                    if let Some(factor) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_smooth_factor(factor));
+                    }
+                }
+                "--smooth-curve" => {
+                    if let Some(value) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_smooth_curve(&value));
+                    }
+                }
+                // 配置文件路径已在读取参数前处理
+                "--config" => {
+                    args.next();
+                }
+                "--skill-click" => {
+                    let Some(value) = next_arg(&source, &mut args) else {
+                        continue;
+                    };
+                    let result = match value.split_once('=') {
+                        Some((key, button)) => self.add_skill_click(key, button),
+                        None => Err(SKILL_CLICK_HINT.to_string()),
+                    };
+                    warn_invalid(&source, result);
+                }
+                "--self-cast" => match args.next() {
+                    Some(keys) => self.add_self_cast(keys.split(',')),
+                    None => warn!("[配置] 参数 --self-cast 缺少技能键"),
+                },
+                "--skill-click-delay-ms" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(ms) => self.skill_click_delay = Duration::from_millis(ms),
+                    None => warn!("[配置] 参数 --skill-click-delay-ms 需要毫秒数"),
+                },
+                "--skill-click-hold-ms" => {
+                    if let Some(ms) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_skill_click_hold_ms(ms));
+                    }
+                }
+                "--modifier-delay-ms" => match args.next().and_then(|v| v.parse().ok()) {
+                    Some(ms) => self.modifier_delay = Duration::from_millis(ms),
+                    None => warn!("[配置] 参数 --modifier-delay-ms 需要毫秒数"),
+                },
+                "--scroll-step" => {
+                    if let Some(step) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_scroll_step(step));
+                    }
+                }
+                "--binary-crc" => self.binary_crc = true,
+                "--auth" => {
+                    self.pin.get_or_insert_with(generate_pin);
+                }
+                "--pin" => {
+                    if let Some(pin) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_pin(&pin));
+                    }
+                }
+                "--encrypt" => self.encrypt = true,
+                "--psk" => {
+                    if let Some(psk) = next_arg(&source, &mut args) {
+                        warn_invalid(&source, self.set_psk(&psk));
+                    }
+                }
+                "--mouse-sensitivity" => {
+                    if let Some(v) = next_value(&source, &mut args) {
+                        warn_invalid(&source, self.set_mouse_sensitivity(v));
+                    }
+                }
+                _ => warn!("[配置] 忽略未知参数: {}", arg),
+            }
+        }
+        tcp_same_port
+    }
+
+    // 以下设置同时用于命令行参数和配置文件：取值不合法时返回说明，配置保持不变
+
+    fn set_transport(&mut self, value: &str) -> Result<(), String> {
+        self.transport = match value {
+            "udp" => Transport::Udp,
+            "tcp" => Transport::Tcp,
+            "both" => Transport::Both,
+            _ => return Err("取值应为 udp、tcp 或 both".to_string()),
+        };
+        Ok(())
+    }
+
+    fn set_max_held_keys(&mut self, n: usize) -> Result<(), String> {
+        if n == 0 {
+            return Err("需要正整数".to_string());
+        }
+        self.max_held_keys = n;
+        Ok(())
+    }
+
+    fn set_held_key_policy(&mut self, value: &str) -> Result<(), String> {
+        self.held_key_policy = match value {
+            "refuse" => HeldKeyPolicy::Refuse,
+            "release-oldest" => HeldKeyPolicy::ReleaseOldest,
+            _ => return Err("取值应为 refuse 或 release-oldest".to_string()),
+        };
+        Ok(())
+    }
+
+    fn set_joystick_enter(&mut self, v: f32) -> Result<(), String> {
+        check_unit(v)?;
+        self.joystick.enter = v;
+        Ok(())
+    }
+
+    fn set_joystick_exit(&mut self, v: f32) -> Result<(), String> {
+        check_unit(v)?;
+        self.joystick.exit = v;
+        Ok(())
+    }
+
+    fn set_deadzone(&mut self, v: f32) -> Result<(), String> {
+        check_unit(v)?;
+        self.joystick.set_deadzone(v);
+        Ok(())
+    }
+
+    fn set_deadzone_mode(&mut self, value: &str) -> Result<(), String> {
+        self.joystick.deadzone_mode = match value {
+            "axis" => DeadzoneMode::PerAxis,
+            "radial" => DeadzoneMode::Radial,
+            _ => return Err("取值应为 axis 或 radial".to_string()),
+        };
+        Ok(())
+    }
+
+    fn set_run_threshold(&mut self, v: f32) -> Result<(), String> {
+        if !(v > 0.0 && v <= 1.0) {
+            return Err("需要 0~1 之间的数值".to_string());
+        }
+        self.joystick.run_threshold = Some(v);
+        Ok(())
+    }
+
+    fn set_run_key(&mut self, key: &str) -> Result<(), String> {
+        if crate::parse_key(key).is_none() {
+            return Err("需要有效的按键".to_string());
+        }
+        self.joystick.run_key = key.to_lowercase();
+        Ok(())
+    }
+
+    /// 按上、左、下、右的顺序设置摇杆四个方向的按键
+    fn set_joystick_keys(&mut self, keys: &[String]) -> Result<(), String> {
+        let keys: Vec<String> = keys.iter().map(|k| k.trim().to_lowercase()).collect();
+        match keys.as_slice() {
+            [up, left, down, right] if keys.iter().all(|k| crate::parse_key(k).is_some()) => {
+                self.joystick.set_keys(up, left, down, right);
+                Ok(())
+            }
+            _ => Err("需要 4 个有效按键，顺序为 上,左,下,右".to_string()),
+        }
+    }
+
+    fn set_joystick_dir(&mut self, name: &str, keys: &[String]) -> Result<(), String> {
+        let keys: Vec<String> = keys.iter().map(|k| k.trim().to_lowercase()).filter(|k| !k.is_empty()).collect();
+        match DIRECTION_NAMES.iter().position(|n| *n == name.trim()) {
+            Some(index) if keys.iter().all(|k| crate::parse_key(k).is_some()) => {
+                self.joystick.directions[index] = keys;
+                Ok(())
+            }
+            _ => Err(joystick_dir_hint()),
+        }
+    }
+
+    fn set_joystick_mode(&mut self, value: &str) -> Result<(), String> {
+        self.joystick_mode = match value {
+            "keys" => JoystickMode::Keys,
+            "velocity" => JoystickMode::Velocity,
+            "scroll" => JoystickMode::Scroll,
+            "gamepad" => JoystickMode::Gamepad,
+            _ => return Err("取值应为 keys、velocity、scroll 或 gamepad".to_string()),
+        };
+        Ok(())
+    }
+
+    fn set_joystick_max_rate(&mut self, rate: f32) -> Result<(), String> {
+        check_positive(rate)?;
+        self.joystick_max_rate = rate;
+        Ok(())
+    }
+
+    fn set_joystick_scroll_rate(&mut self, rate: f32) -> Result<(), String> {
+        check_positive(rate)?;
+        self.joystick_scroll_rate = rate;
+        Ok(())
+    }
+
+    fn set_heartbeat_timeout(&mut self, secs: u64) -> Result<(), String> {
+        if secs == 0 {
+            return Err("需要正整数秒数".to_string());
+        }
+        self.heartbeat_timeout = Duration::from_secs(secs);
+        Ok(())
+    }
+
+    fn add_access_rule(&mut self, allow: bool, item: &str) -> Result<(), String> {
+        let rule = IpRule::parse(item.trim()).ok_or_else(|| format!("中的地址 {} 无效", item))?;
+        if allow {
+            self.access.allow.push(rule);
+        } else {
+            self.access.deny.push(rule);
+        }
+        Ok(())
+    }
+
+    fn set_max_packet_size(&mut self, size: usize) -> Result<(), String> {
+        if !MAX_PACKET_SIZE_RANGE.contains(&size) {
+            return Err(format!(
+                "需要 {}~{} 之间的字节数",
+                MAX_PACKET_SIZE_RANGE.start(),
+                MAX_PACKET_SIZE_RANGE.end()
+            ));
+        }
+        self.max_packet_size = size;
+        Ok(())
+    }
+
+    fn add_remap(&mut self, from: &str, to: &str) -> Result<(), String> {
+        if crate::parse_key(to.trim()).is_none() {
+            return Err(format!("的目标 {} 不是有效按键", to.trim()));
+        }
+        self.key_remap.insert(from.trim().to_lowercase(), to.trim().to_string());
+        Ok(())
+    }
+
+    fn add_gamepad_key(&mut self, key: &str, pad: &str) -> Result<(), String> {
+        let button = GamepadButton::parse(pad.trim()).ok_or_else(|| format!("中的 {} 不是有效的 pad_按键", pad.trim()))?;
+        self.gamepad_keys.insert(key.trim().to_lowercase(), button);
+        Ok(())
+    }
+
+    fn set_log_file(&mut self, path: &str) -> Result<(), String> {
+        if path.trim().is_empty() {
+            return Err("缺少路径".to_string());
+        }
+        self.log_file = Some(PathBuf::from(path.trim()));
+        Ok(())
+    }
+
+    fn set_ip_mode(&mut self, value: &str) -> Result<(), String> {
+        self.ip_mode = match value {
+            "ipv4" => IpMode::V4,
+            "ipv6" => IpMode::V6,
+            "dual" => IpMode::Dual,
+            _ => return Err("取值应为 ipv4、ipv6 或 dual".to_string()),
+        };
+        Ok(())
+    }
+
+    fn set_bind(&mut self, value: &str) -> Result<(), String> {
+        let ip = value.trim().parse::<IpAddr>().map_err(|_| "需要有效的 IP 地址".to_string())?;
+        self.bind = Some(ip);
+        Ok(())
+    }
+
+    fn add_skill_radius(&mut self, key: &str, radius: i32) -> Result<(), String> {
+        if radius <= 0 {
+            return Err("格式应为 <技能键>=<正整数像素>".to_string());
+        }
+        self.skill_radius.insert(key.trim().to_lowercase(), radius);
+        Ok(())
+    }
+
+    fn set_default_skill_radius(&mut self, radius: i32) -> Result<(), String> {
+        if radius <= 0 {
+            return Err("需要正整数像素".to_string());
+        }
+        self.default_skill_radius = radius;
+        Ok(())
+    }
+
+    fn set_smooth_factor(&mut self, factor: f32) -> Result<(), String> {
+        if !(factor > 0.0 && factor <= 1.0) {
+            return Err("需要 0~1 之间的数（不含 0）".to_string());
+        }
+        self.smooth_factor = factor;
+        Ok(())
+    }
+
+    fn set_smooth_curve(&mut self, value: &str) -> Result<(), String> {
+        self.smooth_curve = match value {
+            "linear" => SmoothCurve::Linear,
+            "ease-out" => SmoothCurve::EaseOut,
+            _ => return Err("取值应为 linear 或 ease-out".to_string()),
+        };
+        Ok(())
+    }
+
+    fn add_skill_click(&mut self, key: &str, button: &str) -> Result<(), String> {
+        let confirm = match button.trim() {
+            "left" => "mouse_left",
+            "right" => "mouse_right",
+            "middle" => "mouse_middle",
+            "none" => "none",
+            _ => return Err(SKILL_CLICK_HINT.to_string()),
+        };
+        self.skill_click.insert(key.trim().to_lowercase(), confirm.to_string());
+        Ok(())
+    }
+
+    fn add_self_cast<'a>(&mut self, keys: impl IntoIterator<Item = &'a str>) {
+        self.self_cast
+            .extend(keys.into_iter().map(|k| k.trim().to_lowercase()).filter(|k| !k.is_empty()));
+    }
+
+    fn set_skill_click_hold_ms(&mut self, ms: u64) -> Result<(), String> {
+        if ms < MIN_SKILL_CLICK_HOLD_MS {
+            return Err(format!("需要不小于 {} 的毫秒数", MIN_SKILL_CLICK_HOLD_MS));
+        }
+        self.skill_click_hold = Duration::from_millis(ms);
+        Ok(())
+    }
+
+    fn set_scroll_step(&mut self, step: i32) -> Result<(), String> {
+        if !(1..=MAX_SCROLL_AMOUNT).contains(&step) {
+            return Err(format!("需要 1~{} 的整数", MAX_SCROLL_AMOUNT));
+        }
+        self.scroll_step = step;
+        Ok(())
+    }
+
+    fn set_pin(&mut self, pin: &str) -> Result<(), String> {
+        if pin.trim().is_empty() {
+            return Err("缺少取值".to_string());
+        }
+        self.pin = Some(pin.trim().to_string());
+        Ok(())
+    }
+
+    /// 设置预共享密钥（隐含开启加密）
+    fn set_psk(&mut self, psk: &str) -> Result<(), String> {
+        if psk.is_empty() {
+            return Err("缺少取值".to_string());
+        }
+        self.psk = Some(psk.to_string());
+        self.encrypt = true;
+        Ok(())
+    }
+
+    fn set_mouse_sensitivity(&mut self, v: f32) -> Result<(), String> {
+        check_positive(v)?;
+        self.mouse_sensitivity = v;
+        Ok(())
+    }
+}
+
+const SKILL_CLICK_HINT: &str = "格式应为 <技能键>=<left|right|middle|none>";
+
+fn joystick_dir_hint() -> String {
+    format!("格式应为 <方向>=<键1+键2>（均为有效按键），方向可选: {}", DIRECTION_NAMES.join(", "))
+}
+
+/// 摇杆阈值、死区等比例值需在 [0, 1) 内
+fn check_unit(v: f32) -> Result<(), String> {
+    if (0.0..1.0).contains(&v) {
+        Ok(())
+    } else {
+        Err("需要 0~1 之间的数值".to_string())
+    }
+}
+
+fn check_positive(v: f32) -> Result<(), String> {
+    if v > 0.0 {
+        Ok(())
+    } else {
+        Err("需要正数".to_string())
+    }
+}
+
+/// 0 表示不启用
+fn nonzero_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// 0 表示不启用
+fn nonzero_millis(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// 取下一个参数作为取值，缺少时输出警告
+fn next_arg(source: &str, args: &mut impl Iterator<Item = String>) -> Option<String> {
+    let value = args.next();
+    if value.is_none() {
+        warn!("[配置] {} 缺少取值", source);
+    }
+    value
+}
+
+/// 取下一个参数并解析为数值，缺少或格式无效时输出警告
+fn next_value<T: FromStr>(source: &str, args: &mut impl Iterator<Item = String>) -> Option<T> {
+    let value = next_arg(source, args)?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        warn!("[配置] {} 的取值 {} 格式无效", source, value);
+    }
+    parsed
+}
+
+/// 设置失败时输出警告，source 为命令行参数或配置文件中的键
+fn warn_invalid(source: &str, result: Result<(), String>) {
+    if let Err(e) = result {
+        warn!("[配置] {} {}", source, e);
+    }
+}
+
+/// 查找配置文件：`--config` 指定的路径优先，其次当前目录，最后系统配置目录
+fn find_config_file(explicit: Option<&str>) -> Option<PathBuf> {
+    if let Some(path) = explicit {
+        return Some(PathBuf::from(path));
+    }
+    let local = PathBuf::from(CONFIG_FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let user = dirs::config_dir()?.join("touch-server").join(CONFIG_FILE_NAME);
+    user.is_file().then_some(user)
+}
+
+/// 配置文件内容，键与命令行参数一一对应（`--skill-click-hold-ms` 对应 `skill_click_hold_ms`），例如：
+///
+/// ```toml
+/// port = 9527
+/// deadzone = 0.3
+/// binary_crc = true                     # false 表示关闭（覆盖前面的设置）
+/// joystick_keys = ["w", "a", "s", "d"]
+///
+/// [skill_radius]                        # 等同于 --skill-radius q=1200
+/// q = 1200
+///
+/// [joystick_dir]                        # 等同于 --joystick-dir up_right=w+d
+/// up_right = ["w", "d"]
+/// ```
+///
+/// 出现未知键或类型不符时整个文件读取失败；取值范围的检查与命令行参数相同。
+///
+/// 配置方案写在 `[profiles.<name>]` 下，键与顶层相同，只需写出与基础配置不同的部分：
+///
+/// ```toml
+/// [profiles.moba.skill_radius]
+/// q = 1200
+///
+/// [profiles.fps]
+/// joystick_keys = ["up", "left", "down", "right"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    port: Option<u16>,
+    log_include: Option<Vec<String>>,
+    log_exclude: Option<Vec<String>>,
+    log_file: Option<String>,
+    quiet: Option<bool>,
+    no_mdns: Option<bool>,
+    ip_mode: Option<String>,
+    bind: Option<String>,
+    tcp: Option<bool>,
+    tcp_port: Option<u16>,
+    transport: Option<String>,
+    max_held_keys: Option<usize>,
+    held_key_policy: Option<String>,
+    metrics: Option<bool>,
+    metrics_bind: Option<String>,
+    deadzone: Option<f32>,
+    deadzone_mode: Option<String>,
+    run_threshold: Option<f32>,
+    run_key: Option<String>,
+    joystick_keys: Option<Vec<String>>,
+    joystick_enter: Option<f32>,
+    joystick_exit: Option<f32>,
+    joystick_dir: Option<BTreeMap<String, Vec<String>>>,
+    joystick_mode: Option<String>,
+    joystick_max_rate: Option<f32>,
+    joystick_recenter_ms: Option<u64>,
+    joystick_scroll_rate: Option<f32>,
+    heartbeat_timeout: Option<u64>,
+    idle_timeout: Option<u64>,
+    skill_timeout: Option<u64>,
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+    keepalive: Option<u64>,
+    one_way_delay: Option<bool>,
+    skill_drag_max_rate: Option<u32>,
+    rate_limit: Option<u32>,
+    max_packet_size: Option<usize>,
+    remap: Option<BTreeMap<String, String>>,
+    gamepad_key: Option<BTreeMap<String, String>>,
+    report_foreground: Option<bool>,
+    mouse_sensitivity: Option<f32>,
+    skill_click: Option<BTreeMap<String, String>>,
+    self_cast: Option<Vec<String>>,
+    skill_click_suppress_ms: Option<u64>,
+    skill_click_delay_ms: Option<u64>,
+    skill_click_hold_ms: Option<u64>,
+    modifier_delay_ms: Option<u64>,
+    scroll_step: Option<i32>,
+    binary_crc: Option<bool>,
+    auth: Option<bool>,
+    pin: Option<String>,
+    encrypt: Option<bool>,
+    psk: Option<String>,
+    skill_radius: Option<BTreeMap<String, i32>>,
+    skill_radius_default: Option<i32>,
+    smooth_factor: Option<f32>,
+    smooth_curve: Option<String>,
+    /// 配置方案，只允许出现在顶层
+    profiles: Option<BTreeMap<String, FileConfig>>,
+}
+
+impl FileConfig {
+    /// 把文件中出现的项应用到配置上，返回是否出现了 `tcp = true`（需在端口最终确定后处理）
+    ///
+    /// 取值检查与命令行参数相同；开关项为 false 时关闭，覆盖之前的配置
+    fn apply(&self, config: &mut Config) -> bool {
+        let source = |key: &str| format!("配置项 {}", key);
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(kinds) = &self.log_include {
+            config.log_filter.include.extend(kinds.iter().flat_map(|k| parse_kind_list(k)));
+        }
+        if let Some(kinds) = &self.log_exclude {
+            config.log_filter.exclude.extend(kinds.iter().flat_map(|k| parse_kind_list(k)));
+        }
+        if let Some(path) = &self.log_file {
+            warn_invalid(&source("log_file"), config.set_log_file(path));
+        }
+        if let Some(quiet) = self.quiet {
+            config.quiet = quiet;
+        }
+        if let Some(no_mdns) = self.no_mdns {
+            config.mdns = !no_mdns;
+        }
+        if let Some(mode) = &self.ip_mode {
+            warn_invalid(&source("ip_mode"), config.set_ip_mode(mode));
+        }
+        if let Some(bind) = &self.bind {
+            warn_invalid(&source("bind"), config.set_bind(bind));
+        }
+        if let Some(port) = self.tcp_port {
+            config.tcp_port = Some(port);
+        }
+        if let Some(transport) = &self.transport {
+            warn_invalid(&source("transport"), config.set_transport(transport));
+        }
+        if let Some(n) = self.max_held_keys {
+            warn_invalid(&source("max_held_keys"), config.set_max_held_keys(n));
+        }
+        if let Some(policy) = &self.held_key_policy {
+            warn_invalid(&source("held_key_policy"), config.set_held_key_policy(policy));
+        }
+        match self.metrics {
+            Some(true) => {
+                config.metrics_bind.get_or_insert_with(|| DEFAULT_METRICS_BIND.to_string());
+            }
+            Some(false) => config.metrics_bind = None,
+            None => {}
+        }
+        if let Some(addr) = &self.metrics_bind {
+            config.metrics_bind = Some(addr.clone());
+        }
+        if let Some(v) = self.deadzone {
+            warn_invalid(&source("deadzone"), config.set_deadzone(v));
+        }
+        if let Some(mode) = &self.deadzone_mode {
+            warn_invalid(&source("deadzone_mode"), config.set_deadzone_mode(mode));
+        }
+        if let Some(v) = self.run_threshold {
+            warn_invalid(&source("run_threshold"), config.set_run_threshold(v));
+        }
+        if let Some(key) = &self.run_key {
+            warn_invalid(&source("run_key"), config.set_run_key(key));
+        }
+        if let Some(keys) = &self.joystick_keys {
+            warn_invalid(&source("joystick_keys"), config.set_joystick_keys(keys));
+        }
+        if let Some(v) = self.joystick_enter {
+            warn_invalid(&source("joystick_enter"), config.set_joystick_enter(v));
+        }
+        if let Some(v) = self.joystick_exit {
+            warn_invalid(&source("joystick_exit"), config.set_joystick_exit(v));
+        }
+        for (direction, keys) in self.joystick_dir.iter().flatten() {
+            warn_invalid(&source("joystick_dir"), config.set_joystick_dir(direction, keys));
+        }
+        if let Some(mode) = &self.joystick_mode {
+            warn_invalid(&source("joystick_mode"), config.set_joystick_mode(mode));
+        }
+        if let Some(rate) = self.joystick_max_rate {
+            warn_invalid(&source("joystick_max_rate"), config.set_joystick_max_rate(rate));
+        }
+        if let Some(ms) = self.joystick_recenter_ms {
+            config.joystick_recenter = nonzero_millis(ms);
+        }
+        if let Some(rate) = self.joystick_scroll_rate {
+            warn_invalid(&source("joystick_scroll_rate"), config.set_joystick_scroll_rate(rate));
+        }
+        if let Some(secs) = self.heartbeat_timeout {
+            warn_invalid(&source("heartbeat_timeout"), config.set_heartbeat_timeout(secs));
+        }
+        if let Some(secs) = self.idle_timeout {
+            config.idle_timeout = nonzero_secs(secs);
+        }
+        if let Some(secs) = self.skill_timeout {
+            config.skill_timeout = nonzero_secs(secs);
+        }
+        for item in self.allow.iter().flatten() {
+            warn_invalid(&source("allow"), config.add_access_rule(true, item));
+        }
+        for item in self.deny.iter().flatten() {
+            warn_invalid(&source("deny"), config.add_access_rule(false, item));
+        }
+        if let Some(secs) = self.keepalive {
+            config.keepalive = nonzero_secs(secs);
+        }
+        if let Some(enabled) = self.one_way_delay {
+            config.one_way_delay = enabled;
+        }
+        if let Some(rate) = self.skill_drag_max_rate {
+            config.skill_drag_max_rate = rate;
+        }
+        if let Some(rate) = self.rate_limit {
+            config.rate_limit = rate;
+        }
+        if let Some(size) = self.max_packet_size {
+            warn_invalid(&source("max_packet_size"), config.set_max_packet_size(size));
+        }
+        for (from, to) in self.remap.iter().flatten() {
+            warn_invalid(&source("remap"), config.add_remap(from, to));
+        }
+        for (key, pad) in self.gamepad_key.iter().flatten() {
+            warn_invalid(&source("gamepad_key"), config.add_gamepad_key(key, pad));
+        }
+        if let Some(enabled) = self.report_foreground {
+            config.report_foreground = enabled;
+        }
+        if let Some(v) = self.mouse_sensitivity {
+            warn_invalid(&source("mouse_sensitivity"), config.set_mouse_sensitivity(v));
+        }
+        for (key, button) in self.skill_click.iter().flatten() {
+            warn_invalid(&source("skill_click"), config.add_skill_click(key, button));
+        }
+        if let Some(keys) = &self.self_cast {
+            config.add_self_cast(keys.iter().map(String::as_str));
+        }
+        if let Some(ms) = self.skill_click_suppress_ms {
+            config.skill_click_suppress_ms = ms;
+        }
+        if let Some(ms) = self.skill_click_delay_ms {
+            config.skill_click_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.skill_click_hold_ms {
+            warn_invalid(&source("skill_click_hold_ms"), config.set_skill_click_hold_ms(ms));
+        }
+        if let Some(ms) = self.modifier_delay_ms {
+            config.modifier_delay = Duration::from_millis(ms);
+        }
+        if let Some(step) = self.scroll_step {
+            warn_invalid(&source("scroll_step"), config.set_scroll_step(step));
+        }
+        if let Some(enabled) = self.binary_crc {
+            config.binary_crc = enabled;
+        }
+        match self.auth {
+            Some(true) => {
+                config.pin.get_or_insert_with(generate_pin);
+            }
+            Some(false) => config.pin = None,
+            None => {}
+        }
+        if let Some(pin) = &self.pin {
+            warn_invalid(&source("pin"), config.set_pin(pin));
+        }
+        if let Some(enabled) = self.encrypt {
+            config.encrypt = enabled;
+        }
+        if let Some(psk) = &self.psk {
+            warn_invalid(&source("psk"), config.set_psk(psk));
+        }
+        for (key, radius) in self.skill_radius.iter().flatten() {
+            warn_invalid(&source("skill_radius"), config.add_skill_radius(key, *radius));
+        }
+        if let Some(radius) = self.skill_radius_default {
+            warn_invalid(&source("skill_radius_default"), config.set_default_skill_radius(radius));
+        }
+        if let Some(factor) = self.smooth_factor {
+            warn_invalid(&source("smooth_factor"), config.set_smooth_factor(factor));
+        }
+        if let Some(curve) = &self.smooth_curve {
+            warn_invalid(&source("smooth_curve"), config.set_smooth_curve(curve));
+        }
+        self.tcp == Some(true)
+    }
+}
+
+/// 解析配置文件内容，配置方案中不能再嵌套配置方案，也不能使用基础配置的方案名
+fn parse_config_file(content: &str) -> Result<FileConfig, String> {
+    let file: FileConfig = toml::from_str(content).map_err(|e| e.to_string())?;
+    if let Some(name) = file.profiles.iter().flatten().find_map(|(name, p)| p.profiles.is_some().then_some(name)) {
+        return Err(format!("配置方案 {} 中不能再定义 profiles", name));
+    }
+    if file.profiles.iter().flatten().any(|(name, _)| name == crate::DEFAULT_PROFILE) {
+        return Err(format!("配置方案名 {} 已用于基础配置，请改用其他名称", crate::DEFAULT_PROFILE));
+    }
+    Ok(file)
+}
+
+fn load_config_file(path: &Path) -> Result<FileConfig, String> {
+    parse_config_file(&std::fs::read_to_string(path).map_err(|e| e.to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.apply_args(args.iter().map(|a| a.to_string()).collect());
+        config
+    }
+
+    #[test]
+    fn gamepad_key_routes_only_valid_pad_buttons() {
+        let config = parse(&["--gamepad-key", "Space=pad_a", "--gamepad-key", "e=pad_bogus"]);
+        assert_eq!(config.gamepad_keys.get("space"), Some(&GamepadButton::A));
+        assert_eq!(config.gamepad_keys.len(), 1);
+    }
+
+    #[test]
+    fn joystick_dir_rejects_unknown_keys_and_directions() {
+        let up_right = DIRECTION_NAMES.iter().position(|n| *n == "up_right").unwrap();
+        let config = parse(&["--joystick-dir", "up_right=W+D+shift"]);
+        assert_eq!(config.joystick.directions[up_right], ["w", "d", "shift"]);
+
+        let default = Config::default().joystick.directions;
+        let config = parse(&["--joystick-dir", "up_right=w+bogus", "--joystick-dir", "sideways=w"]);
+        assert_eq!(config.joystick.directions, default);
+    }
+
+    #[test]
+    fn skill_click_hold_below_minimum_keeps_default() {
+        let config = parse(&["--skill-click-hold-ms", "1"]);
+        assert_eq!(config.skill_click_hold, Config::default().skill_click_hold);
+        let config = parse(&["--skill-click-hold-ms", "40", "--skill-click-delay-ms", "20"]);
+        assert_eq!(config.skill_click_hold, Duration::from_millis(40));
+        assert_eq!(config.skill_click_delay, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn config_file_rejects_unknown_keys_and_wrong_types() {
+        assert!(parse_config_file("prot = 9527").is_err());
+        assert!(parse_config_file("port = \"9527\"").is_err());
+        assert!(parse_config_file("binary_crc = 1").is_err());
+        assert!(parse_config_file("[profiles.fps.profiles.inner]\nport = 1").is_err());
+        assert!(parse_config_file("[profiles.fps]\nbogus = 1").is_err());
+        // default 是基础配置的方案名，同名方案会被覆盖，直接拒绝
+        assert!(parse_config_file("[profiles.default]\nport = 1").is_err());
+    }
+
+    #[test]
+    fn config_file_values_merge_over_defaults() {
+        let file = parse_config_file(
+            r#"
+            port = 9600
+            binary_crc = true
+            joystick_keys = ["up", "left", "down", "right"]
+            self_cast = ["f", "d"]
+
+            [skill_radius]
+            q = 1200
+
+            [joystick_dir]
+            up_right = ["w", "d"]
+
+            [profiles.plain]
+            binary_crc = false
+            no_mdns = true
+            "#,
+        )
+        .unwrap();
+        let config = Config::build(file, Vec::new());
+        assert_eq!(config.port, 9600);
+        assert!(config.binary_crc);
+        assert_eq!(config.joystick.directions[0], ["up"]);
+        assert_eq!(config.self_cast, HashSet::from(["f".to_string(), "d".to_string()]));
+        assert_eq!(config.skill_radius.get("q"), Some(&1200));
+        let up_right = DIRECTION_NAMES.iter().position(|n| *n == "up_right").unwrap();
+        assert_eq!(config.joystick.directions[up_right], ["w", "d"]);
+        // 未出现的项保持默认
+        assert_eq!(config.smooth_factor, Config::default().smooth_factor);
+
+        // 取值检查与命令行参数相同，不合法的项保持原值
+        let file = parse_config_file("deadzone = 1.5\nscroll_step = 0\nskill_click_hold_ms = 40").unwrap();
+        let checked = Config::build(file, Vec::new());
+        assert_eq!(checked.joystick, Config::default().joystick);
+        assert_eq!(checked.scroll_step, Config::default().scroll_step);
+        assert_eq!(checked.skill_click_hold, Duration::from_millis(40));
+
+        // false 会关闭基础配置中打开的开关
+        let plain = &config.profiles["plain"];
+        assert!(!plain.binary_crc);
+        assert!(!plain.mdns);
+        assert_eq!(plain.port, 9600);
+    }
+}
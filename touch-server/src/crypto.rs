@@ -0,0 +1,176 @@
+//! 载荷加密
+//!
+//! 按键和文本默认以明文在局域网中传输。`--encrypt` 后每个 UDP 数据包 / TCP 帧都用 ChaCha20-Poly1305 加密：
+//! `[MAGIC][发送方 ID:8][nonce:12][密文 + tag:16]`，MAGIC 和发送方 ID 保持明文，密文内是原本的 JSON 或二进制消息。
+//! 发送方 ID 作为附加认证数据参与认证，认证失败（密钥不符、被篡改、未加密）的包一律丢弃。
+//!
+//! 发送方 ID 由每个客户端（及服务端）启动时随机生成。nonce 为 `[计数:u64 LE][随机:4]`，计数是发送方
+//! 严格递增的微秒时间戳。接收方按发送方 ID 记录最近一次通过认证的计数，不大于它的包视为重放而丢弃。
+//! 计数与来源地址无关，截获的数据包换端口、换主机或改走 TCP 重发同样会被丢弃；
+//! 改动发送方 ID 会导致认证失败，攻击者无法借新的 ID 绕开计数。
+//!
+//! 密钥由 `--psk` 指定的预共享密钥派生，未指定时使用配对 PIN。PIN 位数少、可被离线穷举，
+//! 对安全性有要求时应使用足够长的 `--psk`。
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use sha2::{Digest, Sha256};
+
+use crate::binary_protocol::MAGIC;
+
+/// 发送方 ID 的长度
+const SENDER_ID_LEN: usize = 8;
+const NONCE_LEN: usize = 12;
+/// nonce 中计数部分的长度
+const COUNTER_LEN: usize = 8;
+/// 最多记录多少个发送方的计数，超出时淘汰最久没有收到数据的发送方
+const MAX_TRACKED_SENDERS: usize = 1024;
+/// 密钥派生的上下文，防止同一口令在其他用途中派生出相同密钥
+const KEY_CONTEXT: &[u8] = b"air-touch/payload-key/v1";
+
+static CIPHER: OnceLock<ChaCha20Poly1305> = OnceLock::new();
+/// 本端的发送方 ID，启用加密时随机生成
+static SENDER_ID: OnceLock<[u8; SENDER_ID_LEN]> = OnceLock::new();
+/// 本端最近一次发送使用的计数
+static LAST_SENT: AtomicU64 = AtomicU64::new(0);
+/// 每个发送方最近一次通过认证的计数
+static LAST_RECEIVED: Mutex<Option<HashMap<[u8; SENDER_ID_LEN], u64>>> = Mutex::new(None);
+
+/// 下一个发送计数：当前微秒时间戳，时钟回拨或同一微秒内多次发送时在上一个计数上加一
+fn next_counter() -> u64 {
+    let now = crate::unix_millis().saturating_mul(1000);
+    let mut last = LAST_SENT.load(Ordering::Relaxed);
+    loop {
+        let next = now.max(last + 1);
+        match LAST_SENT.compare_exchange_weak(last, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return next,
+            Err(current) => last = current,
+        }
+    }
+}
+
+/// 计数比该发送方上一次的大时记录并返回 true，否则视为重放
+fn accept_counter(sender: [u8; SENDER_ID_LEN], counter: u64) -> bool {
+    let mut guard = LAST_RECEIVED.lock().unwrap_or_else(PoisonError::into_inner);
+    let last = guard.get_or_insert_with(HashMap::new);
+    if last.get(&sender).is_some_and(|&prev| counter <= prev) {
+        return false;
+    }
+    if !last.contains_key(&sender) && last.len() >= MAX_TRACKED_SENDERS {
+        if let Some(oldest) = last.iter().min_by_key(|(_, &c)| c).map(|(id, _)| *id) {
+            last.remove(&oldest);
+        }
+    }
+    last.insert(sender, counter);
+    true
+}
+
+/// 由口令派生密钥
+fn derive_cipher(secret: &str) -> ChaCha20Poly1305 {
+    let key = Sha256::new()
+        .chain_update(KEY_CONTEXT)
+        .chain_update(secret.as_bytes())
+        .finalize();
+    ChaCha20Poly1305::new(&key)
+}
+
+/// 启用加密（只在启动时调用一次）
+pub fn enable(secret: &str) {
+    let _ = CIPHER.set(derive_cipher(secret));
+    let mut id = [0u8; SENDER_ID_LEN];
+    OsRng.fill_bytes(&mut id);
+    let _ = SENDER_ID.set(id);
+}
+
+/// 加密一条待发送的消息（未启用时原样返回）
+pub fn seal(plain: &[u8]) -> Cow<'_, [u8]> {
+    let (Some(cipher), Some(sender)) = (CIPHER.get(), SENDER_ID.get()) else {
+        return Cow::Borrowed(plain);
+    };
+    Cow::Owned(seal_with(cipher, sender, plain))
+}
+
+fn seal_with(cipher: &ChaCha20Poly1305, sender: &[u8; SENDER_ID_LEN], plain: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..COUNTER_LEN].copy_from_slice(&next_counter().to_le_bytes());
+    OsRng.fill_bytes(&mut nonce[COUNTER_LEN..]);
+    let payload = Payload { msg: plain, aad: sender };
+    let Ok(ciphertext) = cipher.encrypt(Nonce::from_slice(&nonce), payload) else {
+        return Vec::new();
+    };
+    let mut packet = Vec::with_capacity(1 + SENDER_ID_LEN + NONCE_LEN + ciphertext.len());
+    packet.push(MAGIC);
+    packet.extend_from_slice(sender);
+    packet.extend_from_slice(&nonce);
+    packet.extend_from_slice(&ciphertext);
+    packet
+}
+
+/// 解密收到的数据包，格式不符、认证失败或重放时返回 None（未启用时原样返回）
+pub fn open(packet: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let Some(cipher) = CIPHER.get() else {
+        return Some(Cow::Borrowed(packet));
+    };
+    open_with(cipher, packet).map(Cow::Owned)
+}
+
+fn open_with(cipher: &ChaCha20Poly1305, packet: &[u8]) -> Option<Vec<u8>> {
+    let (&magic, rest) = packet.split_first()?;
+    if magic != MAGIC || rest.len() < SENDER_ID_LEN + NONCE_LEN {
+        return None;
+    }
+    let (sender, rest) = rest.split_at(SENDER_ID_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let payload = Payload { msg: ciphertext, aad: sender };
+    let plain = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+    // 通过认证后才记录计数，伪造的包无法推高计数来屏蔽正常客户端
+    let counter = u64::from_le_bytes(nonce[..COUNTER_LEN].try_into().ok()?);
+    accept_counter(sender.try_into().ok()?, counter).then_some(plain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_strictly_increasing() {
+        let first = next_counter();
+        let second = next_counter();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn replayed_or_older_counters_are_rejected_per_sender() {
+        let sender = [1u8; SENDER_ID_LEN];
+        let other = [2u8; SENDER_ID_LEN];
+        assert!(accept_counter(sender, 100));
+        assert!(!accept_counter(sender, 100));
+        assert!(!accept_counter(sender, 99));
+        assert!(accept_counter(sender, 101));
+        // 其他发送方的计数互不影响
+        assert!(accept_counter(other, 50));
+    }
+
+    #[test]
+    fn replays_are_rejected_and_the_sender_id_is_authenticated() {
+        // 不启用全局加密，避免影响其他测试
+        let cipher = derive_cipher("test-secret");
+        let sender = [3u8; SENDER_ID_LEN];
+        let packet = seal_with(&cipher, &sender, b"ping");
+        assert_eq!(open_with(&cipher, &packet).as_deref(), Some(&b"ping"[..]));
+        // 同一个包无论从哪个地址重发都被丢弃
+        assert!(open_with(&cipher, &packet).is_none());
+
+        // 改用新的发送方 ID 重发时认证失败
+        let mut forged = seal_with(&cipher, &sender, b"ping");
+        forged[1] ^= 0xFF;
+        assert!(open_with(&cipher, &forged).is_none());
+        assert!(open_with(&derive_cipher("other"), &seal_with(&cipher, &sender, b"ping")).is_none());
+    }
+}
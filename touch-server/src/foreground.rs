@@ -0,0 +1,99 @@
+//! 前台窗口上报
+//!
+//! 定期查询当前前台窗口的标题和进程名，变化时主动推送给客户端，
+//! 客户端可据此自动切换按键布局。窗口标题可能包含隐私信息，默认关闭，需 `--report-foreground` 开启。
+//!
+//! 事件格式：
+//! - JSON：`{"type":"foreground","title":"...","process":"..."}`
+//! - 二进制：`[magic][MSG_FOREGROUND][title_len:u8][title][process_len:u8][process]`，字段超过 255 字节时截断
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use log::info;
+use serde::Serialize;
+
+use crate::binary_protocol::{self, ByteOrder};
+
+/// 前台窗口轮询间隔
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// 事件接收方：所有活跃客户端的地址及其二进制协议字节序（None 表示 JSON）
+pub type EventTargets = Arc<Mutex<Vec<(SocketAddr, Option<ByteOrder>)>>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ForegroundMessage {
+    r#type: &'static str,
+    title: String,
+    process: String,
+}
+
+/// 查询当前前台窗口，失败（无权限、无窗口、Wayland 等）时返回 None
+fn query_foreground() -> Option<ForegroundMessage> {
+    let window = active_win_pos_rs::get_active_window().ok()?;
+    Some(ForegroundMessage {
+        r#type: "foreground",
+        title: window.title,
+        process: window.app_name,
+    })
+}
+
+/// 截断到不超过 255 字节（保持 UTF-8 字符完整）
+fn truncate_field(s: &str) -> &[u8] {
+    let mut end = s.len().min(u8::MAX as usize);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s.as_bytes()[..end]
+}
+
+fn build_binary_foreground(msg: &ForegroundMessage, order: ByteOrder) -> Vec<u8> {
+    let title = truncate_field(&msg.title);
+    let process = truncate_field(&msg.process);
+    let mut buf = Vec::with_capacity(5 + title.len() + process.len());
+    buf.push(order.magic());
+    buf.push(binary_protocol::MSG_FOREGROUND);
+    buf.push(title.len() as u8);
+    buf.extend_from_slice(title);
+    buf.push(process.len() as u8);
+    buf.extend_from_slice(process);
+    binary_protocol::seal(buf)
+}
+
+/// 启动前台窗口监视线程
+pub fn spawn_watcher(socket: UdpSocket, targets: EventTargets) {
+    info!("[前台窗口] 已启用前台窗口上报");
+    thread::spawn(move || {
+        let mut last: Option<ForegroundMessage> = None;
+        let mut notified: Vec<SocketAddr> = Vec::new();  // 已收到当前窗口信息的客户端
+        loop {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            let targets = targets.lock().unwrap_or_else(PoisonError::into_inner).clone();
+            if targets.is_empty() {
+                continue;
+            }
+            let Some(current) = query_foreground() else { continue };
+            if last.as_ref() != Some(&current) {
+                notified.clear();
+            }
+            // 窗口变化时通知所有客户端，新连接的客户端补发一次
+            for (addr, order) in targets {
+                if notified.contains(&addr) {
+                    continue;
+                }
+                let data = match order {
+                    Some(order) => build_binary_foreground(&current, order),
+                    None => match serde_json::to_vec(&current) {
+                        Ok(data) => data,
+                        Err(_) => continue,
+                    },
+                };
+                crate::send_packet(&socket, &data, addr);
+                notified.push(addr);
+            }
+            last = Some(current);
+        }
+    });
+}
@@ -0,0 +1,265 @@
+//! 虚拟手柄输出
+//!
+//! 键盘鼠标只能模拟 WASD 这类“开/关”方向，很多游戏用真正的模拟摇杆手感好得多。
+//! `--joystick-mode gamepad` 时摇杆偏移直接作为虚拟手柄的左摇杆输出，`pad_` 开头的按键
+//! （如 `pad_a`、`pad_lb`、`pad_up`）映射为手柄按键，其余按键仍由 enigo 模拟。
+//!
+//! 目前只有 Linux uinput 实现（需 `--features gamepad`，且当前用户可写 /dev/uinput），
+//! 其他平台或创建失败时回退为方向键映射。
+//!
+//! 虚拟手柄声明了震动（FF_RUMBLE）能力，游戏播放震动效果时由定时线程读出，
+//! 以 `rumble` 事件转发给客户端，由手机振动反馈。
+
+use log::warn;
+
+/// 手柄按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftBumper,
+    RightBumper,
+    Back,
+    Start,
+    Guide,
+    LeftThumb,
+    RightThumb,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 15] = [
+        GamepadButton::A,
+        GamepadButton::B,
+        GamepadButton::X,
+        GamepadButton::Y,
+        GamepadButton::LeftBumper,
+        GamepadButton::RightBumper,
+        GamepadButton::Back,
+        GamepadButton::Start,
+        GamepadButton::Guide,
+        GamepadButton::LeftThumb,
+        GamepadButton::RightThumb,
+        GamepadButton::DpadUp,
+        GamepadButton::DpadDown,
+        GamepadButton::DpadLeft,
+        GamepadButton::DpadRight,
+    ];
+
+    /// 解析 `pad_` 开头的按键名（不区分大小写）
+    pub fn parse(key: &str) -> Option<Self> {
+        let name = key.to_lowercase();
+        let button = match name.strip_prefix("pad_")? {
+            "a" => GamepadButton::A,
+            "b" => GamepadButton::B,
+            "x" => GamepadButton::X,
+            "y" => GamepadButton::Y,
+            "lb" => GamepadButton::LeftBumper,
+            "rb" => GamepadButton::RightBumper,
+            "back" | "select" => GamepadButton::Back,
+            "start" => GamepadButton::Start,
+            "guide" | "home" => GamepadButton::Guide,
+            "ls" => GamepadButton::LeftThumb,
+            "rs" => GamepadButton::RightThumb,
+            "up" => GamepadButton::DpadUp,
+            "down" => GamepadButton::DpadDown,
+            "left" => GamepadButton::DpadLeft,
+            "right" => GamepadButton::DpadRight,
+            _ => return None,
+        };
+        Some(button)
+    }
+}
+
+/// 游戏发给虚拟手柄的震动效果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rumble {
+    /// 重马达强度（0~65535）
+    pub strong: u16,
+    /// 轻马达强度（0~65535）
+    pub weak: u16,
+    /// 持续时间（毫秒），0 表示停止震动
+    pub duration_ms: u16,
+}
+
+impl Rumble {
+    /// 合并两个马达后的强度（0~255），供客户端直接换算为振幅
+    pub fn intensity(&self) -> u8 {
+        (self.strong.max(self.weak) >> 8) as u8
+    }
+}
+
+/// 手柄输出后端
+pub trait GamepadOutput: Send {
+    /// 设置左摇杆，x/y 为 -1~1（y 向下为正，与客户端一致）
+    fn set_stick(&mut self, x: f32, y: f32);
+    /// 按下/释放一个手柄按键
+    fn button(&mut self, button: GamepadButton, pressed: bool);
+    /// 读取游戏最近一次开始/停止的震动效果（不阻塞），没有新的震动时返回 None
+    fn poll_rumble(&mut self) -> Option<Rumble> {
+        None
+    }
+}
+
+/// 创建当前平台的虚拟手柄，不支持或失败时返回 None
+pub fn create() -> Option<Box<dyn GamepadOutput>> {
+    #[cfg(all(feature = "gamepad", target_os = "linux"))]
+    {
+        match uinput::UinputGamepad::new() {
+            Ok(pad) => {
+                log::info!("[手柄] 已创建虚拟手柄");
+                return Some(Box::new(pad));
+            }
+            Err(e) => {
+                warn!("[手柄] 创建虚拟手柄失败（需要 /dev/uinput 写权限）: {}，摇杆改用方向键", e);
+                return None;
+            }
+        }
+    }
+    #[allow(unreachable_code)]
+    {
+        warn!("[手柄] 当前构建不支持虚拟手柄（Linux 需 --features gamepad 编译），摇杆改用方向键");
+        None
+    }
+}
+
+#[cfg(all(feature = "gamepad", target_os = "linux"))]
+mod uinput {
+    use std::collections::HashMap;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+    use evdev::{
+        AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, FFEffectKind, FFEffectType,
+        InputEvent, InputEventKind, InputId, Key, UInputEventType, UinputAbsSetup,
+    };
+
+    use super::{GamepadButton, GamepadOutput, Rumble};
+
+    const AXIS_MAX: i32 = 32767;
+    const FF_EFFECTS_MAX: u32 = 16;  // 游戏最多可同时上传的震动效果数
+
+    /// 以 Xbox 360 手柄的身份注册，大多数游戏无需额外映射即可识别
+    pub struct UinputGamepad {
+        device: VirtualDevice,
+        effects: HashMap<i16, Rumble>,  // 游戏已上传的震动效果（按效果 ID）
+    }
+
+    fn key_code(button: GamepadButton) -> Key {
+        match button {
+            GamepadButton::A => Key::BTN_SOUTH,
+            GamepadButton::B => Key::BTN_EAST,
+            GamepadButton::X => Key::BTN_WEST,
+            GamepadButton::Y => Key::BTN_NORTH,
+            GamepadButton::LeftBumper => Key::BTN_TL,
+            GamepadButton::RightBumper => Key::BTN_TR,
+            GamepadButton::Back => Key::BTN_SELECT,
+            GamepadButton::Start => Key::BTN_START,
+            GamepadButton::Guide => Key::BTN_MODE,
+            GamepadButton::LeftThumb => Key::BTN_THUMBL,
+            GamepadButton::RightThumb => Key::BTN_THUMBR,
+            GamepadButton::DpadUp => Key::BTN_DPAD_UP,
+            GamepadButton::DpadDown => Key::BTN_DPAD_DOWN,
+            GamepadButton::DpadLeft => Key::BTN_DPAD_LEFT,
+            GamepadButton::DpadRight => Key::BTN_DPAD_RIGHT,
+        }
+    }
+
+    impl UinputGamepad {
+        pub fn new() -> io::Result<Self> {
+            let mut keys = AttributeSet::<Key>::new();
+            for button in GamepadButton::ALL {
+                keys.insert(key_code(button));
+            }
+            let mut ff = AttributeSet::<FFEffectType>::new();
+            ff.insert(FFEffectType::FF_RUMBLE);
+            let axis = AbsInfo::new(0, -AXIS_MAX, AXIS_MAX, 16, 128, 0);
+            let device = VirtualDeviceBuilder::new()?
+                .name("Touch Server Gamepad")
+                .input_id(InputId::new(BusType::BUS_USB, 0x045e, 0x028e, 0x0110))
+                .with_keys(&keys)?
+                .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_X, axis))?
+                .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, axis))?
+                .with_ff(&ff)?
+                .with_ff_effects_max(FF_EFFECTS_MAX)
+                .build()?;
+            // 震动事件由定时线程轮询读取，不能阻塞
+            let fd = device.as_raw_fd();
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+            Ok(Self { device, effects: HashMap::new() })
+        }
+    }
+
+    impl GamepadOutput for UinputGamepad {
+        fn set_stick(&mut self, x: f32, y: f32) {
+            let scale = |v: f32| (v.clamp(-1.0, 1.0) * AXIS_MAX as f32).round() as i32;
+            let _ = self.device.emit(&[
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, scale(x)),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, scale(y)),
+            ]);
+        }
+
+        fn button(&mut self, button: GamepadButton, pressed: bool) {
+            let _ = self.device.emit(&[InputEvent::new(EventType::KEY, key_code(button).code(), pressed as i32)]);
+        }
+
+        fn poll_rumble(&mut self) -> Option<Rumble> {
+            let mut latest = None;
+            // 没有待读事件时 fetch_events 返回 WouldBlock
+            loop {
+                let events: Vec<_> = match self.device.fetch_events() {
+                    Ok(events) => events.collect(),
+                    Err(_) => break,
+                };
+                if events.is_empty() {
+                    break;
+                }
+                for event in events {
+                    match event.kind() {
+                        // 上传/删除效果必须应答，否则游戏的 ioctl 会一直等待
+                        InputEventKind::UInput(code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                            let Ok(mut upload) = self.device.process_ff_upload(event) else { continue };
+                            let effect = upload.effect();
+                            match effect.kind {
+                                FFEffectKind::Rumble { strong_magnitude, weak_magnitude } => {
+                                    let rumble = Rumble {
+                                        strong: strong_magnitude,
+                                        weak: weak_magnitude,
+                                        duration_ms: effect.replay.length,
+                                    };
+                                    self.effects.insert(upload.effect_id(), rumble);
+                                    upload.set_retval(0);
+                                }
+                                _ => upload.set_retval(-libc::EINVAL),
+                            }
+                        }
+                        InputEventKind::UInput(code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                            let Ok(mut erase) = self.device.process_ff_erase(event) else { continue };
+                            self.effects.remove(&(erase.effect_id() as i16));
+                            erase.set_retval(0);
+                        }
+                        // 播放（value 为重复次数）或停止一个已上传的效果
+                        InputEventKind::ForceFeedback(id) => {
+                            latest = if event.value() > 0 {
+                                self.effects.get(&(id as i16)).copied().or(latest)
+                            } else {
+                                Some(Rumble::default())
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            latest
+        }
+    }
+}
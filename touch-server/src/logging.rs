@@ -0,0 +1,101 @@
+//! 日志输出
+//!
+//! 日志始终输出到标准输出；`--log-file` 指定文件后同时写入文件，文件超过大小上限时轮转
+//! （`touch.log` → `touch.log.1` → `touch.log.2` ...）。`--quiet` 只保留警告和错误，
+//! 适合无人值守运行，绑定端口失败等错误在任何模式下都会输出。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use log::LevelFilter;
+
+/// 单个日志文件的大小上限
+const MAX_LOG_FILE_BYTES: u64 = 10 * 1024 * 1024;
+/// 轮转时保留的旧日志文件数
+const LOG_FILE_BACKUPS: u32 = 3;
+
+static LOG_FILE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+
+/// 按大小轮转的日志文件
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, written })
+    }
+
+    fn backup_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..LOG_FILE_BACKUPS).rev() {
+            let _ = fs::rename(self.backup_path(index), self.backup_path(index + 1));
+        }
+        fs::rename(&self.path, self.backup_path(1))?;
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.written + buf.len() as u64 > MAX_LOG_FILE_BYTES {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// 日志输出目标：标准输出 + 可选的日志文件
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write_all(buf)?;
+        if let Some(file) = LOG_FILE.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+            // 写文件失败不影响控制台输出
+            let _ = file.write_record(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// 初始化日志：默认输出本程序 info 及以上级别（依赖库只输出 warn 以上），
+/// 可用 RUST_LOG 调整，如 `RUST_LOG=touch_server=debug` 显示逐条输入事件
+pub fn init() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,touch_server=info"))
+        .target(env_logger::Target::Pipe(Box::new(LogWriter)))
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{} {}", level, record.args()),
+        })
+        .init();
+}
+
+/// 按配置调整日志：安静模式只保留警告和错误，指定日志文件时同时写入文件
+pub fn configure(quiet: bool, log_file: Option<&Path>) {
+    if quiet && log::max_level() > LevelFilter::Warn {
+        log::set_max_level(LevelFilter::Warn);
+    }
+    if let Some(path) = log_file {
+        match RotatingFile::open(path) {
+            Ok(file) => *LOG_FILE.lock().unwrap_or_else(PoisonError::into_inner) = Some(file),
+            Err(e) => log::error!("[日志] 无法打开日志文件 {}: {}", path.display(), e),
+        }
+    }
+}
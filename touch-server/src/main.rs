@@ -1,21 +1,65 @@
+mod config;
+mod crypto;
+mod foreground;
+mod gamepad;
+mod logging;
+mod metrics;
+mod net;
+mod permission;
+mod tcp;
+
+use config::{
+    Config, DeadzoneMode, HeldKeyPolicy, IpMode, JoystickConfig, JoystickMode, LogFilter, SmoothCurve,
+    Transport, MAX_SCROLL_AMOUNT,
+};
 use display_info::DisplayInfo;
 use enigo::{Button, Coordinate, Enigo, Key, Keyboard, Mouse, Settings};
-use local_ip_address::local_ip;
+use binary_protocol::ByteOrder;
+use gamepad::{GamepadButton, GamepadOutput};
+use log::{debug, error, info, warn};
 use mdns_sd::{ServiceDaemon, ServiceInfo};
+use metrics::METRICS;
 use mouse_position::mouse_position::Mouse as MousePos;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::net::UdpSocket;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::Instant;
 use std::thread;
 
-const PORT: u16 = 9527;
+const PORT: u16 = 9527;  // 默认端口，可用 --port 或 TOUCH_PORT 覆盖
 const SERVICE_TYPE: &str = "_touchserver._udp.local.";
-const DEADZONE: f32 = 0.2;
-const HEARTBEAT_TIMEOUT_SECS: u64 = 3;
-const SKILL_MOUSE_RADIUS: i32 = 800;
-const SKILL_CLICK_DELAY_MS: u64 = 50;   // 技能释放时鼠标移动后的点击延迟
-const SKILL_CLICK_HOLD_MS: u64 = 100;   // 鼠标按下保持时间
+const DEADZONE: f32 = 0.2;  // 默认摇杆死区，可用 --deadzone 覆盖
+const HEARTBEAT_TIMEOUT_SECS: u64 = 3;  // 默认心跳超时，可用 --heartbeat-timeout 覆盖
+const SKILL_MOUSE_RADIUS: i32 = 800;  // 默认技能半径，可用 --skill-radius-default 覆盖或 --skill-radius 按技能覆盖
+const SKILL_CLICK_DELAY_MS: u64 = 50;   // 技能释放时鼠标移动后的点击延迟（默认值）
+const SKILL_CLICK_HOLD_MS: u64 = 100;   // 鼠标按下保持时间（默认值）
+const MODIFIER_DELAY_MS: u64 = 10;      // 修饰键与主键之间的等待时间（默认值）
+const TICK_INTERVAL_MS: u64 = 8;        // 定时任务周期（约 120Hz）
+const MAX_TEXT_BYTES: usize = 4096;     // 单条文本消息的最大长度
+const MDNS_IP_POLL_SECS: u64 = 5;       // 检查本机 IP 变化的间隔
+const CONFIG_POLL_INTERVAL_MS: u64 = 1000;  // 检查配置文件修改的间隔
+const DEFAULT_PROFILE: &str = "default";  // 基础配置对应的方案名
+const AUTH_TIMEOUT_SECS: u64 = 10;      // 未通过认证的会话保留时长
+const MAX_AUTH_ATTEMPTS: u32 = 5;       // 每个来源 IP 连续 PIN 错误多少次后锁定
+const AUTH_LOCKOUT_SECS: u64 = 30;      // 首次锁定时长，之后每次锁定翻倍
+const MAX_AUTH_LOCKOUT_SECS: u64 = 3600;
+const MAX_AUTH_TRACKED: usize = 1024;   // 最多记录多少个来源 IP 的 PIN 错误
+const ENIGO_ERROR_THRESHOLD: u32 = 5;   // 连续多少次输入失败后清空按键状态
+const DEFAULT_CLICK_INTERVAL_MS: u16 = 80;  // 多次点击的默认间隔
+const MAX_CLICK_COUNT: u8 = 5;          // 单条点击消息的最大点击次数
+const MAX_CLICK_INTERVAL_MS: u16 = 1000;
+const MONITOR_CHECK_INTERVAL_MS: u64 = 500;  // 技能拖拽期间检查显示器布局变化的间隔
+const DRAG_STEP_MS: u64 = 16;           // 拖放各步之间的间隔（按下后、移动后各等待一步）
+const MAX_BATCH_EVENTS: usize = 32;     // 单条批量消息的最大事件数
+const MAX_WARNED_KEYS: usize = 64;      // 最多记录多少个已警告过的未知按键名
+const DEFAULT_TURBO_HZ: u16 = 10;       // 连发默认频率
+const MAX_TURBO_HZ: u16 = 30;           // 连发最高频率，过快时游戏可能识别不到单次点击
+const DRAG_SMOOTH_STEPS: u32 = 12;      // 平滑拖放的移动步数
+const MAX_UDP_SESSIONS: usize = 32;     // 同时存在的 UDP 会话上限（含未认证的会话）
+const MAX_RATE_LIMITED_IPS: usize = 1024;  // 最多同时为多少个来源 IP 限流
+const MAX_LOGGED_SOURCES: usize = 256;  // 每类警告最多记录多少个已提示过的来源，满后清空重新记录
 
 // 极限模式：二进制协议消息类型
 mod binary_protocol {
@@ -28,15 +72,174 @@ mod binary_protocol {
     pub const MSG_PING: u8 = 0x07;
     pub const MSG_PONG: u8 = 0x08;
     pub const MSG_ACK: u8 = 0x09;
-    // 可靠消息类型（带序列号，需要ACK）
+    pub const MSG_SET_ACTIVE: u8 = 0x0A;
+    pub const MSG_FOREGROUND: u8 = 0x0B;  // 服务端推送：前台窗口变化
+    pub const MSG_TEXT: u8 = 0x0C;
+    pub const MSG_MOUSE_MOVE: u8 = 0x0D;
+    pub const MSG_AUTH: u8 = 0x0E;
+    pub const MSG_AUTH_RESULT: u8 = 0x0F;
+    // 可靠消息类型（带序列号，需要ACK），类型值为对应普通消息加 0x10
     pub const MSG_RELIABLE_BUTTON: u8 = 0x12;
     pub const MSG_RELIABLE_SKILL_RELEASE: u8 = 0x15;
     pub const MSG_RELIABLE_SKILL_CANCEL: u8 = 0x16;
+    // 扩展消息类型，从 0x20 开始，避开可靠消息的 0x10 区间
+    pub const MSG_COMBO: u8 = 0x20;
+    pub const MSG_SCROLL: u8 = 0x21;
+    pub const MSG_SELECT_PROFILE: u8 = 0x22;
+    pub const MSG_CLICK: u8 = 0x23;
+    pub const MSG_DRAG: u8 = 0x24;
+    pub const MSG_RELEASE_ALL: u8 = 0x25;
+    pub const MSG_TURBO: u8 = 0x26;
+    pub const MSG_KEEPALIVE: u8 = 0x27;  // 服务端推送：保活
+    pub const MSG_RUMBLE: u8 = 0x28;  // 服务端推送：手柄震动
     pub const MAGIC: u8 = 0xAB;  // 魔数，用于识别二进制协议
+    pub const MAGIC_BE: u8 = 0xBA;  // 大端序客户端使用的魔数，多字节字段按大端序编码
+    /// 二进制协议版本，帧格式变化时递增；不带版本字节的旧客户端视为版本 1
+    pub const PROTOCOL_VERSION: u8 = 1;
+    pub const MIN_PROTOCOL_VERSION: u8 = 1;
+    /// 魔数后的字节最高位为 1 时表示版本字节（消息类型都小于 0x80），低 7 位为版本号
+    const VERSION_FLAG: u8 = 0x80;
+
+    use std::borrow::Cow;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// 是否已警告过不支持的协议版本（只警告一次）
+    static VERSION_WARNED: AtomicBool = AtomicBool::new(false);
+
+    /// 去掉可选的版本字节：`[magic][0x80|版本][type]...` 转为 `[magic][type]...`；
+    /// 版本不在支持范围内时返回 None，不猜测其格式
+    pub fn strip_version(buf: &[u8]) -> Option<Cow<'_, [u8]>> {
+        match buf.get(1) {
+            Some(&byte) if byte & VERSION_FLAG != 0 => {
+                let version = byte & !VERSION_FLAG;
+                if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+                    if !VERSION_WARNED.swap(true, Ordering::Relaxed) {
+                        log::warn!(
+                            "[协议] 客户端使用不支持的二进制协议版本 {}（支持 {}~{}），已丢弃",
+                            version,
+                            MIN_PROTOCOL_VERSION,
+                            PROTOCOL_VERSION
+                        );
+                    }
+                    return None;
+                }
+                let mut stripped = Vec::with_capacity(buf.len() - 1);
+                stripped.push(buf[0]);
+                stripped.extend_from_slice(&buf[2..]);
+                Some(Cow::Owned(stripped))
+            }
+            _ => Some(Cow::Borrowed(buf)),
+        }
+    }
+
+    /// 二进制消息中多字节字段的字节序，由首字节的魔数决定（默认小端序）
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum ByteOrder {
+        #[default]
+        Little,
+        Big,
+    }
+
+    impl ByteOrder {
+        /// 根据魔数识别二进制消息的字节序，不是二进制消息时返回 None
+        pub fn of(buf: &[u8]) -> Option<ByteOrder> {
+            match *buf.first()? {
+                MAGIC => Some(ByteOrder::Little),
+                MAGIC_BE => Some(ByteOrder::Big),
+                _ => None,
+            }
+        }
+
+        pub fn magic(self) -> u8 {
+            match self {
+                ByteOrder::Little => MAGIC,
+                ByteOrder::Big => MAGIC_BE,
+            }
+        }
+
+        /// 读取 N 字节并按字节序转换为小端序（调用方保证长度足够）
+        fn read<const N: usize>(self, buf: &[u8], at: usize) -> [u8; N] {
+            let mut bytes = [0u8; N];
+            bytes.copy_from_slice(&buf[at..at + N]);
+            if self == ByteOrder::Big {
+                bytes.reverse();
+            }
+            bytes
+        }
+
+        pub fn u16(self, buf: &[u8], at: usize) -> u16 {
+            u16::from_le_bytes(self.read(buf, at))
+        }
+
+        pub fn u32(self, buf: &[u8], at: usize) -> u32 {
+            u32::from_le_bytes(self.read(buf, at))
+        }
+
+        pub fn u64(self, buf: &[u8], at: usize) -> u64 {
+            u64::from_le_bytes(self.read(buf, at))
+        }
+
+        pub fn f32(self, buf: &[u8], at: usize) -> f32 {
+            f32::from_bits(self.u32(buf, at))
+        }
+
+        pub fn put_u16(self, buf: &mut Vec<u8>, value: u16) {
+            match self {
+                ByteOrder::Little => buf.extend_from_slice(&value.to_le_bytes()),
+                ByteOrder::Big => buf.extend_from_slice(&value.to_be_bytes()),
+            }
+        }
+
+        pub fn put_u32(self, buf: &mut Vec<u8>, value: u32) {
+            match self {
+                ByteOrder::Little => buf.extend_from_slice(&value.to_le_bytes()),
+                ByteOrder::Big => buf.extend_from_slice(&value.to_be_bytes()),
+            }
+        }
+
+        pub fn put_u64(self, buf: &mut Vec<u8>, value: u64) {
+            match self {
+                ByteOrder::Little => buf.extend_from_slice(&value.to_le_bytes()),
+                ByteOrder::Big => buf.extend_from_slice(&value.to_be_bytes()),
+            }
+        }
+    }
+
+    /// 是否启用 CRC8 校验（--binary-crc）：启用后收发的每个二进制消息末尾都带 1 字节校验和
+    pub static CRC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+    /// CRC-8（多项式 0x07，初始值 0）
+    pub fn crc8(data: &[u8]) -> u8 {
+        let mut crc = 0u8;
+        for &byte in data {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            }
+        }
+        crc
+    }
+
+    /// 发送前附加校验和（未启用时原样返回）
+    pub fn seal(mut buf: Vec<u8>) -> Vec<u8> {
+        if CRC_ENABLED.load(Ordering::Relaxed) {
+            buf.push(crc8(&buf));
+        }
+        buf
+    }
+
+    /// 校验并去掉末尾的校验和，校验失败返回 None（未启用时原样返回）
+    pub fn verify(buf: &[u8]) -> Option<&[u8]> {
+        if !CRC_ENABLED.load(Ordering::Relaxed) {
+            return Some(buf);
+        }
+        let (&checksum, body) = buf.split_last()?;
+        (crc8(body) == checksum).then_some(body)
+    }
 }
 
 /// 显示器信息
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct Monitor {
     x: i32,
     y: i32,
@@ -52,6 +255,23 @@ impl Monitor {
         )
     }
 
+    /// 将归一化坐标（0~1，超出范围会被限制）映射为显示器内的绝对坐标
+    fn point_at(&self, nx: f32, ny: f32) -> (i32, i32) {
+        let max_x = self.width.saturating_sub(1) as f32;
+        let max_y = self.height.saturating_sub(1) as f32;
+        (
+            self.x + (nx.clamp(0.0, 1.0) * max_x).round() as i32,
+            self.y + (ny.clamp(0.0, 1.0) * max_y).round() as i32,
+        )
+    }
+
+    /// 将坐标限制在显示器范围内
+    fn clamp(&self, x: f32, y: f32) -> (f32, f32) {
+        let max_x = (self.x + self.width as i32 - 1) as f32;
+        let max_y = (self.y + self.height as i32 - 1) as f32;
+        (x.clamp(self.x as f32, max_x), y.clamp(self.y as f32, max_y))
+    }
+
     fn contains(&self, x: i32, y: i32) -> bool {
         x >= self.x
             && x < self.x + self.width as i32
@@ -82,29 +302,55 @@ fn get_mouse_position() -> Option<(i32, i32)> {
     }
 }
 
-/// 获取鼠标所在显示器的中心坐标
-fn get_current_display_center() -> (i32, i32) {
-    let monitors = get_all_monitors();
-    
-    if let Some((mx, my)) = get_mouse_position() {
+/// 显示器布局与鼠标位置的来源
+///
+/// InputState 只通过它查询系统，几何逻辑（显示器选择、中心计算、越界限制）可以换成构造的多显示器布局来验证
+trait DisplayProvider: Send {
+    fn monitors(&self) -> Vec<Monitor>;
+    fn mouse_position(&self) -> Option<(i32, i32)>;
+
+    /// 鼠标所在的显示器（找不到时回退到第一个显示器）
+    fn current_monitor(&self) -> Option<Monitor> {
+        monitor_at(self.monitors(), self.mouse_position())
+    }
+}
+
+/// 查询真实系统的显示器和鼠标位置
+struct SystemDisplay;
+
+impl DisplayProvider for SystemDisplay {
+    fn monitors(&self) -> Vec<Monitor> {
+        get_all_monitors()
+    }
+
+    fn mouse_position(&self) -> Option<(i32, i32)> {
+        get_mouse_position()
+    }
+}
+
+/// 键盘鼠标输出后端：正常运行时为 Enigo，测试中替换为只记录调用的实现
+trait InputBackend: Keyboard + Mouse + Send {}
+
+impl<T: Keyboard + Mouse + Send> InputBackend for T {}
+
+/// 在给定的显示器中找到包含鼠标位置的一个（找不到时回退到第一个显示器）
+fn monitor_at(monitors: Vec<Monitor>, mouse: Option<(i32, i32)>) -> Option<Monitor> {
+    if let Some((mx, my)) = mouse {
         // 找到鼠标所在的显示器
         for monitor in &monitors {
             if monitor.contains(mx, my) {
-                return monitor.center();
+                return Some(monitor.clone());
             }
         }
     }
     
-    // 回退：使用第一个显示器或默认值
-    monitors
-        .first()
-        .map(|m| m.center())
-        .unwrap_or((960, 540))
+    monitors.into_iter().next()
 }
 
 
+
 /// 修饰键
-#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy)]
 struct Modifiers {
     #[serde(default)]
     shift: bool,
@@ -129,6 +375,47 @@ impl Modifiers {
             command: (b & 0x08) != 0,
         }
     }
+
+    fn union(&self, other: &Modifiers) -> Modifiers {
+        Modifiers {
+            shift: self.shift || other.shift,
+            control: self.control || other.control,
+            alt: self.alt || other.alt,
+            command: self.command || other.command,
+        }
+    }
+
+    fn intersection(&self, other: &Modifiers) -> Modifiers {
+        Modifiers {
+            shift: self.shift && other.shift,
+            control: self.control && other.control,
+            alt: self.alt && other.alt,
+            command: self.command && other.command,
+        }
+    }
+
+    /// 按键名对应的修饰键（含左右区分的写法），不是修饰键时返回 None
+    fn of_key(key_lower: &str) -> Option<Modifiers> {
+        let mut mods = Modifiers::default();
+        match key_lower {
+            "shift" | "lshift" | "rshift" => mods.shift = true,
+            "ctrl" | "control" | "lctrl" | "lcontrol" | "rctrl" | "rcontrol" => mods.control = true,
+            "alt" | "lalt" | "ralt" => mods.alt = true,
+            "cmd" | "meta" | "win" | "lcmd" | "lmeta" | "lwin" | "rcmd" | "rmeta" | "rwin" => mods.command = true,
+            _ => return None,
+        }
+        Some(mods)
+    }
+
+    /// 去掉 other 中包含的修饰键
+    fn without(&self, other: &Modifiers) -> Modifiers {
+        Modifiers {
+            shift: self.shift && !other.shift,
+            control: self.control && !other.control,
+            alt: self.alt && !other.alt,
+            command: self.command && !other.command,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -139,7 +426,7 @@ enum InputMessage {
     #[serde(rename = "button")]
     Button { key: String, pressed: bool, #[serde(default)] modifiers: Option<Modifiers>, #[serde(default)] seq: Option<u32> },
     #[serde(rename = "skill_start")]
-    SkillStart { key: String, #[serde(default)] offset_x: i32, #[serde(default)] offset_y: i32, #[serde(default)] modifiers: Option<Modifiers> },
+    SkillStart { key: String, #[serde(default)] offset_x: i32, #[serde(default)] offset_y: i32, #[serde(default)] modifiers: Option<Modifiers>, #[serde(default)] confirm: Option<String> },
     #[serde(rename = "skill_drag")]
     SkillDrag { key: String, dx: f32, dy: f32, distance: f32, #[serde(default)] smooth: bool },
     #[serde(rename = "skill_release")]
@@ -148,12 +435,238 @@ enum InputMessage {
     SkillCancel { key: String, #[serde(default)] seq: Option<u32> },
     #[serde(rename = "ping")]
     Ping { timestamp: u64 },
+    /// 能力协商；客户端可附带根据上次响应中 server_time 算出的时钟偏移（服务端时钟 - 客户端时钟，毫秒）
+    #[serde(rename = "capabilities")]
+    Capabilities { #[serde(default)] clock_offset_ms: Option<i64> },
+    #[serde(rename = "set_active")]
+    SetActive { active: bool },
+    #[serde(rename = "text")]
+    Text { content: String },
+    /// 绝对定位：x/y 为鼠标所在显示器上的归一化坐标（0~1）
+    #[serde(rename = "mouse_pos")]
+    MousePos { x: f32, y: f32 },
+    /// 相对移动：dx/dy 乘以灵敏度后作为像素位移
+    #[serde(rename = "mouse_move")]
+    MouseMove { dx: f32, dy: f32 },
+    /// 配对认证：启用 PIN 时必须先发送，否则其他消息一律丢弃
+    #[serde(rename = "auth")]
+    Auth { pin: String },
+    /// 按指定格数滚动：axis 为 vertical / horizontal，amount 正数向下/右
+    #[serde(rename = "scroll")]
+    Scroll { axis: String, amount: i32 },
+    /// 组合键：按顺序按下所有键，再逆序释放
+    #[serde(rename = "combo")]
+    Combo { keys: Vec<String> },
+    /// 切换配置方案（default 为基础配置）
+    #[serde(rename = "select_profile")]
+    SelectProfile { name: String },
+    /// 多次点击：同一鼠标键连续点击 count 次，每次间隔 interval_ms
+    #[serde(rename = "click")]
+    Click { button: String, count: u8, #[serde(default = "default_click_interval")] interval_ms: u16 },
+    /// 拖放：按住鼠标键移动到 to_x/to_y（当前显示器上的归一化坐标）后松开，smooth 时沿直线逐步移动
+    #[serde(rename = "drag")]
+    Drag { button: String, to_x: f32, to_y: f32, #[serde(default)] smooth: bool },
+    /// 查询服务端当前状态（按住的键、修饰键、进行中的技能），用于排查按键卡住；
+    /// 响应远大于请求且包含按键信息，只在启用 PIN 时响应
+    #[serde(rename = "query")]
+    Query,
+    /// 立即释放所有按键、鼠标键和进行中的技能（客户端切到后台或检测到自身状态异常时发送）
+    #[serde(rename = "release_all")]
+    ReleaseAll,
+    /// 连发：pressed 为 true 时按 rate_hz 频率反复点击 key，直到收到 pressed 为 false
+    #[serde(rename = "turbo")]
+    Turbo { key: String, pressed: bool, #[serde(default = "default_turbo_rate")] rate_hz: u16 },
+    /// 批量消息：同一个数据包内按顺序处理多个输入；不允许嵌套，也不能包含控制消息和带序列号的可靠消息
+    #[serde(rename = "batch")]
+    Batch { events: Vec<InputMessage> },
+}
+
+fn default_click_interval() -> u16 {
+    DEFAULT_CLICK_INTERVAL_MS
+}
+
+fn default_turbo_rate() -> u16 {
+    DEFAULT_TURBO_HZ
+}
+
+impl InputMessage {
+    /// 消息类型名称（与 JSON 的 type 字段一致），用于日志过滤
+    fn kind(&self) -> &'static str {
+        match self {
+            InputMessage::Joystick { .. } => "joystick",
+            InputMessage::Button { .. } => "button",
+            InputMessage::SkillStart { .. } => "skill_start",
+            InputMessage::SkillDrag { .. } => "skill_drag",
+            InputMessage::SkillRelease { .. } => "skill_release",
+            InputMessage::SkillCancel { .. } => "skill_cancel",
+            InputMessage::Ping { .. } => "ping",
+            InputMessage::Capabilities { .. } => "capabilities",
+            InputMessage::SetActive { .. } => "set_active",
+            InputMessage::Text { .. } => "text",
+            InputMessage::MousePos { .. } => "mouse_pos",
+            InputMessage::MouseMove { .. } => "mouse_move",
+            InputMessage::Auth { .. } => "auth",
+            InputMessage::Combo { .. } => "combo",
+            InputMessage::Scroll { .. } => "scroll",
+            InputMessage::SelectProfile { .. } => "select_profile",
+            InputMessage::Click { .. } => "click",
+            InputMessage::Drag { .. } => "drag",
+            InputMessage::Query => "query",
+            InputMessage::ReleaseAll => "release_all",
+            InputMessage::Turbo { .. } => "turbo",
+            InputMessage::Batch { .. } => "batch",
+        }
+    }
+
+    /// 是否为实际的输入消息（心跳、能力协商、暂停控制、认证不算）
+    fn is_input(&self) -> bool {
+        !matches!(
+            self,
+            InputMessage::Ping { .. }
+                | InputMessage::Capabilities { .. }
+                | InputMessage::SetActive { .. }
+                | InputMessage::Auth { .. }
+                | InputMessage::SelectProfile { .. }
+                | InputMessage::Query
+        )
+    }
+
+    /// 能否放进批量消息：批量内的事件直接分发，不会回复（ping、查询等）、不做认证，也不会 ACK 和去重
+    fn batchable(&self) -> bool {
+        let control = matches!(
+            self,
+            InputMessage::Batch { .. }
+                | InputMessage::Ping { .. }
+                | InputMessage::Capabilities { .. }
+                | InputMessage::Auth { .. }
+                | InputMessage::Query
+        );
+        !control && self.seq().is_none()
+    }
+
+    /// 可靠消息的序列号（需要 ACK 与去重）
+    fn seq(&self) -> Option<u32> {
+        match self {
+            InputMessage::Button { seq, .. } => *seq,
+            InputMessage::SkillRelease { seq, .. } => *seq,
+            InputMessage::SkillCancel { seq, .. } => *seq,
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct PongMessage {
     r#type: &'static str,
     timestamp: u64,
+    /// 服务端收到 Ping 时的时间（Unix 毫秒），客户端结合时钟偏移可自行计算单向延迟
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_time: Option<u64>,
+    /// 单向延迟估算值（毫秒），依赖客户端提供的时钟偏移，时钟漂移时可能不准
+    #[serde(skip_serializing_if = "Option::is_none")]
+    one_way_delay_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct AuthResultMessage {
+    r#type: &'static str,
+    ok: bool,
+}
+
+/// 构建认证结果响应（二进制: [magic][type][ok:u8]，魔数与客户端的字节序一致）
+fn build_auth_result(ok: bool, order: Option<ByteOrder>) -> Vec<u8> {
+    if let Some(order) = order {
+        binary_protocol::seal(vec![order.magic(), binary_protocol::MSG_AUTH_RESULT, ok as u8])
+    } else {
+        serde_json::to_vec(&AuthResultMessage { r#type: "auth_result", ok }).unwrap_or_default()
+    }
+}
+
+/// 校验 PIN；未启用认证时总是通过
+fn check_pin(expected: Option<&str>, pin: &str) -> bool {
+    expected.is_none_or(|expected| expected == pin.trim())
+}
+
+/// 单个来源 IP 的 PIN 错误记录
+#[derive(Debug, Default)]
+struct AuthFailures {
+    count: u32,  // 本轮连续错误次数
+    lockouts: u32,  // 已被锁定的次数，决定下次锁定时长
+    locked_until: Option<Instant>,
+}
+
+/// 按来源 IP 记录 PIN 错误，不随会话/连接重建而清零，UDP 与 TCP 共用，防止重连后继续暴力猜测
+struct AuthGuard {
+    failures: BTreeMap<IpAddr, AuthFailures>,
+}
+
+impl AuthGuard {
+    const fn new() -> Self {
+        Self { failures: BTreeMap::new() }
+    }
+
+    /// 来源 IP 仍处于锁定期时返回剩余时长
+    fn locked(&self, ip: IpAddr, now: Instant) -> Option<std::time::Duration> {
+        let until = self.failures.get(&ip)?.locked_until?;
+        (until > now).then(|| until - now)
+    }
+
+    /// 记录一次 PIN 错误，返回本轮错误次数；达到上限时开始锁定并返回锁定时长（逐次翻倍）
+    fn fail(&mut self, ip: IpAddr, now: Instant) -> (u32, Option<std::time::Duration>) {
+        if self.failures.len() >= MAX_AUTH_TRACKED && !self.failures.contains_key(&ip) {
+            // 记录过多时只保留仍在锁定期的来源
+            self.failures.retain(|_, f| f.locked_until.is_some_and(|until| until > now));
+        }
+        let record = self.failures.entry(ip).or_default();
+        record.count += 1;
+        let count = record.count;
+        if count < MAX_AUTH_ATTEMPTS {
+            return (count, None);
+        }
+        let secs = AUTH_LOCKOUT_SECS
+            .saturating_mul(1 << record.lockouts.min(16))
+            .min(MAX_AUTH_LOCKOUT_SECS);
+        let lockout = std::time::Duration::from_secs(secs);
+        record.count = 0;
+        record.lockouts += 1;
+        record.locked_until = Some(now + lockout);
+        (count, Some(lockout))
+    }
+
+    /// 认证成功后清除该来源的错误记录
+    fn succeed(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+}
+
+static AUTH_GUARD: Mutex<AuthGuard> = Mutex::new(AuthGuard::new());
+
+/// 获取全局 PIN 错误记录
+fn auth_guard() -> MutexGuard<'static, AuthGuard> {
+    AUTH_GUARD.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 校验一次认证请求并更新该来源 IP 的错误记录；锁定期间不校验，直接返回 None
+fn authenticate(expected: Option<&str>, pin: &str, peer: SocketAddr) -> Option<bool> {
+    let now = Instant::now();
+    let mut guard = auth_guard();
+    if guard.locked(peer.ip(), now).is_some() {
+        return None;
+    }
+    if check_pin(expected, pin) {
+        guard.succeed(peer.ip());
+        return Some(true);
+    }
+    match guard.fail(peer.ip(), now) {
+        (count, None) => warn!("[认证] 客户端 {} PIN 错误（{}/{}）", peer, count, MAX_AUTH_ATTEMPTS),
+        (_, Some(lockout)) => warn!(
+            "[认证] 来源 {} PIN 连续错误 {} 次，锁定 {} 秒",
+            peer.ip(),
+            MAX_AUTH_ATTEMPTS,
+            lockout.as_secs()
+        ),
+    }
+    Some(false)
 }
 
 #[derive(Debug, Serialize)]
@@ -162,29 +675,393 @@ struct AckMessage {
     seq: u32,
 }
 
-const SMOOTH_FACTOR: f32 = 0.4;  // 服务端平滑系数
+const STATS_REPORT_INTERVAL_SECS: u64 = 60;  // 连接统计（可靠消息、心跳抖动）的输出间隔
+
+/// 去重缓存保留的序列号数量
+const MAX_PROCESSED_SEQS: usize = 100;
+
+/// 可靠消息去重：VecDeque 记录淘汰顺序，HashSet 用于 O(1) 查重，两者始终同步
+#[derive(Default)]
+struct SeqDedup {
+    order: VecDeque<u32>,
+    seen: HashSet<u32>,
+}
+
+impl SeqDedup {
+    /// 记录序列号，已处理过时返回 false
+    fn insert(&mut self, seq: u32) -> bool {
+        if !self.seen.insert(seq) {
+            return false;
+        }
+        self.order.push_back(seq);
+        if self.order.len() > MAX_PROCESSED_SEQS {
+            if let Some(evicted) = self.order.pop_front() {
+                self.seen.remove(&evicted);
+            }
+        }
+        true
+    }
+}
+
+/// 单个客户端按住的按键和连发，客户端断开时只释放它自己按下的输入
+#[derive(Default)]
+struct ClientKeys {
+    keys: HashSet<String>,
+    turbo: HashSet<String>,
+}
+
+impl ClientKeys {
+    /// 根据即将分发的消息更新记录
+    fn track(&mut self, msg: &InputMessage) {
+        match msg {
+            InputMessage::Button { key, pressed: true, .. } => {
+                self.keys.insert(key.clone());
+            }
+            InputMessage::Button { key, pressed: false, .. } => {
+                self.keys.remove(key);
+            }
+            InputMessage::Turbo { key, pressed: true, .. } => {
+                self.turbo.insert(key.clone());
+            }
+            InputMessage::Turbo { key, pressed: false, .. } => {
+                self.turbo.remove(key);
+            }
+            InputMessage::ReleaseAll => {
+                self.keys.clear();
+                self.turbo.clear();
+            }
+            InputMessage::Batch { events } => events.iter().for_each(|event| self.track(event)),
+            _ => {}
+        }
+    }
+}
+
+/// 单个客户端的令牌桶限流：容量为一秒的配额，非输入消息（心跳等）只能使用前一半，
+/// 过载时优先丢弃非输入消息，保证按键、摇杆等输入仍能处理
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+    limiting: bool,  // 当前是否处于限流状态，只在进入/退出时记录日志
+    dropped: u64,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+            limiting: false,
+            dropped: 0,
+        }
+    }
+
+    /// 尝试消耗一个令牌，返回是否允许处理（rate 为 0 时不限制）
+    fn allow(&mut self, is_input: bool, addr: &SocketAddr) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+
+        let reserve = if is_input { 1.0 } else { self.rate / 2.0 };
+        if self.tokens >= reserve {
+            self.tokens -= 1.0;
+            if self.limiting && self.tokens >= self.rate / 2.0 {
+                info!("[限流] 客户端 {} 恢复正常，期间丢弃 {} 个数据包", addr, self.dropped);
+                self.limiting = false;
+                self.dropped = 0;
+            }
+            return true;
+        }
+        if !self.limiting {
+            warn!("[限流] 客户端 {} 发送过快（超过 {:.0} 包/秒），开始丢弃数据包", addr, self.rate);
+            self.limiting = true;
+        }
+        self.dropped += 1;
+        METRICS.packets_rate_limited.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+}
+
+/// 按来源 IP 限流：同一主机换端口发送不会得到新的配额，也不会为每个端口各建一个限流器
+struct IpRateLimits {
+    rate: u32,
+    limiters: HashMap<IpAddr, RateLimiter>,
+}
+
+impl IpRateLimits {
+    fn new(rate: u32) -> Self {
+        Self { rate, limiters: HashMap::new() }
+    }
+
+    /// 按来源 IP 消耗令牌；记录的 IP 数达到上限且都在活跃时，新来源的数据包直接丢弃
+    fn allow(&mut self, is_input: bool, addr: &SocketAddr) -> bool {
+        let ip = addr.ip();
+        if !self.limiters.contains_key(&ip) && self.limiters.len() >= MAX_RATE_LIMITED_IPS {
+            // 超过一秒没有收包的来源配额已经回满，与新建的限流器等价，可以丢弃
+            self.limiters.retain(|_, limiter| limiter.last_refill.elapsed() < std::time::Duration::from_secs(1));
+            if self.limiters.len() >= MAX_RATE_LIMITED_IPS {
+                METRICS.packets_rate_limited.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        let rate = self.rate;
+        self.limiters.entry(ip).or_insert_with(|| RateLimiter::new(rate)).allow(is_input, addr)
+    }
+}
+
+/// 可靠消息的送达统计：重复数反映客户端因丢 ACK 而重传的次数
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReliableStats {
+    pub received: u64,    // 收到的可靠消息总数（含重传）
+    pub duplicates: u64,  // 去重丢弃的重传
+    pub processed: u64,   // 实际处理的消息
+}
+
+impl ReliableStats {
+    fn record(&mut self, duplicate: bool) {
+        self.received += 1;
+        if duplicate {
+            self.duplicates += 1;
+        } else {
+            self.processed += 1;
+        }
+    }
+
+    fn summary(&self) -> String {
+        let ratio = if self.received > 0 { self.duplicates as f64 * 100.0 / self.received as f64 } else { 0.0 };
+        format!(
+            "收到 {} 条，重传 {} 条（{:.1}%），处理 {} 条",
+            self.received, self.duplicates, ratio, self.processed
+        )
+    }
+}
+
+/// 心跳间隔统计：服务端无法得知客户端时钟，只能根据到达间隔判断网络是否稳定
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PingStats {
+    pub count: u64,
+    last_arrival: Option<Instant>,
+    last_interval_ms: Option<f64>,
+    /// 平均到达间隔（毫秒，指数滑动平均）
+    pub mean_interval_ms: f64,
+    /// 到达间隔抖动（毫秒，按 RFC 3550 的方式平滑）
+    pub jitter_ms: f64,
+}
+
+impl PingStats {
+    fn record(&mut self, now: Instant) {
+        self.count += 1;
+        if let Some(last) = self.last_arrival {
+            let interval = now.duration_since(last).as_secs_f64() * 1000.0;
+            match self.last_interval_ms {
+                Some(prev) => {
+                    self.mean_interval_ms += (interval - self.mean_interval_ms) / 16.0;
+                    self.jitter_ms += ((interval - prev).abs() - self.jitter_ms) / 16.0;
+                }
+                None => self.mean_interval_ms = interval,
+            }
+            self.last_interval_ms = Some(interval);
+        }
+        self.last_arrival = Some(now);
+    }
+
+    fn summary(&self) -> String {
+        let rate = if self.mean_interval_ms > 0.0 { 1000.0 / self.mean_interval_ms } else { 0.0 };
+        format!(
+            "心跳 {} 次，平均间隔 {:.0}ms（{:.1} 次/秒），抖动 {:.1}ms",
+            self.count, self.mean_interval_ms, rate, self.jitter_ms
+        )
+    }
+}
+
+/// 单个客户端的会话状态
+struct ClientSession {
+    created: Instant,
+    authenticated: bool,  // 未启用 PIN 时直接视为已认证
+    last_heartbeat: Instant,
+    last_input: Instant,  // 最近一次实际输入（不含心跳）
+    byte_order: Option<ByteOrder>,  // 极限模式（二进制协议）下客户端使用的字节序，None 表示 JSON
+    clock_offset_ms: Option<i64>,  // 客户端上报的时钟偏移，用于估算单向延迟
+    processed_seqs: SeqDedup,  // 可靠消息去重
+    reliable_stats: ReliableStats,
+    ping_stats: PingStats,
+    stats_reported: Instant,  // 上次输出连接统计的时间
+    last_keepalive: Instant,  // 上次主动发送保活包的时间
+    held: ClientKeys,  // 该客户端按住的按键，断开时释放
+}
+
+impl ClientSession {
+    fn new(authenticated: bool) -> Self {
+        Self {
+            created: Instant::now(),
+            authenticated,
+            last_heartbeat: Instant::now(),
+            last_input: Instant::now(),
+            byte_order: None,
+            clock_offset_ms: None,
+            processed_seqs: SeqDedup::default(),
+            reliable_stats: ReliableStats::default(),
+            ping_stats: PingStats::default(),
+            stats_reported: Instant::now(),
+            last_keepalive: Instant::now(),
+            held: ClientKeys::default(),
+        }
+    }
+
+    /// 输出连接统计（没有数据的项不输出）
+    fn report_stats(&self, addr: &SocketAddr) {
+        if self.reliable_stats.received > 0 {
+            info!("[可靠消息] 客户端 {} {}", addr, self.reliable_stats.summary());
+        }
+        if self.ping_stats.count > 1 {
+            info!("[心跳] 客户端 {} {}", addr, self.ping_stats.summary());
+        }
+    }
+}
+
+/// 能力协商响应：告知客户端可用的 TCP 可靠通道
+#[derive(Debug, Serialize)]
+struct CapabilitiesMessage {
+    r#type: &'static str,
+    /// 传输方式：udp / tcp / both
+    transport: &'static str,
+    tcp_port: Option<u16>,
+    tcp_messages: &'static [&'static str],
+    /// 服务端当前时间（Unix 毫秒），供客户端计算时钟偏移
+    server_time: u64,
+    /// 二进制消息是否需要附带 CRC8 校验和
+    binary_crc: bool,
+}
+
+/// 状态查询响应：服务端当前认为按住的输入
+#[derive(Debug, Serialize)]
+struct StatusMessage {
+    r#type: &'static str,
+    pressed_keys: Vec<String>,
+    pressed_modifiers: Modifiers,
+    active_skill: Option<String>,
+    skill_center: Option<(i32, i32)>,
+}
+
+#[derive(Debug, Serialize)]
+struct KeepaliveMessage {
+    r#type: &'static str,
+    server_time: u64,
+}
+
+/// 构建服务端保活包（二进制: [magic][type]，魔数与客户端最近使用的字节序一致）
+fn build_keepalive(order: Option<ByteOrder>) -> Vec<u8> {
+    if let Some(order) = order {
+        binary_protocol::seal(vec![order.magic(), binary_protocol::MSG_KEEPALIVE])
+    } else {
+        let msg = KeepaliveMessage { r#type: "keepalive", server_time: unix_millis() };
+        serde_json::to_vec(&msg).unwrap_or_default()
+    }
+}
+
+/// 手柄震动事件（服务端推送），客户端据此振动
+#[derive(Debug, Serialize)]
+struct RumbleMessage {
+    r#type: &'static str,
+    /// 强度 0~255，0 表示停止震动
+    intensity: u8,
+    duration_ms: u16,
+}
+
+/// 构建手柄震动事件（二进制: [magic][type][intensity:u8][duration_ms:u16]，按客户端的字节序编码）
+fn build_rumble(rumble: gamepad::Rumble, order: Option<ByteOrder>) -> Vec<u8> {
+    if let Some(order) = order {
+        let mut buf = vec![order.magic(), binary_protocol::MSG_RUMBLE, rumble.intensity()];
+        order.put_u16(&mut buf, rumble.duration_ms);
+        binary_protocol::seal(buf)
+    } else {
+        let msg = RumbleMessage { r#type: "rumble", intensity: rumble.intensity(), duration_ms: rumble.duration_ms };
+        serde_json::to_vec(&msg).unwrap_or_default()
+    }
+}
+
+/// 取出本次收到的数据报：buf 跨包复用，只返回 buf[..len]，不会读到上一个更长数据包残留的字节；
+/// 缓冲区比上限多留 1 字节，填满缓冲区说明数据报已被截断，返回 None，丢弃而不是解析半条消息
+fn received_packet(buf: &[u8], len: usize) -> Option<&[u8]> {
+    (len < buf.len()).then(|| &buf[..len])
+}
+
+/// 每个来源只提示一次；记录满 MAX_LOGGED_SOURCES 个后清空重新开始，
+/// 伪造大量来源地址时占用的内存有上限，警告最多按该数量重复输出
+fn first_report<T: std::hash::Hash + Eq>(seen: &mut HashSet<T>, source: T) -> bool {
+    if seen.len() >= MAX_LOGGED_SOURCES && !seen.contains(&source) {
+        seen.clear();
+    }
+    seen.insert(source)
+}
+
+/// 发送一个 UDP 数据包（启用加密时先加密）
+fn send_packet(socket: &UdpSocket, data: &[u8], addr: SocketAddr) {
+    let _ = socket.send_to(&crypto::seal(data), addr);
+}
+
+/// 构建能力协商响应（JSON）
+fn build_capabilities(config: &Config) -> Vec<u8> {
+    let caps = CapabilitiesMessage {
+        r#type: "capabilities",
+        transport: config.transport.name(),
+        tcp_port: config.tcp_port,
+        tcp_messages: tcp::RELIABLE_KINDS,
+        server_time: unix_millis(),
+        binary_crc: config.binary_crc,
+    };
+    serde_json::to_vec(&caps).unwrap_or_default()
+}
+
+/// 当前 Unix 时间（毫秒）
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 根据 Ping 时间戳（客户端时钟，Unix 毫秒）和时钟偏移估算单向（上行）延迟
+fn estimate_one_way_delay(timestamp: u64, clock_offset_ms: i64) -> i64 {
+    unix_millis() as i64 - (timestamp as i64 + clock_offset_ms)
+}
+
+const SMOOTH_FACTOR: f32 = 0.4;  // 默认服务端平滑系数，可用 --smooth-factor 覆盖
+/// 缓出曲线的最小系数占比：接近目标时系数不低于 smooth_factor 的这一比例，避免迟迟到不了目标
+const EASE_OUT_MIN_RATIO: f32 = 0.25;
 
 // 极限模式：解析二进制消息，返回 (消息, 可选的序列号用于ACK)
 fn parse_binary_message(buf: &[u8]) -> Option<(InputMessage, Option<u32>)> {
-    if buf.len() < 2 || buf[0] != binary_protocol::MAGIC {
+    let order = ByteOrder::of(buf)?;
+    let stripped = binary_protocol::strip_version(buf)?;
+    let buf: &[u8] = &stripped;
+    if buf.len() < 2 {
         return None;
     }
     
     match buf[1] {
         binary_protocol::MSG_JOYSTICK if buf.len() >= 10 => {
-            let x = f32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
-            let y = f32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]);
+            let x = order.f32(buf, 2);
+            let y = order.f32(buf, 6);
             Some((InputMessage::Joystick { x, y }, None))
         }
         binary_protocol::MSG_BUTTON if buf.len() >= 4 => {
-            // 新格式: [magic][type][key_len][key...][pressed][modifiers]
-            let key_len = buf[2] as usize;
-            if buf.len() < 4 + key_len + 2 {
-                // 兼容旧格式: [magic][type][key:u8][pressed:u8]
+            // 兼容旧格式: [magic][type][key:u8][pressed:u8]
+            if buf.len() == 4 {
                 let key = (buf[2] as char).to_string();
                 let pressed = buf[3] != 0;
                 return Some((InputMessage::Button { key, pressed, modifiers: None, seq: None }, None));
             }
+            // 新格式: [magic][type][key_len][key...][pressed][modifiers]
+            let key_len = buf[2] as usize;
+            if buf.len() != 5 + key_len {
+                return None;
+            }
             let key = String::from_utf8_lossy(&buf[3..3+key_len]).to_string();
             let pressed = buf[3 + key_len] != 0;
             let modifiers = Modifiers::from_byte(buf[4 + key_len]);
@@ -192,10 +1069,10 @@ fn parse_binary_message(buf: &[u8]) -> Option<(InputMessage, Option<u32>)> {
         }
         // 可靠按键消息: [magic:1][type:1][seq:4][key_len:1][key:N][pressed:1][modifiers:1] = 9 + N bytes
         binary_protocol::MSG_RELIABLE_BUTTON if buf.len() >= 9 => {
-            let seq = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+            let seq = order.u32(buf, 2);
             let key_len = buf[6] as usize;
             // 需要 7 + key_len + 2 = 9 + key_len 字节
-            if buf.len() < 9 + key_len {
+            if buf.len() != 9 + key_len {
                 return None;
             }
             let key = String::from_utf8_lossy(&buf[7..7+key_len]).to_string();
@@ -209,75 +1086,211 @@ fn parse_binary_message(buf: &[u8]) -> Option<(InputMessage, Option<u32>)> {
             }, Some(seq)))
         }
         binary_protocol::MSG_SKILL_START if buf.len() >= 3 => {
-            // 新格式: [magic][type][key_len][key...][modifiers]
-            let key_len = buf[2] as usize;
-            if buf.len() < 3 + key_len + 1 {
-                // 兼容旧格式
+            // 兼容旧格式: [magic][type][key:u8]
+            if buf.len() == 3 {
                 let key = (buf[2] as char).to_string();
-                return Some((InputMessage::SkillStart { key, offset_x: 0, offset_y: 0, modifiers: None }, None));
+                return Some((InputMessage::SkillStart { key, offset_x: 0, offset_y: 0, modifiers: None, confirm: None }, None));
             }
-            let key = String::from_utf8_lossy(&buf[3..3+key_len]).to_string();
+            // 新格式: [magic][type][key_len][key...][modifiers]
+            let key_len = buf[2] as usize;
+            if buf.len() != 4 + key_len {
+                return None;
+            }
+            let key = String::from_utf8_lossy(&buf[3..3+key_len]).to_string();
             let modifiers = Modifiers::from_byte(buf[3 + key_len]);
-            Some((InputMessage::SkillStart { key, offset_x: 0, offset_y: 0, modifiers: if modifiers.is_empty() { None } else { Some(modifiers) } }, None))
+            Some((InputMessage::SkillStart { key, offset_x: 0, offset_y: 0, modifiers: if modifiers.is_empty() { None } else { Some(modifiers) }, confirm: None }, None))
         }
+        // 新格式: [magic][type][key_len][key...][dx:f32][dy:f32][distance:f32][smooth:u8]
+        // 旧格式: [magic][type][key:u8][dx:f32][dy:f32][distance:f32][smooth:u8]?
         binary_protocol::MSG_SKILL_DRAG if buf.len() >= 15 => {
-            let key = (buf[2] as char).to_string();
-            let dx = f32::from_le_bytes([buf[3], buf[4], buf[5], buf[6]]);
-            let dy = f32::from_le_bytes([buf[7], buf[8], buf[9], buf[10]]);
-            let distance = f32::from_le_bytes([buf[11], buf[12], buf[13], buf[14]]);
-            let smooth = buf.get(15).map(|&b| b != 0).unwrap_or(true);
+            // smooth 字节可省略
+            let (key, at) = read_skill_key(buf, 2, 13).or_else(|| read_skill_key(buf, 2, 12))?;
+            let dx = order.f32(buf, at);
+            let dy = order.f32(buf, at + 4);
+            let distance = order.f32(buf, at + 8);
+            let smooth = buf.get(at + 12).map(|&b| b != 0).unwrap_or(true);
             Some((InputMessage::SkillDrag { key, dx, dy, distance, smooth }, None))
         }
+        // 新格式: [magic][type][key_len][key...][dx:f32][dy:f32]，旧格式 key 为单字节
         binary_protocol::MSG_SKILL_RELEASE if buf.len() >= 11 => {
-            let key = (buf[2] as char).to_string();
-            let dx = f32::from_le_bytes([buf[3], buf[4], buf[5], buf[6]]);
-            let dy = f32::from_le_bytes([buf[7], buf[8], buf[9], buf[10]]);
+            let (key, at) = read_skill_key(buf, 2, 8)?;
+            let dx = order.f32(buf, at);
+            let dy = order.f32(buf, at + 4);
             Some((InputMessage::SkillRelease { key, dx, dy, seq: None }, None))
         }
-        // 可靠技能释放: [magic][type][seq:u32][key:u8][dx:f32][dy:f32]
+        // 可靠技能释放: [magic][type][seq:u32][key_len][key...][dx:f32][dy:f32]，旧格式 key 为单字节
         binary_protocol::MSG_RELIABLE_SKILL_RELEASE if buf.len() >= 15 => {
-            let seq = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
-            let key = (buf[6] as char).to_string();
-            let dx = f32::from_le_bytes([buf[7], buf[8], buf[9], buf[10]]);
-            let dy = f32::from_le_bytes([buf[11], buf[12], buf[13], buf[14]]);
+            let seq = order.u32(buf, 2);
+            let (key, at) = read_skill_key(buf, 6, 8)?;
+            let dx = order.f32(buf, at);
+            let dy = order.f32(buf, at + 4);
             Some((InputMessage::SkillRelease { key, dx, dy, seq: Some(seq) }, Some(seq)))
         }
+        // 新格式: [magic][type][key_len][key...]，旧格式 key 为单字节
         binary_protocol::MSG_SKILL_CANCEL if buf.len() >= 3 => {
-            let key = (buf[2] as char).to_string();
+            let (key, _) = read_skill_key(buf, 2, 0)?;
             Some((InputMessage::SkillCancel { key, seq: None }, None))
         }
-        // 可靠技能取消: [magic][type][seq:u32][key:u8]
+        // 可靠技能取消: [magic][type][seq:u32][key_len][key...]，旧格式 key 为单字节
         binary_protocol::MSG_RELIABLE_SKILL_CANCEL if buf.len() >= 7 => {
-            let seq = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
-            let key = (buf[6] as char).to_string();
+            let seq = order.u32(buf, 2);
+            let (key, _) = read_skill_key(buf, 6, 0)?;
             Some((InputMessage::SkillCancel { key, seq: Some(seq) }, Some(seq)))
         }
+        // 认证: [magic][type][len:u8][pin...]
+        binary_protocol::MSG_AUTH if buf.len() >= 3 => {
+            let len = buf[2] as usize;
+            if buf.len() != 3 + len {
+                return None;
+            }
+            let pin = String::from_utf8_lossy(&buf[3..]).to_string();
+            Some((InputMessage::Auth { pin }, None))
+        }
+        // 相对移动: [magic][type][dx:f32][dy:f32]
+        binary_protocol::MSG_MOUSE_MOVE if buf.len() >= 10 => {
+            let dx = order.f32(buf, 2);
+            let dy = order.f32(buf, 6);
+            Some((InputMessage::MouseMove { dx, dy }, None))
+        }
+        // 文本输入: [magic][type][len:u16][utf8...]
+        binary_protocol::MSG_TEXT if buf.len() >= 4 => {
+            let len = order.u16(buf, 2) as usize;
+            if buf.len() != 4 + len {
+                return None;
+            }
+            let content = std::str::from_utf8(&buf[4..]).ok()?.to_string();
+            Some((InputMessage::Text { content }, None))
+        }
+        // 切换配置方案: [magic][type][len:u8][name...]
+        binary_protocol::MSG_SELECT_PROFILE if buf.len() >= 3 => {
+            let len = buf[2] as usize;
+            if buf.len() != 3 + len {
+                return None;
+            }
+            let name = std::str::from_utf8(&buf[3..]).ok()?.to_string();
+            Some((InputMessage::SelectProfile { name }, None))
+        }
+        // 滚动: [magic][type][axis:u8 0=垂直 1=水平][amount:i32]
+        binary_protocol::MSG_SCROLL if buf.len() >= 7 => {
+            let axis = match buf[2] {
+                0 => "vertical",
+                1 => "horizontal",
+                _ => return None,
+            };
+            let amount = order.u32(buf, 3) as i32;
+            Some((InputMessage::Scroll { axis: axis.to_string(), amount }, None))
+        }
+        // 多次点击: [magic][type][len:u8][button...][count:u8][interval_ms:u16]
+        binary_protocol::MSG_CLICK if buf.len() >= 3 => {
+            let len = buf[2] as usize;
+            if buf.len() != 6 + len {
+                return None;
+            }
+            let button = std::str::from_utf8(&buf[3..3 + len]).ok()?.to_string();
+            let at = 3 + len;
+            let interval_ms = order.u16(buf, at + 1);
+            Some((InputMessage::Click { button, count: buf[at], interval_ms }, None))
+        }
+        // 拖放: [magic][type][len:u8][button...][to_x:f32][to_y:f32][smooth:u8]
+        binary_protocol::MSG_DRAG if buf.len() >= 3 => {
+            let len = buf[2] as usize;
+            if buf.len() != 12 + len {
+                return None;
+            }
+            let button = std::str::from_utf8(&buf[3..3 + len]).ok()?.to_string();
+            let at = 3 + len;
+            let smooth = buf[at + 8] != 0;
+            Some((InputMessage::Drag { button, to_x: order.f32(buf, at), to_y: order.f32(buf, at + 4), smooth }, None))
+        }
+        // 组合键: [magic][type][count:u8]([key_len:u8][key...]) * count
+        binary_protocol::MSG_COMBO if buf.len() >= 3 => {
+            let mut keys = Vec::with_capacity(buf[2] as usize);
+            let mut at = 3;
+            for _ in 0..buf[2] {
+                let len = *buf.get(at)? as usize;
+                keys.push(String::from_utf8_lossy(buf.get(at + 1..at + 1 + len)?).to_string());
+                at += 1 + len;
+            }
+            if at != buf.len() {
+                return None;
+            }
+            Some((InputMessage::Combo { keys }, None))
+        }
+        // 连发: [magic][type][key_len:u8][key...][pressed:u8][rate_hz:u16]
+        binary_protocol::MSG_TURBO if buf.len() >= 3 => {
+            let len = buf[2] as usize;
+            if buf.len() != 6 + len {
+                return None;
+            }
+            let key = std::str::from_utf8(&buf[3..3 + len]).ok()?.to_string();
+            let at = 3 + len;
+            Some((InputMessage::Turbo { key, pressed: buf[at] != 0, rate_hz: order.u16(buf, at + 1) }, None))
+        }
+        // 全部释放: [magic][type]
+        binary_protocol::MSG_RELEASE_ALL if buf.len() == 2 => Some((InputMessage::ReleaseAll, None)),
+        // 暂停/恢复: [magic][type][active:u8]
+        binary_protocol::MSG_SET_ACTIVE if buf.len() >= 3 => {
+            Some((InputMessage::SetActive { active: buf[2] != 0 }, None))
+        }
         binary_protocol::MSG_PING if buf.len() >= 10 => {
-            let timestamp = u64::from_le_bytes([
-                buf[2], buf[3], buf[4], buf[5], buf[6], buf[7], buf[8], buf[9]
-            ]);
+            let timestamp = order.u64(buf, 2);
             Some((InputMessage::Ping { timestamp }, None))
         }
         _ => None,
     }
 }
 
-// 极限模式：构建二进制 pong 响应
-fn build_binary_pong(timestamp: u64) -> [u8; 10] {
-    let mut buf = [0u8; 10];
-    buf[0] = binary_protocol::MAGIC;
-    buf[1] = binary_protocol::MSG_PONG;
-    buf[2..10].copy_from_slice(&timestamp.to_le_bytes());
-    buf
+/// 读取技能键，返回 (key, 后续字段的起始位置)
+///
+/// 新格式为 `[key_len][key...]`，旧格式只有 1 字节 key。按总长度区分：
+/// 新格式的长度恰好为 `at + 1 + key_len + rest`（rest 为 key 之后固定字段的字节数），
+/// 否则长度恰为 `at + 1 + rest` 时按旧格式处理（旧格式的 key 是可打印字符，当作长度时远超实际包长），
+/// 两者都不符合视为畸形包，返回 None
+fn read_skill_key(buf: &[u8], at: usize, rest: usize) -> Option<(String, usize)> {
+    let key_len = *buf.get(at)? as usize;
+    let end = at + 1 + key_len;
+    if key_len > 0 && buf.len() == end + rest {
+        Some((String::from_utf8_lossy(&buf[at + 1..end]).to_string(), end))
+    } else if buf.len() == at + 1 + rest {
+        Some(((buf[at] as char).to_string(), at + 1))
+    } else {
+        None
+    }
+}
+
+/// 解析一条消息（自动识别二进制/JSON 协议），返回 (消息, 可选的序列号用于ACK)
+fn parse_message(buf: &[u8]) -> Option<(InputMessage, Option<u32>)> {
+    if ByteOrder::of(buf).is_some() {
+        parse_binary_message(binary_protocol::verify(buf)?)
+    } else {
+        let msg = serde_json::from_slice::<InputMessage>(buf).ok()?;
+        if let InputMessage::Batch { events } = &msg {
+            if events.len() > MAX_BATCH_EVENTS {
+                warn!("[批量] 批量消息超过 {} 个事件，已丢弃", MAX_BATCH_EVENTS);
+                return None;
+            }
+            if let Some(event) = events.iter().find(|e| !e.batchable()) {
+                warn!("[批量] 批量消息包含不能批量发送的 {} 消息（嵌套、控制消息或带序列号），已丢弃", event.kind());
+                return None;
+            }
+        }
+        let seq = msg.seq();
+        Some((msg, seq))
+    }
+}
+
+// 极限模式：构建二进制 pong 响应（与请求使用相同的字节序）
+fn build_binary_pong(timestamp: u64, order: ByteOrder) -> Vec<u8> {
+    let mut buf = vec![order.magic(), binary_protocol::MSG_PONG];
+    order.put_u64(&mut buf, timestamp);
+    binary_protocol::seal(buf)
 }
 
-// 极限模式：构建二进制 ACK 响应
-fn build_binary_ack(seq: u32) -> [u8; 6] {
-    let mut buf = [0u8; 6];
-    buf[0] = binary_protocol::MAGIC;
-    buf[1] = binary_protocol::MSG_ACK;
-    buf[2..6].copy_from_slice(&seq.to_le_bytes());
-    buf
+// 极限模式：构建二进制 ACK 响应（与请求使用相同的字节序）
+fn build_binary_ack(seq: u32, order: ByteOrder) -> Vec<u8> {
+    let mut buf = vec![order.magic(), binary_protocol::MSG_ACK];
+    order.put_u32(&mut buf, seq);
+    binary_protocol::seal(buf)
 }
 
 
@@ -292,14 +1305,97 @@ enum MouseAction {
     Forward,
     ScrollUp,
     ScrollDown,
+    ScrollLeft,
+    ScrollRight,
 }
 
-/// 解析结果：键盘按键或鼠标操作
+/// 解析结果：键盘按键、需要输入的字符或鼠标操作
 enum ParsedInput {
     Keyboard(Key),
+    /// 需要 Shift 才能打出的字符（大写字母、上档符号），按下时自动包裹 Shift，值为不带 Shift 的按键
+    Shifted(Key),
+    /// 键盘上无法直接按出的字符（非 ASCII），通过 enigo.text 直接输入
+    Text(char),
     Mouse(MouseAction),
 }
 
+/// 美式键盘布局中需要 Shift 的符号，及其对应的不带 Shift 的按键（按位置一一对应）
+const SHIFTED_SYMBOLS: &str = "~!@#$%^&*()_+{}|:\"<>?";
+const UNSHIFTED_SYMBOLS: &str = "`1234567890-=[]\\;',./";
+
+/// 需要 Shift 才能打出的字符，返回对应的不带 Shift 的字符
+fn unshifted_char(c: char) -> Option<char> {
+    if c.is_ascii_uppercase() {
+        return Some(c.to_ascii_lowercase());
+    }
+    let index = SHIFTED_SYMBOLS.chars().position(|s| s == c)?;
+    UNSHIFTED_SYMBOLS.chars().nth(index)
+}
+
+/// parse_key 接受的按键名称（按类别分组，每行为同一按键的别名），供 --list-keys 输出；
+/// 输出时逐个用 parse_key 校验，当前平台不可用或已失效的名称会被标出
+const KEY_NAMES: &[(&str, &[&[&str]])] = &[
+    ("鼠标", &[
+        &["mouse_left"], &["mouse_right"], &["mouse_middle"], &["mouse_back"], &["mouse_forward"],
+        &["scroll_up"], &["scroll_down"], &["scroll_left"], &["scroll_right"],
+    ]),
+    ("修饰键", &[
+        &["shift"], &["ctrl", "control"], &["alt"], &["cmd", "meta", "win"],
+        &["lshift"], &["lctrl", "lcontrol"], &["lalt"], &["lcmd", "lmeta", "lwin"],
+        &["rshift"], &["rctrl", "rcontrol"], &["ralt"], &["rcmd", "rmeta", "rwin"],
+    ]),
+    ("常用键", &[
+        &["space"], &["enter", "return"], &["tab"], &["escape", "esc"], &["backspace"], &["delete"],
+        &["capslock"],
+    ]),
+    ("方向与导航", &[
+        &["up"], &["down"], &["left"], &["right"], &["home"], &["end"], &["pageup"], &["pagedown"],
+    ]),
+    ("功能键", &[
+        &["f1"], &["f2"], &["f3"], &["f4"], &["f5"], &["f6"], &["f7"], &["f8"], &["f9"], &["f10"],
+        &["f11"], &["f12"], &["f13"], &["f14"], &["f15"], &["f16"], &["f17"], &["f18"], &["f19"],
+        &["f20"], &["f21"], &["f22"], &["f23"], &["f24"],
+    ]),
+    ("小键盘", &[
+        &["num0", "numpad0"], &["num1", "numpad1"], &["num2", "numpad2"], &["num3", "numpad3"],
+        &["num4", "numpad4"], &["num5", "numpad5"], &["num6", "numpad6"], &["num7", "numpad7"],
+        &["num8", "numpad8"], &["num9", "numpad9"], &["numadd", "numplus"], &["numsub", "numminus"],
+        &["nummul", "nummultiply"], &["numdiv", "numdivide"], &["numdec", "numdecimal"], &["numenter"],
+        &["numlock"],
+    ]),
+    ("特殊功能键", &[
+        &["menu", "apps", "contextmenu"], &["printscreen", "prtsc", "prtscr"], &["scrolllock"],
+        &["pause", "break"], &["insert", "ins"],
+    ]),
+    ("媒体键", &[
+        &["volumeup"], &["volumedown"], &["mute"], &["playpause"], &["nexttrack"], &["prevtrack"],
+        &["stop"],
+    ]),
+    ("虚拟手柄（--joystick-mode gamepad）", &[
+        &["pad_a"], &["pad_b"], &["pad_x"], &["pad_y"], &["pad_lb"], &["pad_rb"],
+        &["pad_back", "pad_select"], &["pad_start"], &["pad_guide", "pad_home"], &["pad_ls"], &["pad_rs"],
+        &["pad_up"], &["pad_down"], &["pad_left"], &["pad_right"],
+    ]),
+];
+
+/// 输出所有可用的按键名称（--list-keys）；输出被管道截断（如 `| head`）时静默结束
+fn print_key_list() {
+    use std::io::Write;
+    let mut out = std::io::stdout().lock();
+    for (category, groups) in KEY_NAMES {
+        let _ = writeln!(out, "{}:", category);
+        for aliases in groups.iter() {
+            let unavailable = aliases
+                .iter()
+                .any(|name| parse_key(name).is_none() && GamepadButton::parse(name).is_none());
+            let note = if unavailable { "  （当前平台不可用）" } else { "" };
+            let _ = writeln!(out, "  {}{}", aliases.join(" / "), note);
+        }
+    }
+    let _ = writeln!(out, "单个字符:");
+    let _ = writeln!(out, "  a-z、0-9 及标点符号；需要 Shift 的字符（如 A、!）自动加 Shift，非 ASCII 字符按文本输入");
+}
+
 /// 解析按键字符串
 fn parse_key(key_str: &str) -> Option<ParsedInput> {
     let key_lower = key_str.to_lowercase();
@@ -312,6 +1408,8 @@ fn parse_key(key_str: &str) -> Option<ParsedInput> {
         "mouse_forward" => Some(ParsedInput::Mouse(MouseAction::Forward)),
         "scroll_up" => Some(ParsedInput::Mouse(MouseAction::ScrollUp)),
         "scroll_down" => Some(ParsedInput::Mouse(MouseAction::ScrollDown)),
+        "scroll_left" => Some(ParsedInput::Mouse(MouseAction::ScrollLeft)),
+        "scroll_right" => Some(ParsedInput::Mouse(MouseAction::ScrollRight)),
         // 修饰键（左）
         "lshift" => Some(ParsedInput::Keyboard(Key::LShift)),
         "lctrl" | "lcontrol" => Some(ParsedInput::Keyboard(Key::LControl)),
@@ -358,6 +1456,19 @@ fn parse_key(key_str: &str) -> Option<ParsedInput> {
         "f10" => Some(ParsedInput::Keyboard(Key::F10)),
         "f11" => Some(ParsedInput::Keyboard(Key::F11)),
         "f12" => Some(ParsedInput::Keyboard(Key::F12)),
+        "f13" => Some(ParsedInput::Keyboard(Key::F13)),
+        "f14" => Some(ParsedInput::Keyboard(Key::F14)),
+        "f15" => Some(ParsedInput::Keyboard(Key::F15)),
+        "f16" => Some(ParsedInput::Keyboard(Key::F16)),
+        "f17" => Some(ParsedInput::Keyboard(Key::F17)),
+        "f18" => Some(ParsedInput::Keyboard(Key::F18)),
+        "f19" => Some(ParsedInput::Keyboard(Key::F19)),
+        "f20" => Some(ParsedInput::Keyboard(Key::F20)),
+        // F21–F24 在 macOS 上不可用
+        "f21" => special_key("f21").map(ParsedInput::Keyboard),
+        "f22" => special_key("f22").map(ParsedInput::Keyboard),
+        "f23" => special_key("f23").map(ParsedInput::Keyboard),
+        "f24" => special_key("f24").map(ParsedInput::Keyboard),
         // 小键盘数字
         "num0" | "numpad0" => Some(ParsedInput::Keyboard(Key::Numpad0)),
         "num1" | "numpad1" => Some(ParsedInput::Keyboard(Key::Numpad1)),
@@ -376,10 +1487,65 @@ fn parse_key(key_str: &str) -> Option<ParsedInput> {
         "numdiv" | "numdivide" => Some(ParsedInput::Keyboard(Key::Divide)),
         "numdec" | "numdecimal" => Some(ParsedInput::Keyboard(Key::Decimal)),
         "numenter" => Some(ParsedInput::Keyboard(Key::Return)),  // 小键盘回车映射到普通回车
-        // 单字符按键
-        s if s.len() == 1 => {
-            s.chars().next().map(|c| ParsedInput::Keyboard(Key::Unicode(c.to_ascii_lowercase())))
+        // 特殊功能键（当前平台没有对应按键时返回 None）
+        "menu" | "apps" | "contextmenu" => special_key("menu").map(ParsedInput::Keyboard),
+        "printscreen" | "prtsc" | "prtscr" => special_key("printscreen").map(ParsedInput::Keyboard),
+        "scrolllock" => special_key("scrolllock").map(ParsedInput::Keyboard),
+        "pause" | "break" => special_key("pause").map(ParsedInput::Keyboard),
+        "insert" | "ins" => special_key("insert").map(ParsedInput::Keyboard),
+        "numlock" => special_key("numlock").map(ParsedInput::Keyboard),
+        // 媒体键
+        "volumeup" => Some(ParsedInput::Keyboard(Key::VolumeUp)),
+        "volumedown" => Some(ParsedInput::Keyboard(Key::VolumeDown)),
+        "mute" => Some(ParsedInput::Keyboard(Key::VolumeMute)),
+        "playpause" => Some(ParsedInput::Keyboard(Key::MediaPlayPause)),
+        "nexttrack" => Some(ParsedInput::Keyboard(Key::MediaNextTrack)),
+        "prevtrack" => Some(ParsedInput::Keyboard(Key::MediaPrevTrack)),
+        "stop" => special_key("stop").map(ParsedInput::Keyboard),
+        // 单字符按键：保留原始大小写，需要 Shift 的字符自动加 Shift，非 ASCII 字符走文本输入
+        _ => {
+            let mut chars = key_str.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_ascii() => Some(ParsedInput::Text(c)),
+                (Some(c), None) => match unshifted_char(c) {
+                    Some(base) => Some(ParsedInput::Shifted(Key::Unicode(base))),
+                    None => Some(ParsedInput::Keyboard(Key::Unicode(c))),
+                },
+                _ => None,
+            }
         }
+    }
+}
+
+/// 平台相关的特殊功能键，enigo 在 macOS 上没有这些键
+fn special_key(name: &str) -> Option<Key> {
+    match name {
+        #[cfg(target_os = "windows")]
+        "menu" => Some(Key::Apps),
+        #[cfg(all(unix, not(target_os = "macos")))]
+        "menu" => Some(Key::LMenu),  // X11 下映射到 Menu keysym
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "printscreen" => Some(Key::PrintScr),
+        #[cfg(target_os = "windows")]
+        "scrolllock" => Some(Key::Scroll),
+        #[cfg(all(unix, not(target_os = "macos")))]
+        "scrolllock" => Some(Key::ScrollLock),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "pause" => Some(Key::Pause),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "insert" => Some(Key::Insert),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "numlock" => Some(Key::Numlock),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "stop" => Some(Key::MediaStop),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "f21" => Some(Key::F21),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "f22" => Some(Key::F22),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "f23" => Some(Key::F23),
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        "f24" => Some(Key::F24),
         _ => None,
     }
 }
@@ -392,56 +1558,354 @@ fn mouse_action_to_button(action: MouseAction) -> Option<Button> {
         MouseAction::Middle => Some(Button::Middle),
         MouseAction::Back => Some(Button::Back),
         MouseAction::Forward => Some(Button::Forward),
-        MouseAction::ScrollUp | MouseAction::ScrollDown => None, // 滚轮不是按钮
+        MouseAction::ScrollUp
+        | MouseAction::ScrollDown
+        | MouseAction::ScrollLeft
+        | MouseAction::ScrollRight => None, // 滚轮不是按钮
+    }
+}
+
+/// 将滚轮动作转换为 (方向, 滚动轴)，非滚轮返回 None
+fn mouse_action_to_scroll(action: MouseAction) -> Option<(i32, enigo::Axis)> {
+    match action {
+        MouseAction::ScrollUp => Some((1, enigo::Axis::Vertical)),
+        MouseAction::ScrollDown => Some((-1, enigo::Axis::Vertical)),
+        MouseAction::ScrollLeft => Some((-1, enigo::Axis::Horizontal)),
+        MouseAction::ScrollRight => Some((1, enigo::Axis::Horizontal)),
+        _ => None,
+    }
+}
+
+/// 按滞回阈值计算单轴方向（-1/0/1）：未激活时需超过按下阈值，已激活时回落到松开阈值以下才归零
+fn axis_direction(value: f32, prev: i8, enter: f32, exit: f32) -> i8 {
+    if prev != 0 && value * prev as f32 > exit {
+        return prev;
+    }
+    if value > enter {
+        1
+    } else if value < -enter {
+        -1
+    } else {
+        0
+    }
+}
+
+/// 按角度把摇杆偏移归入 8 个 45° 扇区，返回横纵方向（y 轴负方向为上）
+fn sector_direction(x: f32, y: f32) -> (i8, i8) {
+    const SECTORS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+    let sector = (y.atan2(x) / std::f32::consts::FRAC_PI_4).round() as i32;
+    SECTORS[sector.rem_euclid(8) as usize]
+}
+
+/// 根据横纵方向得到 8 方向索引（顺序同 config::DIRECTION_NAMES，y 轴负方向为上）
+fn direction_index(h: i8, v: i8) -> Option<usize> {
+    match (h, v) {
+        (0, -1) => Some(0),
+        (1, -1) => Some(1),
+        (1, 0) => Some(2),
+        (1, 1) => Some(3),
+        (0, 1) => Some(4),
+        (-1, 1) => Some(5),
+        (-1, 0) => Some(6),
+        (-1, -1) => Some(7),
+        _ => None,
+    }
+}
+
+/// 释放一个按键或鼠标按钮（滚轮、文本字符无需释放）
+fn release_key(enigo: &mut dyn InputBackend, key: &str) {
+    match parse_key(key) {
+        Some(ParsedInput::Keyboard(enigo_key)) => {
+            let _ = enigo.key(enigo_key, enigo::Direction::Release);
+        }
+        Some(ParsedInput::Shifted(enigo_key)) => {
+            let _ = enigo.key(enigo_key, enigo::Direction::Release);
+            let _ = enigo.key(Key::Shift, enigo::Direction::Release);
+        }
+        Some(ParsedInput::Mouse(action)) => {
+            if let Some(btn) = mouse_action_to_button(action) {
+                let _ = enigo.button(btn, enigo::Direction::Release);
+            }
+        }
+        Some(ParsedInput::Text(_)) | None => {}
+    }
+}
+
+/// 当前按住的按键镜像
+///
+/// 崩溃时主状态可能正被 panic 的线程锁住，因此单独保存一份供 panic 钩子读取
+static HELD_MIRROR: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// 安装 panic 钩子：崩溃前尽力释放所有按键和修饰键，避免键盘卡键
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // 钩子内不能阻塞等待锁，拿不到镜像时只释放修饰键
+        let held = HELD_MIRROR.try_lock().map(|m| m.clone()).unwrap_or_default();
+        if let Ok(mut enigo) = Enigo::new(&Settings::default()) {
+            for key in &held {
+                release_key(&mut enigo, key);
+            }
+            for modifier in [Key::Shift, Key::Control, Key::Alt, Key::Meta] {
+                let _ = enigo.key(modifier, enigo::Direction::Release);
+            }
+            eprintln!("[崩溃] 已尝试释放 {} 个按键及所有修饰键", held.len());
+        }
+        default_hook(info);
+    }));
+}
+
+/// 延后执行的输入动作
+///
+/// 技能释放的点击保持、回中等需要等待的步骤不能在收包线程里 sleep（会持有状态锁、阻塞摇杆和按键），
+/// 改为记录到期时间，由定时线程执行
+#[derive(Debug)]
+enum ScheduledAction {
+    /// 在当前位置执行技能确认，完成后回到 center
+    SkillConfirm { confirm: String, center: (i32, i32) },
+    ButtonRelease(Button),
+    ButtonClick(Button),
+    MoveMouse((i32, i32)),
+    /// 松开拖放时按住的鼠标键（按记录的按键名，期间已被松开时不重复操作）
+    ReleaseTracked(String),
+    /// 修饰键生效后按下主键（按键名为重映射后的原始大小写）
+    PressKey(String),
+    /// 松开自施法技能键，按 handle_button 的释放流程一并释放其修饰键
+    ReleaseKey(String),
+    /// 主键松开后释放修饰键
+    ReleaseModifiers(Modifiers),
+}
+
+impl ScheduledAction {
+    /// 是否为鼠标相关的动作；新的技能或光标输入到来前，这些动作需要先执行完，
+    /// 否则上一个技能的确认点击和回中会落在新的瞄准位置上
+    fn is_pointer(&self) -> bool {
+        !matches!(self, Self::PressKey(_) | Self::ReleaseKey(_) | Self::ReleaseModifiers(_))
     }
 }
 
 struct InputState {
     pressed_keys: HashSet<String>,  // 改为 String 以支持特殊按键
+    press_order: VecDeque<String>,  // 按下顺序，用于释放最早的按键
+    max_held_keys: usize,
+    held_key_policy: HeldKeyPolicy,
     pressed_modifiers: Modifiers,   // 当前按下的修饰键
-    enigo: Enigo,
+    modifier_owners: HashMap<String, Modifiers>,  // 按住中的按键各自随按下附带的修饰键
+    warned_keys: HashSet<String>,  // 已警告过的未知按键名，每个只警告一次
+    enigo: Box<dyn InputBackend>,
+    enigo_errors: u32,  // 连续失败的按键/鼠标按钮操作次数
+    display: Box<dyn DisplayProvider>,  // 显示器和鼠标位置的来源
     skill_center: Option<(i32, i32)>,
+    last_skill_event: Option<Instant>,  // 当前技能最近一次开始/拖拽的时间，用于超时自动取消
+    skill_timeout: Option<std::time::Duration>,
+    skill_monitor: Option<Monitor>,  // 技能开始时所在的显示器，瞄准坐标不超出其范围
+    skill_offset: (i32, i32),  // 技能中心相对显示器中心的偏移，显示器变化后重新计算中心时使用
+    monitors: Vec<Monitor>,  // 上次技能开始时的显示器布局，用于检测热插拔和分辨率变化
+    last_monitor_check: Option<Instant>,  // 拖拽期间上次检查显示器的时间
+    skill_radii: HashMap<String, i32>,  // 按技能配置的鼠标半径
+    default_skill_radius: i32,
+    smooth_factor: f32,
+    smooth_curve: SmoothCurve,
+    skill_radius: i32,  // 当前技能的鼠标半径
     active_skill: Option<String>,
+    skill_confirm: Option<String>,  // 技能确认动作（鼠标键或键盘键，"none" 表示不点击），默认左键
+    skill_clicks: HashMap<String, String>,  // 按技能配置的确认动作
+    self_cast: HashSet<String>,  // 自施法/瞬发技能：只按键，不移动鼠标、不点击确认
+    skill_click_delay: std::time::Duration,  // 移动到目标后等待多久再点击，点击后等待多久回中
+    skill_click_hold: std::time::Duration,  // 确认点击的按下保持时间
+    modifier_delay: std::time::Duration,  // 修饰键与主键之间的等待时间
     // 平滑鼠标移动
     current_mouse_x: f32,
     current_mouse_y: f32,
     target_mouse_x: f32,
     target_mouse_y: f32,
+    smoothing: bool,  // 是否仍在向目标插值
+    // 技能拖拽限速：超出频率的拖拽只记录最新目标，由定时线程补发
+    skill_drag_interval: std::time::Duration,
+    last_skill_drag: Option<Instant>,
+    pending_skill_drag: Option<(f32, f32, bool)>,
+    log_filter: LogFilter,
+    key_remap: HashMap<String, String>,  // 按键重映射：请求的按键 -> 实际输出
+    gamepad_keys: HashMap<String, GamepadButton>,  // 按键路由：由虚拟手柄输出的按键，其余走键盘鼠标
+    // 相对鼠标移动
+    mouse_sensitivity: f32,
+    mouse_move_remainder: (f32, f32),  // 不足 1 像素的位移累积到下一次
+    // 摇杆按键映射、滞回阈值及当前状态
+    joystick: JoystickConfig,
+    joystick_direction: (i8, i8),
+    joystick_keys: HashSet<String>,  // 由摇杆按下的按键
+    joystick_running: bool,  // 是否处于跑步档
+    suspended: bool,  // 暂停远程控制（不断开连接）
+    // 技能释放后的左键抑制窗口，防止手机端紧接着的点击在错误位置重复触发
+    skill_click_suppress: std::time::Duration,
+    last_skill_release: Option<Instant>,
+    click_suppressed: bool,  // 按下被抑制时，对应的释放也一并忽略
+    // 摇杆模式（速度模式由定时线程持续移动鼠标）
+    joystick_mode: JoystickMode,
+    joystick_max_rate: f32,
+    joystick_recenter: Option<std::time::Duration>,
+    look_offset: (i32, i32),  // 速度模式下摇杆推动以来光标累计移动的像素，回中时反向移回
+    recenter_velocity: Option<(f32, f32)>,  // 正在回中时的速度（像素/秒）
+    joystick_scroll_rate: f32,
+    scroll_step: i32,  // 滚轮按键每次滚动的格数
+    joystick_axis: (f32, f32),
+    velocity_remainder: (f32, f32),  // 不足 1 像素（或 1 格滚动）的量累积到下一次
+    last_tick: Instant,
+    scheduled: Vec<(Instant, ScheduledAction)>,  // 延后执行的动作，由定时线程执行
+    turbo: HashMap<String, (std::time::Duration, Instant)>,  // 连发中的按键：(间隔, 下次点击时间)
+    profiles: BTreeMap<String, Config>,  // 可切换的配置方案（含 default）
+    active_profile: String,
+    gamepad: Option<Box<dyn GamepadOutput>>,  // 虚拟手柄（摇杆为 gamepad 模式时创建）
 }
 
 impl InputState {
-    fn new() -> Self {
-        Self {
+    /// 创建输入状态；输入后端初始化失败（无图形会话、缺少权限等）时返回对应错误
+    fn new(config: &Config) -> Result<Self, enigo::NewConError> {
+        let enigo = Enigo::new(&Settings::default())?;
+        Ok(Self::with_backend(config, Box::new(enigo), Box::new(SystemDisplay)))
+    }
+
+    /// 使用指定的输出后端和显示器来源创建输入状态
+    fn with_backend(config: &Config, enigo: Box<dyn InputBackend>, display: Box<dyn DisplayProvider>) -> Self {
+        let mut state = Self {
             pressed_keys: HashSet::new(),
+            press_order: VecDeque::new(),
+            max_held_keys: config.max_held_keys,
+            held_key_policy: config.held_key_policy,
             pressed_modifiers: Modifiers::default(),
-            enigo: Enigo::new(&Settings::default()).expect("Failed to create Enigo"),
+            modifier_owners: HashMap::new(),
+            warned_keys: HashSet::new(),
+            enigo,
+            enigo_errors: 0,
+            display,
             skill_center: None,
+            last_skill_event: None,
+            skill_timeout: config.skill_timeout,
+            skill_monitor: None,
+            skill_offset: (0, 0),
+            monitors: Vec::new(),
+            last_monitor_check: None,
+            skill_radii: config.skill_radius.clone(),
+            skill_radius: config.default_skill_radius,
+            default_skill_radius: config.default_skill_radius,
+            smooth_factor: config.smooth_factor,
+            smooth_curve: config.smooth_curve,
             active_skill: None,
+            skill_confirm: None,
+            skill_clicks: config.skill_click.clone(),
+            self_cast: config.self_cast.clone(),
+            skill_click_delay: config.skill_click_delay,
+            skill_click_hold: config.skill_click_hold,
+            modifier_delay: config.modifier_delay,
             current_mouse_x: 0.0,
             current_mouse_y: 0.0,
             target_mouse_x: 0.0,
             target_mouse_y: 0.0,
-        }
+            smoothing: false,
+            skill_drag_interval: config.skill_drag_interval(),
+            last_skill_drag: None,
+            pending_skill_drag: None,
+            log_filter: config.log_filter.clone(),
+            key_remap: config.key_remap.clone(),
+            gamepad_keys: config.gamepad_keys.clone(),
+            mouse_sensitivity: config.mouse_sensitivity,
+            mouse_move_remainder: (0.0, 0.0),
+            joystick: config.joystick.clone(),
+            joystick_direction: (0, 0),
+            joystick_keys: HashSet::new(),
+            joystick_running: false,
+            suspended: false,
+            skill_click_suppress: std::time::Duration::from_millis(config.skill_click_suppress_ms),
+            last_skill_release: None,
+            click_suppressed: false,
+            joystick_mode: config.joystick_mode,
+            joystick_max_rate: config.joystick_max_rate,
+            joystick_recenter: config.joystick_recenter,
+            look_offset: (0, 0),
+            recenter_velocity: None,
+            joystick_scroll_rate: config.joystick_scroll_rate,
+            scroll_step: config.scroll_step,
+            joystick_axis: (0.0, 0.0),
+            velocity_remainder: (0.0, 0.0),
+            last_tick: Instant::now(),
+            scheduled: Vec::new(),
+            turbo: HashMap::new(),
+            profiles: BTreeMap::new(),
+            active_profile: DEFAULT_PROFILE.to_string(),
+            gamepad: None,
+        };
+        state.load_profiles(config);
+        state
     }
     
+    /// 发送一个键盘事件并记录结果
+    fn send_key(&mut self, key: Key, direction: enigo::Direction) -> bool {
+        let result = self.enigo.key(key, direction);
+        self.record_enigo_result(result)
+    }
+
+    /// 发送一个鼠标按钮事件并记录结果
+    fn send_button(&mut self, button: Button, direction: enigo::Direction) -> bool {
+        let result = self.enigo.button(button, direction);
+        self.record_enigo_result(result)
+    }
+
+    /// 统计连续失败次数；系统持续拒绝输入（失去焦点、权限被撤销等）时按键并未真正按下，
+    /// 达到阈值后清空按键记录，避免服务端认为按键仍被按住，下次按下时重新同步；返回是否发送成功
+    fn record_enigo_result(&mut self, result: enigo::InputResult<()>) -> bool {
+        let Err(e) = result else {
+            self.enigo_errors = 0;
+            return true;
+        };
+        self.enigo_errors += 1;
+        debug!("[输入] 输入事件发送失败 ({}/{}): {}", self.enigo_errors, ENIGO_ERROR_THRESHOLD, e);
+        if self.enigo_errors < ENIGO_ERROR_THRESHOLD {
+            return false;
+        }
+        warn!(
+            "[输入] 连续 {} 次输入失败 ({})，清空 {} 个按键记录及修饰键状态",
+            self.enigo_errors,
+            e,
+            self.pressed_keys.len()
+        );
+        self.enigo_errors = 0;
+        self.pressed_keys.clear();
+        self.press_order.clear();
+        self.joystick_keys.clear();
+        self.joystick_direction = (0, 0);
+        self.joystick_running = false;
+        self.pressed_modifiers = Modifiers::default();
+        self.modifier_owners.clear();
+        self.sync_held_mirror();
+        false
+    }
+
+    /// 未知按键名只警告一次，避免客户端反复发送同一个错误按键时刷屏
+    fn warn_unknown_key(&mut self, key: &str) {
+        if self.warned_keys.len() >= MAX_WARNED_KEYS || !self.warned_keys.insert(key.to_string()) {
+            return;
+        }
+        warn!("[按键] 未知按键名 {:?}，已忽略（可用 --list-keys 查看所有按键名）", key);
+    }
+
     /// 按下/释放修饰键
     fn update_modifiers(&mut self, modifiers: &Modifiers, press: bool) {
         let direction = if press { enigo::Direction::Press } else { enigo::Direction::Release };
         
         if modifiers.shift && (press != self.pressed_modifiers.shift) {
-            let _ = self.enigo.key(Key::Shift, direction);
+            self.send_key(Key::Shift, direction);
             self.pressed_modifiers.shift = press;
         }
         if modifiers.control && (press != self.pressed_modifiers.control) {
-            let _ = self.enigo.key(Key::Control, direction);
+            self.send_key(Key::Control, direction);
             self.pressed_modifiers.control = press;
         }
         if modifiers.alt && (press != self.pressed_modifiers.alt) {
-            let _ = self.enigo.key(Key::Alt, direction);
+            self.send_key(Key::Alt, direction);
             self.pressed_modifiers.alt = press;
         }
         if modifiers.command && (press != self.pressed_modifiers.command) {
-            let _ = self.enigo.key(Key::Meta, direction);
+            self.send_key(Key::Meta, direction);
             self.pressed_modifiers.command = press;
         }
     }
@@ -449,490 +1913,3337 @@ impl InputState {
     /// 释放所有修饰键
     fn release_all_modifiers(&mut self) {
         if self.pressed_modifiers.shift {
-            let _ = self.enigo.key(Key::Shift, enigo::Direction::Release);
+            self.send_key(Key::Shift, enigo::Direction::Release);
             self.pressed_modifiers.shift = false;
         }
         if self.pressed_modifiers.control {
-            let _ = self.enigo.key(Key::Control, enigo::Direction::Release);
+            self.send_key(Key::Control, enigo::Direction::Release);
             self.pressed_modifiers.control = false;
         }
         if self.pressed_modifiers.alt {
-            let _ = self.enigo.key(Key::Alt, enigo::Direction::Release);
+            self.send_key(Key::Alt, enigo::Direction::Release);
             self.pressed_modifiers.alt = false;
         }
         if self.pressed_modifiers.command {
-            let _ = self.enigo.key(Key::Meta, enigo::Direction::Release);
+            self.send_key(Key::Meta, enigo::Direction::Release);
             self.pressed_modifiers.command = false;
         }
     }
 
-    fn update_key(&mut self, key: char, should_press: bool) {
-        let key_str = key.to_string();
-        let is_pressed = self.pressed_keys.contains(&key_str);
-        if should_press && !is_pressed {
-            let _ = self.enigo.key(Key::Unicode(key), enigo::Direction::Press);
-            self.pressed_keys.insert(key_str);
-        } else if !should_press && is_pressed {
-            let _ = self.enigo.key(Key::Unicode(key), enigo::Direction::Release);
-            self.pressed_keys.remove(&key_str);
+    /// 记录按键已按下
+    fn mark_pressed(&mut self, key: String) {
+        if self.pressed_keys.insert(key.clone()) {
+            self.press_order.push_back(key);
+            self.sync_held_mirror();
         }
     }
 
-    fn handle_joystick(&mut self, x: f32, y: f32) {
-        let x = if x.abs() < DEADZONE { 0.0 } else { x };
-        let y = if y.abs() < DEADZONE { 0.0 } else { y };
-        self.update_key('a', x < -DEADZONE);
-        self.update_key('d', x > DEADZONE);
-        self.update_key('w', y < -DEADZONE);
-        self.update_key('s', y > DEADZONE);
+    /// 记录按键已释放
+    fn mark_released(&mut self, key: &str) {
+        if self.pressed_keys.remove(key) {
+            self.press_order.retain(|k| k != key);
+            self.sync_held_mirror();
+        }
     }
 
-    fn handle_button(&mut self, key: &str, pressed: bool, modifiers: Option<Modifiers>) {
-        let key_lower = key.to_lowercase();
-        
-        if pressed {
-            // 先按下修饰键
-            if let Some(ref mods) = modifiers {
-                if !mods.is_empty() {
-                    self.update_modifiers(mods, true);
-                    // 给系统一点时间识别修饰键
-                    thread::sleep(std::time::Duration::from_millis(10));
+    /// 同步按键镜像，供 panic 钩子使用
+    fn sync_held_mirror(&self) {
+        if let Ok(mut mirror) = HELD_MIRROR.try_lock() {
+            mirror.clear();
+            mirror.extend(self.press_order.iter().cloned());
+        }
+    }
+
+    /// 释放一个已记录为按住的按键
+    fn release_tracked(&mut self, key: &str) {
+        if let Some(ParsedInput::Shifted(enigo_key)) = parse_key(key) {
+            self.release_shifted(key, enigo_key);
+            return;
+        }
+        release_key(self.enigo.as_mut(), key);
+        self.mark_released(key);
+    }
+
+    /// 检查同时按住的按键上限，返回是否允许按下 key
+    fn reserve_held_slot(&mut self, key: &str) -> bool {
+        if self.pressed_keys.contains(key) || self.pressed_keys.len() < self.max_held_keys {
+            return true;
+        }
+        match self.held_key_policy {
+            HeldKeyPolicy::Refuse => {
+                warn!("[按键] 同时按住的按键已达上限 {}，忽略 {}", self.max_held_keys, key);
+                false
+            }
+            HeldKeyPolicy::ReleaseOldest => {
+                if let Some(oldest) = self.press_order.front().cloned() {
+                    warn!("[按键] 同时按住的按键已达上限 {}，释放最早的 {}", self.max_held_keys, oldest);
+                    self.release_tracked(&oldest);
                 }
+                true
             }
-            
-            // 按下主键或鼠标
-            if let Some(parsed) = parse_key(&key_lower) {
-                match parsed {
-                    ParsedInput::Keyboard(enigo_key) => {
-                        let _ = self.enigo.key(enigo_key, enigo::Direction::Press);
-                        self.pressed_keys.insert(key_lower);
-                    }
-                    ParsedInput::Mouse(action) => {
-                        match action {
-                            MouseAction::ScrollUp => {
-                                let _ = self.enigo.scroll(2, enigo::Axis::Vertical);
-                            }
-                            MouseAction::ScrollDown => {
-                                let _ = self.enigo.scroll(-2, enigo::Axis::Vertical);
-                            }
-                            _ => {
-                                if let Some(btn) = mouse_action_to_button(action) {
-                                    let _ = self.enigo.button(btn, enigo::Direction::Press);
-                                    self.pressed_keys.insert(key_lower);
-                                }
-                            }
-                        }
-                    }
+        }
+    }
+
+    /// 按住自动加 Shift 的字符（按原始大小写记录，避免与不带 Shift 的同一个键混淆）：
+    /// Shift 记为该字符拥有，多个同时按住的字符共用一次 Shift 按下，全部松开后才释放
+    fn press_shifted(&mut self, key: &str, enigo_key: Key) {
+        if self.pressed_keys.contains(key) || !self.reserve_held_slot(key) {
+            return;
+        }
+        let shift = Modifiers { shift: true, ..Modifiers::default() };
+        self.update_modifiers(&shift, true);
+        // 发送失败时不记录为按住，Shift 没有其他按键使用时一并松开
+        if !self.send_key(enigo_key, enigo::Direction::Press) {
+            self.release_unowned_modifiers(shift);
+            return;
+        }
+        self.modifier_owners.insert(key.to_string(), shift);
+        self.mark_pressed(key.to_string());
+    }
+
+    /// 松开自动加 Shift 的字符；Shift 仍被其他按住的按键使用时保留
+    fn release_shifted(&mut self, key: &str, enigo_key: Key) {
+        self.send_key(enigo_key, enigo::Direction::Release);
+        self.mark_released(key);
+        if let Some(owned) = self.modifier_owners.remove(key) {
+            self.release_unowned_modifiers(owned);
+        }
+    }
+
+    /// 按下不带 Shift 的键盘键；Shift 只是因为按住的大写字母/符号而按下时先暂时松开，
+    /// 避免按住 "A" 时输入 "b" 变成 "B"；返回按键是否发送成功
+    fn press_plain_key(&mut self, enigo_key: Key) -> bool {
+        let lift = self.pressed_modifiers.shift
+            && !self
+                .modifier_owners
+                .iter()
+                .any(|(key, mods)| mods.shift && !matches!(parse_key(key), Some(ParsedInput::Shifted(_))));
+        if lift {
+            self.send_key(Key::Shift, enigo::Direction::Release);
+        }
+        let ok = self.send_key(enigo_key, enigo::Direction::Press);
+        if lift {
+            self.send_key(Key::Shift, enigo::Direction::Press);
+        }
+        ok
+    }
+
+    /// 带 Shift 点击一个键；客户端已通过修饰键按住 Shift 时不重复操作 Shift
+    fn shifted_key(&mut self, key: Key, direction: enigo::Direction) {
+        let wrap = !self.pressed_modifiers.shift;
+        if wrap && direction != enigo::Direction::Release {
+            self.send_key(Key::Shift, enigo::Direction::Press);
+        }
+        self.send_key(key, direction);
+        if wrap && direction != enigo::Direction::Press {
+            self.send_key(Key::Shift, enigo::Direction::Release);
+        }
+    }
+
+    /// 按下一个按键或鼠标按钮并记录为按住（已按住时不重复按下）
+    fn press_tracked(&mut self, key: &str) {
+        if self.pressed_keys.contains(key) || !self.reserve_held_slot(key) {
+            return;
+        }
+        match parse_key(key) {
+            Some(ParsedInput::Keyboard(enigo_key)) => {
+                if self.press_plain_key(enigo_key) {
+                    self.mark_pressed(key.to_string());
                 }
             }
-        } else {
-            // 释放主键或鼠标
-            if let Some(parsed) = parse_key(&key_lower) {
-                match parsed {
-                    ParsedInput::Keyboard(enigo_key) => {
-                        let _ = self.enigo.key(enigo_key, enigo::Direction::Release);
-                        self.pressed_keys.remove(&key_lower);
-                    }
-                    ParsedInput::Mouse(action) => {
-                        // 滚轮不需要释放
-                        if action != MouseAction::ScrollUp && action != MouseAction::ScrollDown {
-                            if let Some(btn) = mouse_action_to_button(action) {
-                                let _ = self.enigo.button(btn, enigo::Direction::Release);
-                                self.pressed_keys.remove(&key_lower);
-                            }
-                        }
+            Some(ParsedInput::Shifted(enigo_key)) => self.press_shifted(key, enigo_key),
+            Some(ParsedInput::Mouse(action)) => {
+                if let Some(btn) = mouse_action_to_button(action) {
+                    if self.send_button(btn, enigo::Direction::Press) {
+                        self.mark_pressed(key.to_string());
                     }
                 }
             }
-            
-            // 释放修饰键
-            if let Some(ref mods) = modifiers {
-                if !mods.is_empty() {
-                    // 给系统一点时间识别主键释放
-                    thread::sleep(std::time::Duration::from_millis(10));
-                    self.update_modifiers(mods, false);
+            Some(ParsedInput::Text(_)) | None => {}
+        }
+    }
+
+    fn handle_joystick(&mut self, x: f32, y: f32) {
+        if matches!(self.joystick_mode, JoystickMode::Velocity | JoystickMode::Scroll) {
+            // 死区内视为回中，鼠标停止移动/滚动
+            let in_deadzone = match self.joystick.deadzone_mode {
+                DeadzoneMode::PerAxis => x.abs() < self.joystick.exit && y.abs() < self.joystick.exit,
+                DeadzoneMode::Radial => x.hypot(y) < self.joystick.exit,
+            };
+            self.joystick_axis = if in_deadzone { (0.0, 0.0) } else { (x, y) };
+            return;
+        }
+        // 手柄模式：死区外原样输出模拟量；没有可用的虚拟手柄时按方向键处理
+        if self.joystick_mode == JoystickMode::Gamepad {
+            if let Some(pad) = self.gamepad.as_mut() {
+                let in_deadzone = match self.joystick.deadzone_mode {
+                    DeadzoneMode::PerAxis => x.abs() < self.joystick.exit && y.abs() < self.joystick.exit,
+                    DeadzoneMode::Radial => x.hypot(y) < self.joystick.exit,
+                };
+                if in_deadzone {
+                    pad.set_stick(0.0, 0.0);
+                } else {
+                    pad.set_stick(x, y);
                 }
+                return;
             }
         }
-    }
 
-    fn handle_skill_start(&mut self, key: &str, offset_x: i32, offset_y: i32, modifiers: Option<Modifiers>) {
-        // 获取当前鼠标所在显示器的中心，并应用偏移
-        let base_center = get_current_display_center();
-        let center = (base_center.0 + offset_x, base_center.1 + offset_y);
-        self.skill_center = Some(center);
+        let (prev_h, prev_v) = self.joystick_direction;
+        let (h, v) = match self.joystick.deadzone_mode {
+            DeadzoneMode::PerAxis => (
+                axis_direction(x, prev_h, self.joystick.enter, self.joystick.exit),
+                axis_direction(y, prev_v, self.joystick.enter, self.joystick.exit),
+            ),
+            DeadzoneMode::Radial => {
+                // 按偏移量的模长判断死区（同样带滞回），方向按角度落入的 8 个扇区决定
+                let engaged = (prev_h, prev_v) != (0, 0);
+                let threshold = if engaged { self.joystick.exit } else { self.joystick.enter };
+                if x.hypot(y) > threshold { sector_direction(x, y) } else { (0, 0) }
+            }
+        };
+        self.joystick_direction = (h, v);
 
-        // 先按下修饰键
-        if let Some(ref mods) = modifiers {
-            self.update_modifiers(mods, true);
+        // 跑步档：推得足够深时额外按住跑步键（带滞回，避免在阈值附近反复切换）
+        let was_running = self.joystick_running;
+        self.joystick_running = match self.joystick.run_threshold {
+            Some(threshold) if direction_index(h, v).is_some_and(|i| !self.joystick.directions[i].is_empty()) => {
+                let magnitude = x.hypot(y);
+                if self.joystick_running {
+                    magnitude > threshold - config::JOYSTICK_HYSTERESIS
+                } else {
+                    magnitude > threshold
+                }
+            }
+            _ => false,
+        };
+        // 高频摇杆流中大部分包的量化结果不变，直接跳过，只在方向或跑步档变化时更新按键
+        if (h, v) == (prev_h, prev_v) && self.joystick_running == was_running {
+            return;
         }
 
-        // 按下技能键（点击）- 支持键盘和鼠标
-        if let Some(parsed) = parse_key(key) {
-            match parsed {
-                ParsedInput::Keyboard(enigo_key) => {
-                    let _ = self.enigo.key(enigo_key, enigo::Direction::Click);
-                }
-                ParsedInput::Mouse(action) => {
-                    match action {
-                        MouseAction::ScrollUp => {
-                            let _ = self.enigo.scroll(2, enigo::Axis::Vertical);
-                        }
-                        MouseAction::ScrollDown => {
-                            let _ = self.enigo.scroll(-2, enigo::Axis::Vertical);
-                        }
-                        _ => {
-                            if let Some(btn) = mouse_action_to_button(action) {
-                                let _ = self.enigo.button(btn, enigo::Direction::Click);
-                            }
-                        }
-                    }
+        // 当前方向需要按住的按键集合
+        let mut desired: Vec<String> = direction_index(h, v)
+            .map(|i| self.joystick.directions[i].clone())
+            .unwrap_or_default();
+        if self.joystick_running && !desired.contains(&self.joystick.run_key) {
+            desired.push(self.joystick.run_key.clone());
+        }
+
+        // 先释放不再需要的键，再按下新增的键
+        let stale: Vec<String> = self
+            .joystick_keys
+            .iter()
+            .filter(|k| !desired.contains(k))
+            .cloned()
+            .collect();
+        for key in stale {
+            self.release_tracked(&key);
+            self.joystick_keys.remove(&key);
+        }
+        for key in desired {
+            if !self.joystick_keys.contains(&key) {
+                self.press_tracked(&key);
+                // 发送失败（或达到按键上限）时不记为摇杆按住，下次方向更新时重试
+                if self.pressed_keys.contains(&key) {
+                    self.joystick_keys.insert(key);
                 }
             }
         }
-        
-        // 释放修饰键
-        if let Some(ref mods) = modifiers {
-            self.update_modifiers(mods, false);
-        }
+    }
 
-        // 鼠标移到显示器中心（含偏移）
-        let _ = self.enigo.move_mouse(center.0, center.1, Coordinate::Abs);
-        
-        // 初始化平滑鼠标位置
-        self.current_mouse_x = center.0 as f32;
-        self.current_mouse_y = center.1 as f32;
-        self.target_mouse_x = center.0 as f32;
-        self.target_mouse_y = center.1 as f32;
+    /// 触控板模式：把归一化坐标映射到鼠标当前所在的显示器上
+    fn handle_mouse_pos(&mut self, x: f32, y: f32) {
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        let Some(monitor) = self.display.current_monitor() else { return };
+        let (mouse_x, mouse_y) = monitor.point_at(x, y);
+        let _ = self.enigo.move_mouse(mouse_x, mouse_y, Coordinate::Abs);
+    }
 
-        self.active_skill = Some(key.to_string());
-        
-        let mod_str = modifiers.map(|m| {
-            let mut parts = Vec::new();
-            if m.control { parts.push("Ctrl"); }
-            if m.alt { parts.push("Alt"); }
-            if m.shift { parts.push("Shift"); }
-            if m.command { parts.push("Cmd"); }
-            if parts.is_empty() { String::new() } else { format!(" [{}]", parts.join("+")) }
-        }).unwrap_or_default();
-        
-        if offset_x != 0 || offset_y != 0 {
-            println!("[技能开始] {}{} - 中心 ({}, {}) 偏移 ({}, {})", key, mod_str, center.0, center.1, offset_x, offset_y);
-        } else {
-            println!("[技能开始] {}{} - 中心 ({}, {})", key, mod_str, center.0, center.1);
+    /// 视角控制：按灵敏度缩放后相对移动鼠标，不影响技能瞄准中心
+    fn handle_mouse_move(&mut self, dx: f32, dy: f32) {
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        let move_x = dx * self.mouse_sensitivity + self.mouse_move_remainder.0;
+        let move_y = dy * self.mouse_sensitivity + self.mouse_move_remainder.1;
+        self.mouse_move_remainder = (move_x.fract(), move_y.fract());
+        if move_x.trunc() != 0.0 || move_y.trunc() != 0.0 {
+            let _ = self.enigo.move_mouse(move_x as i32, move_y as i32, Coordinate::Rel);
         }
     }
 
-    fn handle_skill_drag(&mut self, _key: &str, dx: f32, dy: f32, _distance: f32, smooth: bool) {
-        if let Some(center) = self.skill_center {
-            let target_x = center.0 as f32 + dx * SKILL_MOUSE_RADIUS as f32;
-            let target_y = center.1 as f32 + dy * SKILL_MOUSE_RADIUS as f32;
-            
-            if smooth {
-                // 平滑模式：线性插值
-                self.target_mouse_x = target_x;
-                self.target_mouse_y = target_y;
-                
-                self.current_mouse_x += (self.target_mouse_x - self.current_mouse_x) * SMOOTH_FACTOR;
-                self.current_mouse_y += (self.target_mouse_y - self.current_mouse_y) * SMOOTH_FACTOR;
-                
-                let _ = self.enigo.move_mouse(
-                    self.current_mouse_x as i32,
-                    self.current_mouse_y as i32,
-                    Coordinate::Abs
-                );
-            } else {
-                // 直接模式
-                let _ = self.enigo.move_mouse(target_x as i32, target_y as i32, Coordinate::Abs);
-            }
+    /// 松开摇杆按住的方向键和跑步键，下一次摇杆事件会重新按下
+    fn release_joystick_keys(&mut self) {
+        for key in std::mem::take(&mut self.joystick_keys) {
+            self.release_tracked(&key);
         }
+        self.joystick_direction = (0, 0);
+        self.joystick_running = false;
     }
 
-    fn handle_skill_release(&mut self, key: &str, dx: f32, dy: f32) {
-        if let Some(center) = self.skill_center {
-            let mouse_x = center.0 + (dx * SKILL_MOUSE_RADIUS as f32) as i32;
-            let mouse_y = center.1 + (dy * SKILL_MOUSE_RADIUS as f32) as i32;
-            
-            // 移动到最终位置
-            let _ = self.enigo.move_mouse(mouse_x, mouse_y, Coordinate::Abs);
-            // 延迟一下再点击，确保鼠标移动完成
-            thread::sleep(std::time::Duration::from_millis(SKILL_CLICK_DELAY_MS));
-            // 点击确认 - 分开按下和释放
-            let _ = self.enigo.button(Button::Left, enigo::Direction::Press);
-            thread::sleep(std::time::Duration::from_millis(SKILL_CLICK_HOLD_MS));
-            let _ = self.enigo.button(Button::Left, enigo::Direction::Release);
-            // 延迟后再回到中心
-            thread::sleep(std::time::Duration::from_millis(SKILL_CLICK_DELAY_MS));
-            // 回到中心
-            let _ = self.enigo.move_mouse(center.0, center.1, Coordinate::Abs);
-            
-            println!("[技能释放] {} - ({}, {})", key, mouse_x, mouse_y);
+    /// 载入配置方案（启动和热加载时调用），并重新应用当前方案；当前方案已被删除时回到 default
+    fn load_profiles(&mut self, config: &Config) {
+        let mut base = config.clone();
+        self.profiles = std::mem::take(&mut base.profiles);
+        self.profiles.insert(DEFAULT_PROFILE.to_string(), base);
+        if !self.profiles.contains_key(&self.active_profile) {
+            warn!("[配置方案] 方案 {} 已不存在，切换到 {}", self.active_profile, DEFAULT_PROFILE);
+            self.active_profile = DEFAULT_PROFILE.to_string();
         }
-        self.skill_center = None;
-        self.active_skill = None;
+        let profile = self.profiles[&self.active_profile].clone();
+        self.apply_config(&profile);
     }
 
-    fn handle_skill_cancel(&mut self, key: &str) {
-        if let Some(center) = self.skill_center {
-            let _ = self.enigo.move_mouse(center.0, center.1, Coordinate::Abs);
-        }
-        self.skill_center = None;
-        self.active_skill = None;
-        println!("[技能取消] {}", key);
+    /// 切换配置方案；先释放所有按键，避免旧映射按下的键无法按新映射释放
+    fn select_profile(&mut self, name: &str) {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            warn!("[配置方案] 未知方案 {}，保持 {}（可用: {}）", name, self.active_profile, names.join(", "));
+            return;
+        };
+        self.release_all();
+        self.apply_config(&profile);
+        self.active_profile = name.to_string();
+        info!("[配置方案] 已切换到 {}", name);
     }
 
-    fn release_all(&mut self) {
-        for key_str in self.pressed_keys.clone() {
-            if let Some(parsed) = parse_key(&key_str) {
-                match parsed {
-                    ParsedInput::Keyboard(enigo_key) => {
-                        let _ = self.enigo.key(enigo_key, enigo::Direction::Release);
-                    }
-                    ParsedInput::Mouse(action) => {
-                        if let Some(btn) = mouse_action_to_button(action) {
-                            let _ = self.enigo.button(btn, enigo::Direction::Release);
-                        }
-                    }
-                }
+    /// 热加载配置：更新可调参数，已按住的按键保持不变；
+    /// 摇杆映射在下一次摇杆事件生效，切换摇杆模式或映射时先松开方向键以免卡键
+    fn apply_config(&mut self, config: &Config) {
+        if config.joystick_mode != self.joystick_mode || config.joystick != self.joystick {
+            self.release_joystick_keys();
+            self.joystick_axis = (0.0, 0.0);
+            self.velocity_remainder = (0.0, 0.0);
+            if let Some(pad) = self.gamepad.as_mut() {
+                pad.set_stick(0.0, 0.0);
             }
         }
-        self.pressed_keys.clear();
-        self.release_all_modifiers();
-        self.skill_center = None;
-        self.active_skill = None;
+        self.joystick_mode = config.joystick_mode;
+        if self.joystick_mode == JoystickMode::Gamepad && self.gamepad.is_none() {
+            self.gamepad = gamepad::create();
+        }
+        self.joystick = config.joystick.clone();
+        self.joystick_max_rate = config.joystick_max_rate;
+        self.joystick_recenter = config.joystick_recenter;
+        self.joystick_scroll_rate = config.joystick_scroll_rate;
+        self.max_held_keys = config.max_held_keys;
+        self.held_key_policy = config.held_key_policy;
+        self.skill_radii = config.skill_radius.clone();
+        self.default_skill_radius = config.default_skill_radius;
+        self.smooth_factor = config.smooth_factor;
+        self.smooth_curve = config.smooth_curve;
+        self.skill_clicks = config.skill_click.clone();
+        self.self_cast = config.self_cast.clone();
+        self.skill_click_delay = config.skill_click_delay;
+        self.skill_click_hold = config.skill_click_hold;
+        self.modifier_delay = config.modifier_delay;
+        self.skill_click_suppress = std::time::Duration::from_millis(config.skill_click_suppress_ms);
+        self.skill_drag_interval = config.skill_drag_interval();
+        self.skill_timeout = config.skill_timeout;
+        self.log_filter = config.log_filter.clone();
+        self.key_remap = config.key_remap.clone();
+        self.gamepad_keys = config.gamepad_keys.clone();
+        self.mouse_sensitivity = config.mouse_sensitivity;
+        self.scroll_step = config.scroll_step;
     }
-}
 
+    /// 输入一段文本；先松开摇杆按住的方向键，避免文本中的字符与 WASD 状态互相干扰
+    fn handle_text(&mut self, content: &str) {
+        if content.len() > MAX_TEXT_BYTES {
+            warn!("[文本] 内容过长（{} 字节，上限 {}），已忽略", content.len(), MAX_TEXT_BYTES);
+            return;
+        }
+        self.flush_scheduled(|action| !action.is_pointer());
+        self.release_joystick_keys();
+        let _ = self.enigo.text(content);
+    }
 
-fn register_mdns_service(ip: &std::net::IpAddr, port: u16) -> Option<ServiceDaemon> {
-    let mdns = ServiceDaemon::new().ok()?;
-    
-    // 获取主机名作为服务名（去掉可能存在的 .local 后缀）
-    let raw_hostname = hostname::get()
-        .ok()
-        .and_then(|h| h.into_string().ok())
-        .unwrap_or_else(|| "TouchServer".to_string());
-    
-    let hostname = raw_hostname
-        .trim_end_matches(".local")
-        .trim_end_matches('.');
-    
-    let instance_name = format!("TouchServer-{}", hostname);
-    let host_name = format!("{}.local.", hostname);
-    
-    // 创建服务信息
-    let service_info = ServiceInfo::new(
-        SERVICE_TYPE,
-        &instance_name,
-        &host_name,
-        ip,
-        port,
-        None,
-    );
-    
-    match service_info {
-        Ok(info) => {
-            if let Err(e) = mdns.register(info) {
-                println!("[mDNS] 注册失败: {:?}", e);
-                return None;
+    /// 按指定格数滚动，超出上限的滚动量会被截断
+    fn handle_scroll(&mut self, axis: &str, amount: i32) {
+        let axis = match axis {
+            "vertical" => enigo::Axis::Vertical,
+            "horizontal" => enigo::Axis::Horizontal,
+            _ => {
+                warn!("[滚动] 未知滚动轴 {}，应为 vertical 或 horizontal", axis);
+                return;
             }
-            println!("[mDNS] 服务已注册: {}", instance_name);
-            println!("[mDNS] 服务类型: {}", SERVICE_TYPE);
-            println!("[mDNS] 主机名: {}", host_name);
-            Some(mdns)
+        };
+        let amount = amount.clamp(-MAX_SCROLL_AMOUNT, MAX_SCROLL_AMOUNT);
+        if amount != 0 {
+            let _ = self.enigo.scroll(amount, axis);
         }
-        Err(e) => {
-            println!("[mDNS] 创建服务信息失败: {:?}", e);
-            None
+    }
+
+    /// 组合键：按顺序按下所有键再逆序释放，整个过程在一条消息内完成，不受网络抖动影响
+    fn handle_combo(&mut self, keys: &[String]) {
+        self.flush_scheduled(|action| !action.is_pointer());
+        // 只松开本次组合键实际按下的按键；客户端已按住的按键（如 Ctrl）保持按住
+        let mut pressed = Vec::with_capacity(keys.len());
+        for key in keys {
+            let key = self.remap_key(key);
+            let Some(parsed) = parse_key(&key) else {
+                warn!("[组合键] 未知按键 {}，已跳过", key);
+                continue;
+            };
+            // 与 press_main_key 的记录方式一致：自动加 Shift 的字符保留原始大小写
+            let tracked = match parsed {
+                ParsedInput::Shifted(_) => key.clone(),
+                _ => key.to_lowercase(),
+            };
+            if self.pressed_keys.contains(&tracked) {
+                continue;
+            }
+            match Modifiers::of_key(&tracked) {
+                Some(held) => self.hold_modifier_key(&key, held),
+                None => self.press_main_key(&key),
+            }
+            if self.pressed_keys.contains(&tracked) {
+                pressed.push(tracked);
+            }
+        }
+        for key in pressed.into_iter().rev() {
+            match Modifiers::of_key(&key) {
+                Some(held) => self.release_modifier_key(&key, held),
+                None => self.release_tracked(&key),
+            }
         }
     }
-}
 
-fn main() {
-    let local_ip = local_ip().expect("Failed to get local IP");
-    
-    // 注册 mDNS 服务
-    let _mdns = register_mdns_service(&local_ip, PORT);
-    if _mdns.is_none() {
-        println!("[mDNS] 警告: 服务注册失败，客户端需手动输入IP");
+    /// 多次点击：第一次立即点击，其余由定时线程按间隔执行，不受网络抖动影响
+    fn handle_click(&mut self, button: &str, count: u8, interval_ms: u16) {
+        let btn = match parse_key(&self.remap_key(button)) {
+            Some(ParsedInput::Mouse(action)) => mouse_action_to_button(action),
+            _ => None,
+        };
+        let Some(btn) = btn else {
+            warn!("[点击] {} 不是鼠标按键，已忽略", button);
+            return;
+        };
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        let count = count.min(MAX_CLICK_COUNT);
+        let interval = std::time::Duration::from_millis(interval_ms.min(MAX_CLICK_INTERVAL_MS) as u64);
+        for i in 0..count {
+            if i == 0 {
+                self.send_button(btn, enigo::Direction::Click);
+            } else {
+                self.schedule(interval * i as u32, ScheduledAction::ButtonClick(btn));
+            }
+        }
     }
-    
-    // 显示检测到的显示器
-    let monitors = get_all_monitors();
-    
-    println!("========================================");
-    println!("  Touch Server - UDP 低延迟输入服务");
-    println!("========================================");
-    println!("局域网 IP: {}", local_ip);
-    println!("监听端口: {}", PORT);
-    println!("连接地址: {}:{}", local_ip, PORT);
-    println!("----------------------------------------");
-    println!("检测到 {} 个显示器:", monitors.len());
-    for (i, m) in monitors.iter().enumerate() {
-        println!("  [{}] {}x{} @ ({}, {})", i + 1, m.width, m.height, m.x, m.y);
+
+    /// 拖放：按下、移动、松开由定时线程依次执行；松开动作一定会被安排（release_all 也会立即执行它），
+    /// 移动失败也不会留下按住的鼠标键
+    fn handle_drag(&mut self, button: &str, to_x: f32, to_y: f32, smooth: bool) {
+        let key = self.remap_key(button).to_lowercase();
+        let btn = match parse_key(&key) {
+            Some(ParsedInput::Mouse(action)) => mouse_action_to_button(action),
+            _ => None,
+        };
+        let Some(btn) = btn else {
+            warn!("[拖放] {} 不是鼠标按键，已忽略", button);
+            return;
+        };
+        let Some(monitor) = self.display.current_monitor() else {
+            warn!("[拖放] 无法获取显示器信息，已忽略");
+            return;
+        };
+        // 上一次拖放或技能尚未完成的移动和松开先执行完
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        let target = monitor.point_at(to_x, to_y);
+        let step = std::time::Duration::from_millis(DRAG_STEP_MS);
+        // 按键记录为按住，断开或 release_all 时能被松开；客户端已按住该键时只移动，不替它松开
+        let owned = !self.pressed_keys.contains(&key);
+        if owned {
+            if !self.reserve_held_slot(&key) || !self.send_button(btn, enigo::Direction::Press) {
+                return;
+            }
+            self.mark_pressed(key.clone());
+        }
+        let mut delay = step;
+        match self.display.mouse_position().filter(|_| smooth) {
+            Some(start) => {
+                for i in 1..=DRAG_SMOOTH_STEPS {
+                    let t = i as f32 / DRAG_SMOOTH_STEPS as f32;
+                    let x = start.0 as f32 + (target.0 - start.0) as f32 * t;
+                    let y = start.1 as f32 + (target.1 - start.1) as f32 * t;
+                    self.schedule(delay, ScheduledAction::MoveMouse((x.round() as i32, y.round() as i32)));
+                    delay += step;
+                }
+            }
+            None => {
+                self.schedule(delay, ScheduledAction::MoveMouse(target));
+                delay += step;
+            }
+        }
+        if owned {
+            self.schedule(delay, ScheduledAction::ReleaseTracked(key));
+        }
     }
-    println!("----------------------------------------");
-    println!("摇杆映射: W(上) A(左) S(下) D(右)");
-    println!("技能鼠标半径: {}px", SKILL_MOUSE_RADIUS);
-    println!("死区阈值: {:.0}%", DEADZONE * 100.0);
-    println!("支持模式: 普通(JSON) / 极限(二进制)");
-    println!("========================================");
-    println!("等待客户端连接...\n");
 
-    let socket = UdpSocket::bind(format!("0.0.0.0:{}", PORT)).expect("Failed to bind");
-    socket.set_read_timeout(Some(std::time::Duration::from_secs(1))).ok();
-    
-    // 极限模式优化：增大接收缓冲区
-    #[cfg(unix)]
-    {
-        use std::os::unix::io::AsRawFd;
-        let fd = socket.as_raw_fd();
-        unsafe {
-            let buf_size: libc::c_int = 65536;
-            libc::setsockopt(
-                fd,
-                libc::SOL_SOCKET,
-                libc::SO_RCVBUF,
-                &buf_size as *const _ as *const libc::c_void,
-                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
-            );
+    /// 检查左键事件是否落在技能释放后的抑制窗口内
+    fn should_suppress_click(&mut self, key_lower: &str, pressed: bool) -> bool {
+        if key_lower != "mouse_left" {
+            return false;
+        }
+        if !pressed {
+            return std::mem::take(&mut self.click_suppressed);
+        }
+        let suppress = self
+            .last_skill_release
+            .is_some_and(|t| t.elapsed() < self.skill_click_suppress);
+        if suppress {
+            debug!("[按键] 技能刚释放，忽略左键点击");
         }
+        self.click_suppressed = suppress;
+        suppress
     }
 
-    let mut input_state = InputState::new();
-    let mut buf = [0u8; 1024];
-    let mut last_client: Option<std::net::SocketAddr> = None;
-    let mut last_heartbeat = Instant::now();
-    let mut client_extreme_mode = false;  // 跟踪客户端是否使用极限模式
-    
-    // 可靠消息去重：记录最近处理过的序列号
-    let mut processed_seqs: std::collections::VecDeque<u32> = std::collections::VecDeque::with_capacity(100);
-    const MAX_PROCESSED_SEQS: usize = 100;
+    /// 查找按键重映射，未配置时原样返回
+    fn remap_key(&self, key: &str) -> String {
+        match self.key_remap.get(&key.to_lowercase()) {
+            Some(target) => target.clone(),
+            None => key.to_string(),
+        }
+    }
 
-    loop {
-        match socket.recv_from(&mut buf) {
-            Ok((len, src)) => {
-                if last_client != Some(src) {
-                    println!("[连接] 客户端: {}", src);
-                    last_client = Some(src);
-                    client_extreme_mode = false;
-                    processed_seqs.clear();  // 新客户端，清空去重缓存
-                }
-                last_heartbeat = Instant::now();
-
-                // 自动检测协议类型：二进制协议以 MAGIC (0xAB) 开头
-                let is_binary = len > 0 && buf[0] == binary_protocol::MAGIC;
-                
-                // 解析消息，获取消息内容和可选的序列号
-                let (msg, ack_seq) = if is_binary {
-                    if !client_extreme_mode {
-                        println!("[模式] 客户端切换到极限模式 (二进制协议)");
-                        client_extreme_mode = true;
-                    }
-                    match parse_binary_message(&buf[..len]) {
-                        Some((m, seq)) => (Some(m), seq),
-                        None => (None, None),
-                    }
-                } else {
-                    if client_extreme_mode {
-                        println!("[模式] 客户端切换到普通模式 (JSON协议)");
-                        client_extreme_mode = false;
+    /// 查找按键的输出路由：路由表中的按键及 pad_ 开头的按键由虚拟手柄输出，其余返回 None（键盘鼠标）
+    fn gamepad_route(&self, key_lower: &str) -> Option<GamepadButton> {
+        self.gamepad_keys
+            .get(key_lower)
+            .copied()
+            .or_else(|| GamepadButton::parse(key_lower))
+    }
+
+    fn handle_button(&mut self, key: &str, pressed: bool, modifiers: Option<Modifiers>) {
+        // 上一个按键延后的按下/修饰键释放先执行完，否则本次按键会带着上一个按键的修饰键，
+        // 并可能先于上一个按键落下
+        self.flush_scheduled(|action| !action.is_pointer());
+        // 按重映射表替换为实际输出（可跨键盘/鼠标），按住状态按实际输出记录
+        let key = self.remap_key(key);
+        let key_lower = key.to_lowercase();
+        if self.should_suppress_click(&key_lower, pressed) {
+            return;
+        }
+        if let Some(button) = self.gamepad_route(&key_lower) {
+            match self.gamepad.as_mut() {
+                Some(pad) => pad.button(button, pressed),
+                None => debug!("[手柄] 未启用虚拟手柄，忽略 {}", key),
+            }
+            return;
+        }
+        if parse_key(&key).is_none() {
+            self.warn_unknown_key(&key);
+            return;
+        }
+        
+        // 单独按住的修饰键同时计入修饰键状态，之后不带修饰键的按键也按组合键处理
+        if let Some(held) = Modifiers::of_key(&key_lower) {
+            if pressed {
+                self.hold_modifier_key(&key, held);
+            } else {
+                self.release_modifier_key(&key, held);
+            }
+            return;
+        }
+
+        if pressed {
+            // 先按下修饰键
+            if let Some(ref mods) = modifiers {
+                if !mods.is_empty() {
+                    self.update_modifiers(mods, true);
+                    // 记录修饰键归属，释放时即使客户端未附带修饰键也能正确松开
+                    let owned = self.modifier_owners.entry(key_lower.clone()).or_default();
+                    *owned = owned.union(mods);
+                    // 给系统一点时间识别修饰键；由定时线程延后按下主键，不阻塞收包
+                    if !self.modifier_delay.is_zero() {
+                        self.schedule(self.modifier_delay, ScheduledAction::PressKey(key));
+                        return;
                     }
-                    match serde_json::from_slice::<InputMessage>(&buf[..len]) {
-                        Ok(m) => {
-                            // 从 JSON 消息中提取 seq
-                            let seq = match &m {
-                                InputMessage::Button { seq, .. } => *seq,
-                                InputMessage::SkillRelease { seq, .. } => *seq,
-                                InputMessage::SkillCancel { seq, .. } => *seq,
-                                _ => None,
-                            };
-                            (Some(m), seq)
-                        }
-                        Err(_) => (None, None),
+                }
+            }
+            self.press_main_key(&key);
+        } else {
+            // 主键尚在等待修饰键生效时先补按下，保证按下/释放成对
+            let pending = self
+                .scheduled
+                .iter()
+                .position(|(_, action)| matches!(action, ScheduledAction::PressKey(k) if *k == key));
+            if let Some(index) = pending {
+                self.scheduled.remove(index);
+                self.press_main_key(&key);
+            }
+
+            // 释放主键或鼠标
+            if let Some(parsed) = parse_key(&key) {
+                match parsed {
+                    ParsedInput::Keyboard(enigo_key) => {
+                        self.send_key(enigo_key, enigo::Direction::Release);
+                        self.mark_released(&key_lower);
                     }
-                };
-                
-                // 如果有序列号，发送 ACK 并检查去重
-                if let Some(seq) = ack_seq {
-                    // 发送 ACK
-                    if is_binary {
-                        let ack = build_binary_ack(seq);
-                        let _ = socket.send_to(&ack, src);
-                    } else {
-                        let ack = AckMessage { r#type: "ack", seq };
-                        if let Ok(data) = serde_json::to_vec(&ack) {
-                            let _ = socket.send_to(&data, src);
+                    ParsedInput::Shifted(enigo_key) => self.release_shifted(&key, enigo_key),
+                    // 字符已在按下时输入完成
+                    ParsedInput::Text(_) => {}
+                    ParsedInput::Mouse(action) => {
+                        // 滚轮不是按钮，不需要释放
+                        if let Some(btn) = mouse_action_to_button(action) {
+                            self.send_button(btn, enigo::Direction::Release);
+                            self.mark_released(&key_lower);
                         }
                     }
-                    
-                    // 检查是否重复消息
-                    if processed_seqs.contains(&seq) {
-                        // 重复消息，跳过处理但已发送 ACK
-                        continue;
-                    }
-                    
-                    // 记录已处理的序列号
-                    processed_seqs.push_back(seq);
-                    if processed_seqs.len() > MAX_PROCESSED_SEQS {
-                        processed_seqs.pop_front();
-                    }
                 }
+            }
+            
+            // 释放修饰键：按下时记录的与本次附带的合并，仍被其他按住的按键使用的保留
+            let owned = self.modifier_owners.remove(&key_lower).unwrap_or_default();
+            let requested = modifiers.unwrap_or_default().union(&owned);
+            if requested.is_empty() {
+                return;
+            }
+            // 给系统一点时间识别主键释放
+            if self.modifier_delay.is_zero() {
+                self.release_unowned_modifiers(requested);
+            } else {
+                self.schedule(self.modifier_delay, ScheduledAction::ReleaseModifiers(requested));
+            }
+        }
+    }
 
-                if let Some(msg) = msg {
-                    match msg {
-                        InputMessage::Joystick { x, y } => input_state.handle_joystick(x, y),
-                        InputMessage::Button { key, pressed, modifiers, .. } => {
-                            let mod_str = modifiers.as_ref().map(|m| {
-                                let mut parts = Vec::new();
-                                if m.control { parts.push("Ctrl"); }
-                                if m.alt { parts.push("Alt"); }
-                                if m.shift { parts.push("Shift"); }
-                                if m.command { parts.push("Cmd"); }
-                                if parts.is_empty() { String::new() } else { format!("[{}+]", parts.join("+")) }
-                            }).unwrap_or_default();
-                            println!("[按键] {}{} {}", mod_str, key, if pressed { "按下" } else { "释放" });
-                            input_state.handle_button(&key, pressed, modifiers);
-                        }
-                        InputMessage::SkillStart { key, offset_x, offset_y, modifiers } => {
-                            input_state.handle_skill_start(&key, offset_x, offset_y, modifiers);
-                        }
-                        InputMessage::SkillDrag { key, dx, dy, distance, smooth } => {
-                            input_state.handle_skill_drag(&key, dx, dy, distance, smooth)
-                        }
-                        InputMessage::SkillRelease { key, dx, dy, .. } => {
-                            input_state.handle_skill_release(&key, dx, dy)
-                        }
-                        InputMessage::SkillCancel { key, .. } => input_state.handle_skill_cancel(&key),
-                        InputMessage::Ping { timestamp } => {
-                            if is_binary {
-                                // 极限模式：二进制 pong
-                                let pong = build_binary_pong(timestamp);
-                                let _ = socket.send_to(&pong, src);
-                            } else {
-                                // 普通模式：JSON pong
-                                let pong = PongMessage { r#type: "pong", timestamp };
-                                if let Ok(data) = serde_json::to_vec(&pong) {
-                                    let _ = socket.send_to(&data, src);
-                                }
-                            }
-                        }
+    /// 按住单独发送的修饰键：记为该按键拥有的修饰键，带同一修饰键的其他按键松开时不会释放它，
+    /// 自动加 Shift 的字符也不会重复按下/松开 Shift
+    fn hold_modifier_key(&mut self, key: &str, held: Modifiers) {
+        let key_lower = key.to_lowercase();
+        if !self.pressed_keys.contains(&key_lower) {
+            self.press_main_key(key);
+        }
+        if self.pressed_keys.contains(&key_lower) {
+            let owned = self.modifier_owners.entry(key_lower).or_default();
+            *owned = owned.union(&held);
+            self.pressed_modifiers = self.pressed_modifiers.union(&held);
+        }
+    }
+
+    /// 松开单独发送的修饰键；其他仍按住的按键需要同一修饰键时重新按下，保持组合键状态一致
+    fn release_modifier_key(&mut self, key: &str, held: Modifiers) {
+        let key_lower = key.to_lowercase();
+        self.release_tracked(&key_lower);
+        self.modifier_owners.remove(&key_lower);
+        self.pressed_modifiers = self.pressed_modifiers.without(&held);
+        let still_held = self
+            .modifier_owners
+            .values()
+            .fold(Modifiers::default(), |acc, m| acc.union(m));
+        let restore = held.intersection(&still_held);
+        if !restore.is_empty() {
+            self.update_modifiers(&restore, true);
+        }
+    }
+
+    /// 按下主键或鼠标键（修饰键已按下）
+    fn press_main_key(&mut self, key: &str) {
+        let key_lower = key.to_lowercase();
+        let Some(parsed) = parse_key(key) else {
+            return;
+        };
+        match parsed {
+            ParsedInput::Keyboard(enigo_key) => {
+                if self.reserve_held_slot(&key_lower) && self.press_plain_key(enigo_key) {
+                    self.mark_pressed(key_lower);
+                }
+            }
+            ParsedInput::Shifted(enigo_key) => self.press_shifted(key, enigo_key),
+            ParsedInput::Text(c) => {
+                // 字符一次性输入，不记录为按住状态
+                let _ = self.enigo.text(&c.to_string());
+            }
+            ParsedInput::Mouse(action) => {
+                if let Some((direction, axis)) = mouse_action_to_scroll(action) {
+                    let _ = self.enigo.scroll(direction * self.scroll_step, axis);
+                } else if let Some(btn) = mouse_action_to_button(action) {
+                    if self.reserve_held_slot(&key_lower) && self.send_button(btn, enigo::Direction::Press) {
+                        self.mark_pressed(key_lower);
                     }
                 }
             }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::WouldBlock
-                    || e.kind() == std::io::ErrorKind::TimedOut
-                {
-                    if last_client.is_some()
-                        && last_heartbeat.elapsed().as_secs() > HEARTBEAT_TIMEOUT_SECS
-                    {
-                        println!("[断开] 心跳超时");
-                        input_state.release_all();
-                        last_client = None;
+        }
+    }
+
+    /// 释放 mods 中不再被任何按住的按键使用的修饰键；延后执行时按执行时的归属重新计算，
+    /// 期间又被新按键按下的修饰键不会被误释放
+    fn release_unowned_modifiers(&mut self, mods: Modifiers) {
+        let still_held = self
+            .modifier_owners
+            .values()
+            .fold(Modifiers::default(), |acc, m| acc.union(m));
+        let mods = mods.without(&still_held);
+        if !mods.is_empty() {
+            self.update_modifiers(&mods, false);
+        }
+    }
+
+    fn handle_skill_start(&mut self, key: &str, offset_x: i32, offset_y: i32, modifiers: Option<Modifiers>, confirm: Option<String>) {
+        if self.self_cast.contains(&key.to_lowercase()) {
+            self.self_cast_skill(key, modifiers);
+            return;
+        }
+        // 上一个技能的确认点击和回中先执行完，不能落在本次技能的瞄准位置上
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        // 获取当前鼠标所在显示器的中心，并应用偏移
+        let monitors = self.display.monitors();
+        if !self.monitors.is_empty() && monitors != self.monitors {
+            info!("[显示器] 显示器布局已变化: {} 个 -> {} 个", self.monitors.len(), monitors.len());
+        }
+        self.monitors = monitors.clone();
+        let monitor = monitor_at(monitors, self.display.mouse_position());
+        // 回退：使用默认值
+        let base_center = monitor.as_ref().map(Monitor::center).unwrap_or((960, 540));
+        let center = (base_center.0 + offset_x, base_center.1 + offset_y);
+        self.skill_center = Some(center);
+        self.skill_monitor = monitor;
+        self.skill_offset = (offset_x, offset_y);
+        self.last_monitor_check = Some(Instant::now());
+        // 缓存本次技能的半径，保证拖拽和释放使用同一个值
+        self.skill_radius = self
+            .skill_radii
+            .get(&key.to_lowercase())
+            .copied()
+            .unwrap_or(self.default_skill_radius);
+        self.last_skill_drag = None;
+        self.pending_skill_drag = None;
+        self.smoothing = false;
+
+        // 先按下修饰键
+        if let Some(ref mods) = modifiers {
+            self.update_modifiers(mods, true);
+        }
+
+        // 按下技能键（点击）- 支持键盘和鼠标
+        if let Some(parsed) = parse_key(key) {
+            match parsed {
+                ParsedInput::Keyboard(enigo_key) => {
+                    self.send_key(enigo_key, enigo::Direction::Click);
+                }
+                ParsedInput::Shifted(enigo_key) => self.shifted_key(enigo_key, enigo::Direction::Click),
+                ParsedInput::Text(c) => {
+                    let _ = self.enigo.text(&c.to_string());
+                }
+                ParsedInput::Mouse(action) => {
+                    if let Some((direction, axis)) = mouse_action_to_scroll(action) {
+                        let _ = self.enigo.scroll(direction * self.scroll_step, axis);
+                    } else if let Some(btn) = mouse_action_to_button(action) {
+                        self.send_button(btn, enigo::Direction::Click);
                     }
                 }
             }
+        } else {
+            self.warn_unknown_key(key);
+        }
+        
+        // 释放修饰键
+        if let Some(ref mods) = modifiers {
+            self.update_modifiers(mods, false);
+        }
+
+        // 鼠标移到显示器中心（含偏移）
+        let _ = self.enigo.move_mouse(center.0, center.1, Coordinate::Abs);
+        
+        // 初始化平滑鼠标位置
+        self.current_mouse_x = center.0 as f32;
+        self.current_mouse_y = center.1 as f32;
+        self.target_mouse_x = center.0 as f32;
+        self.target_mouse_y = center.1 as f32;
+
+        self.active_skill = Some(key.to_string());
+        self.last_skill_event = Some(Instant::now());
+        // 确认动作优先使用消息携带的值，其次是服务端按技能配置的点击按钮
+        self.skill_confirm = confirm.or_else(|| self.skill_clicks.get(&key.to_lowercase()).cloned());
+        
+        if !self.log_filter.allows("skill_start") {
+            return;
+        }
+        let mod_str = modifiers.map(|m| {
+            let mut parts = Vec::new();
+            if m.control { parts.push("Ctrl"); }
+            if m.alt { parts.push("Alt"); }
+            if m.shift { parts.push("Shift"); }
+            if m.command { parts.push("Cmd"); }
+            if parts.is_empty() { String::new() } else { format!(" [{}]", parts.join("+")) }
+        }).unwrap_or_default();
+        
+        if offset_x != 0 || offset_y != 0 {
+            debug!("[技能开始] {}{} - 中心 ({}, {}) 偏移 ({}, {})", key, mod_str, center.0, center.1, offset_x, offset_y);
+        } else {
+            debug!("[技能开始] {}{} - 中心 ({}, {})", key, mod_str, center.0, center.1);
+        }
+    }
+
+    /// 技能进行中显示器被拔出或分辨率改变时，缓存的中心可能已不在任何显示器上，此时按当前布局重新计算。
+    /// force 为 false 时（拖拽）最多每 MONITOR_CHECK_INTERVAL_MS 查询一次显示器
+    fn revalidate_skill_center(&mut self, force: bool) {
+        let Some(monitor) = self.skill_monitor.clone() else { return };
+        let due = self
+            .last_monitor_check
+            .is_none_or(|t| t.elapsed() >= std::time::Duration::from_millis(MONITOR_CHECK_INTERVAL_MS));
+        if !force && !due {
+            return;
+        }
+        self.last_monitor_check = Some(Instant::now());
+        let monitors = self.display.monitors();
+        if monitors.is_empty() || monitors.contains(&monitor) {
+            return;
+        }
+        let Some(current) = monitor_at(monitors.clone(), self.display.mouse_position()) else { return };
+        let base = current.center();
+        let center = (base.0 + self.skill_offset.0, base.1 + self.skill_offset.1);
+        warn!(
+            "[显示器] 技能进行中显示器布局变化，技能中心 {:?} -> {:?}",
+            self.skill_center.unwrap_or_default(),
+            center
+        );
+        self.skill_center = Some(center);
+        self.skill_monitor = Some(current);
+        self.monitors = monitors;
+    }
+
+    /// 自施法/瞬发技能：按下技能键后立即结束，不移动鼠标、不点击确认，也不打断正在瞄准的技能
+    fn self_cast_skill(&mut self, key: &str, modifiers: Option<Modifiers>) {
+        // 与普通按键相同的按下/释放流程：修饰键记录归属，主键等修饰键生效后再按下，
+        // 松开时只释放没有其他按键使用的修饰键
+        self.handle_button(key, true, modifiers);
+        let remapped = self.remap_key(key);
+        let pending = self
+            .scheduled
+            .iter()
+            .any(|(_, action)| matches!(action, ScheduledAction::PressKey(k) if *k == remapped));
+        if pending {
+            // 排在延后的按下之后松开
+            self.schedule(self.modifier_delay, ScheduledAction::ReleaseKey(key.to_string()));
+        } else {
+            self.handle_button(key, false, None);
+        }
+        if self.log_filter.allows("skill_start") {
+            debug!("[自施法] {} - 不移动鼠标", key);
+        }
+    }
+
+    fn handle_skill_drag(&mut self, key: &str, dx: f32, dy: f32, _distance: f32, smooth: bool) {
+        if self.skill_center.is_none() || self.self_cast.contains(&key.to_lowercase()) {
+            return;
+        }
+        self.last_skill_event = Some(Instant::now());
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        self.revalidate_skill_center(false);
+        // 距上次移动不足限速间隔时丢弃本次移动，只保留最新目标
+        let throttled = self
+            .last_skill_drag
+            .is_some_and(|t| t.elapsed() < self.skill_drag_interval);
+        if throttled {
+            self.pending_skill_drag = Some((dx, dy, smooth));
+            return;
+        }
+        self.apply_skill_drag(dx, dy, smooth);
+    }
+
+    /// 计算技能瞄准的目标坐标，并限制在技能开始时所在的显示器内
+    fn skill_target(&self, center: (i32, i32), dx: f32, dy: f32) -> (f32, f32) {
+        let x = center.0 as f32 + dx * self.skill_radius as f32;
+        let y = center.1 as f32 + dy * self.skill_radius as f32;
+        match &self.skill_monitor {
+            Some(monitor) => monitor.clamp(x, y),
+            None => (x, y),
+        }
+    }
+
+    fn apply_skill_drag(&mut self, dx: f32, dy: f32, smooth: bool) {
+        self.last_skill_drag = Some(Instant::now());
+        self.pending_skill_drag = None;
+        self.smoothing = false;
+        if let Some(center) = self.skill_center {
+            let (target_x, target_y) = self.skill_target(center, dx, dy);
+            
+            self.target_mouse_x = target_x;
+            self.target_mouse_y = target_y;
+            if smooth {
+                // 平滑模式：只更新目标，由定时线程持续插值，客户端停发时也能到达目标
+                self.smoothing = true;
+            } else {
+                // 直接模式
+                self.smoothing = false;
+                self.current_mouse_x = target_x;
+                self.current_mouse_y = target_y;
+                let _ = self.enigo.move_mouse(target_x as i32, target_y as i32, Coordinate::Abs);
+            }
+        }
+    }
+
+    /// 平滑移动一步：向目标线性插值，距离不足 1 像素时直接到达并停止
+    fn step_smoothing(&mut self) {
+        let dx = self.target_mouse_x - self.current_mouse_x;
+        let dy = self.target_mouse_y - self.current_mouse_y;
+        let distance = dx.hypot(dy);
+        if distance < 1.0 {
+            self.current_mouse_x = self.target_mouse_x;
+            self.current_mouse_y = self.target_mouse_y;
+            self.smoothing = false;
+        } else {
+            let factor = match self.smooth_curve {
+                SmoothCurve::Linear => self.smooth_factor,
+                // 剩余距离达到技能半径时使用完整系数，越接近目标系数越小
+                SmoothCurve::EaseOut => {
+                    let ratio = (distance / self.skill_radius.max(1) as f32).clamp(EASE_OUT_MIN_RATIO, 1.0);
+                    self.smooth_factor * ratio
+                }
+            };
+            self.current_mouse_x += dx * factor;
+            self.current_mouse_y += dy * factor;
+        }
+        let _ = self.enigo.move_mouse(
+            self.current_mouse_x as i32,
+            self.current_mouse_y as i32,
+            Coordinate::Abs
+        );
+    }
+
+    fn handle_skill_release(&mut self, key: &str, dx: f32, dy: f32) {
+        // 自施法技能在开始时已完成，释放消息不影响其他正在瞄准的技能
+        if self.self_cast.contains(&key.to_lowercase()) {
+            return;
+        }
+        self.revalidate_skill_center(true);
+        if let Some(center) = self.skill_center {
+            let (target_x, target_y) = self.skill_target(center, dx, dy);
+            let (mouse_x, mouse_y) = (target_x as i32, target_y as i32);
+            
+            // 移动到最终位置；移动失败时光标位置未知，跳过确认点击以免误点，只回到中心
+            let moved = self.enigo.move_mouse(mouse_x, mouse_y, Coordinate::Abs);
+            // 延迟一下再确认，确保鼠标移动完成；确认和回中由定时线程执行，不阻塞收包
+            let confirm = self.skill_confirm.take().unwrap_or_else(|| "mouse_left".to_string());
+            let delay = self.skill_click_delay;
+            if let Err(e) = moved {
+                warn!("[技能释放] {} 移动鼠标到 ({}, {}) 失败: {}，已跳过确认点击", key, mouse_x, mouse_y, e);
+                self.schedule(delay, ScheduledAction::MoveMouse(center));
+            } else if confirm == "none" {
+                // 智能施法：不点击，只在延迟后回到中心
+                self.schedule(delay, ScheduledAction::MoveMouse(center));
+            } else {
+                self.schedule(delay, ScheduledAction::SkillConfirm { confirm, center });
+            }
+            
+            if self.log_filter.allows("skill_release") {
+                debug!("[技能释放] {} - ({}, {})", key, mouse_x, mouse_y);
+            }
+            if !self.skill_click_suppress.is_zero() {
+                self.last_skill_release = Some(Instant::now());
+            }
+        }
+        self.skill_center = None;
+        self.active_skill = None;
+        self.skill_confirm = None;
+        self.pending_skill_drag = None;
+        self.smoothing = false;
+    }
+
+    /// 在当前鼠标位置执行技能确认：鼠标键按下/保持/释放，或键盘键点击，完成后回到中心
+    fn perform_skill_confirm(&mut self, confirm: &str, center: (i32, i32)) {
+        let return_delay = self.skill_click_delay;
+        let button = match parse_key(confirm) {
+            Some(ParsedInput::Keyboard(enigo_key)) => {
+                self.send_key(enigo_key, enigo::Direction::Click);
+                self.schedule(return_delay, ScheduledAction::MoveMouse(center));
+                return;
+            }
+            Some(ParsedInput::Shifted(enigo_key)) => {
+                self.shifted_key(enigo_key, enigo::Direction::Click);
+                self.schedule(return_delay, ScheduledAction::MoveMouse(center));
+                return;
+            }
+            Some(ParsedInput::Text(c)) => {
+                let _ = self.enigo.text(&c.to_string());
+                self.schedule(return_delay, ScheduledAction::MoveMouse(center));
+                return;
+            }
+            Some(ParsedInput::Mouse(action)) => mouse_action_to_button(action),
+            None => None,
+        };
+        let button = button.unwrap_or_else(|| {
+            warn!("[技能释放] 无效的确认动作 {}，改用左键", confirm);
+            Button::Left
+        });
+        // 点击确认 - 分开按下和释放
+        let hold = self.skill_click_hold;
+        self.send_button(button, enigo::Direction::Press);
+        self.schedule(hold, ScheduledAction::ButtonRelease(button));
+        self.schedule(hold + return_delay, ScheduledAction::MoveMouse(center));
+    }
+
+    /// 安排一个延后执行的动作
+    fn schedule(&mut self, delay: std::time::Duration, action: ScheduledAction) {
+        self.scheduled.push((Instant::now() + delay, action));
+    }
+
+    /// 开始/停止连发；连发由定时线程驱动，不依赖客户端持续发包
+    fn handle_turbo(&mut self, key: &str, pressed: bool, rate_hz: u16) {
+        let key = self.remap_key(key);
+        if !pressed {
+            self.turbo.remove(&key);
+            return;
+        }
+        if parse_key(&key).is_none() {
+            self.warn_unknown_key(&key);
+            return;
         }
+        // 连发数量与同时按住的按键共用上限，避免客户端无限制地开启连发
+        if !self.turbo.contains_key(&key) && self.turbo.len() >= self.max_held_keys {
+            warn!("[连发] 同时连发的按键已达上限 {}，忽略 {}", self.max_held_keys, key);
+            return;
+        }
+        let rate_hz = rate_hz.clamp(1, MAX_TURBO_HZ);
+        let interval = std::time::Duration::from_secs(1) / rate_hz as u32;
+        // 重复的开始消息只更新频率，不打乱当前节奏
+        let next = self.turbo.get(&key).map_or_else(Instant::now, |(_, next)| *next);
+        self.turbo.insert(key, (interval, next));
+    }
+
+    /// 点击一次连发按键
+    fn tap_key(&mut self, key: &str) {
+        match parse_key(key) {
+            Some(ParsedInput::Keyboard(enigo_key)) => {
+                self.send_key(enigo_key, enigo::Direction::Click);
+            }
+            Some(ParsedInput::Shifted(enigo_key)) => self.shifted_key(enigo_key, enigo::Direction::Click),
+            Some(ParsedInput::Text(c)) => {
+                let _ = self.enigo.text(&c.to_string());
+            }
+            Some(ParsedInput::Mouse(action)) => {
+                if let Some((direction, axis)) = mouse_action_to_scroll(action) {
+                    let _ = self.enigo.scroll(direction * self.scroll_step, axis);
+                } else if let Some(btn) = mouse_action_to_button(action) {
+                    self.send_button(btn, enigo::Direction::Click);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// 执行到期的连发点击；定时线程被阻塞过久时不补发，从当前时间重新计时
+    fn run_turbo(&mut self, now: Instant) {
+        let due: Vec<String> = self
+            .turbo
+            .iter()
+            .filter(|(_, (_, next))| *next <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in due {
+            self.tap_key(&key);
+            if let Some((interval, next)) = self.turbo.get_mut(&key) {
+                let scheduled = *next + *interval;
+                *next = if scheduled < now { now + *interval } else { scheduled };
+            }
+        }
+    }
+
+    /// 执行所有已到期的延后动作（按安排顺序）
+    fn run_scheduled(&mut self) {
+        let now = Instant::now();
+        while let Some(index) = self.next_scheduled(|at, _| at <= now) {
+            let (_, action) = self.scheduled.remove(index);
+            self.perform_scheduled(action);
+        }
+    }
+
+    /// 立即执行满足条件的延后动作（不等到期），执行中新安排的同类动作也一并执行
+    fn flush_scheduled(&mut self, filter: fn(&ScheduledAction) -> bool) {
+        while let Some(index) = self.next_scheduled(|_, action| filter(action)) {
+            let (_, action) = self.scheduled.remove(index);
+            self.perform_scheduled(action);
+        }
+    }
+
+    /// 满足条件的延后动作中最早到期的一个（到期时间相同时按安排顺序）
+    fn next_scheduled(&self, ready: impl Fn(Instant, &ScheduledAction) -> bool) -> Option<usize> {
+        self.scheduled
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, action))| ready(*at, action))
+            .min_by_key(|(_, (at, _))| *at)
+            .map(|(index, _)| index)
+    }
+
+    /// 执行一个延后动作
+    fn perform_scheduled(&mut self, action: ScheduledAction) {
+        match action {
+            ScheduledAction::SkillConfirm { confirm, center } => {
+                self.perform_skill_confirm(&confirm, center)
+            }
+            ScheduledAction::ButtonRelease(button) => {
+                self.send_button(button, enigo::Direction::Release);
+            }
+            ScheduledAction::ButtonClick(button) => {
+                self.send_button(button, enigo::Direction::Click);
+            }
+            ScheduledAction::MoveMouse((x, y)) => {
+                let _ = self.enigo.move_mouse(x, y, Coordinate::Abs);
+            }
+            ScheduledAction::ReleaseTracked(key) => {
+                if self.pressed_keys.contains(&key) {
+                    self.release_tracked(&key);
+                }
+            }
+            ScheduledAction::PressKey(key) => self.press_main_key(&key),
+            ScheduledAction::ReleaseKey(key) => self.handle_button(&key, false, None),
+            ScheduledAction::ReleaseModifiers(mods) => self.release_unowned_modifiers(mods),
+        }
+    }
+
+    /// 判断 key 是否为当前激活的技能
+    ///
+    /// 旧版二进制协议的 drag/release/cancel 只携带技能键的首字节，此时按首字符匹配
+    fn is_active_skill(&self, key: &str) -> bool {
+        let Some(active) = self.active_skill.as_deref() else {
+            return false;
+        };
+        if active == key {
+            return true;
+        }
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => active.starts_with(c),
+            _ => false,
+        }
+    }
+
+    fn handle_skill_cancel(&mut self, key: &str) {
+        // 只取消当前激活的技能，避免迟到/错配的取消消息把正在瞄准的其他技能拉回中心
+        if !self.is_active_skill(key) {
+            if self.log_filter.allows("skill_cancel") {
+                debug!(
+                    "[技能取消] 忽略 {}：当前技能为 {}",
+                    key,
+                    self.active_skill.as_deref().unwrap_or("无")
+                );
+            }
+            return;
+        }
+        self.flush_scheduled(ScheduledAction::is_pointer);
+        if let Some(center) = self.skill_center {
+            let _ = self.enigo.move_mouse(center.0, center.1, Coordinate::Abs);
+        }
+        self.skill_center = None;
+        self.active_skill = None;
+        self.skill_confirm = None;
+        self.pending_skill_drag = None;
+        self.smoothing = false;
+        if self.log_filter.allows("skill_cancel") {
+            debug!("[技能取消] {}", key);
+        }
+    }
+
+    /// 定时任务，由定时线程周期调用
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_tick).as_secs_f32();
+        self.last_tick = now;
+
+        self.run_scheduled();
+        self.run_turbo(now);
+
+        // 技能开始后客户端长时间没有后续消息（可能已崩溃），自动取消，避免鼠标一直停在技能中心
+        let stalled = self
+            .last_skill_event
+            .zip(self.skill_timeout)
+            .is_some_and(|(last, timeout)| last.elapsed() > timeout);
+        if stalled {
+            self.last_skill_event = None;
+            if let Some(key) = self.active_skill.clone() {
+                warn!("[技能取消] {} 超过 {} 秒没有后续消息，自动取消", key, self.skill_timeout.unwrap_or_default().as_secs());
+                self.handle_skill_cancel(&key);
+            }
+        }
+
+        // 技能拖拽平滑：持续向目标插值
+        if self.smoothing && self.skill_center.is_some() {
+            self.step_smoothing();
+        }
+
+        // 补发被限速丢弃的技能拖拽，保证最终停在最新目标上
+        if let Some((dx, dy, smooth)) = self.pending_skill_drag {
+            let due = self
+                .last_skill_drag
+                .is_none_or(|t| t.elapsed() >= self.skill_drag_interval);
+            if due {
+                self.apply_skill_drag(dx, dy, smooth);
+            }
+        }
+
+        // 速度模式：按摇杆偏移量持续移动鼠标
+        let (x, y) = self.joystick_axis;
+        if self.joystick_mode == JoystickMode::Velocity && (x != 0.0 || y != 0.0) {
+            self.recenter_velocity = None;
+            let move_x = x * self.joystick_max_rate * dt + self.velocity_remainder.0;
+            let move_y = y * self.joystick_max_rate * dt + self.velocity_remainder.1;
+            self.velocity_remainder = (move_x.fract(), move_y.fract());
+            if move_x.trunc() != 0.0 || move_y.trunc() != 0.0 {
+                let _ = self.enigo.move_mouse(move_x as i32, move_y as i32, Coordinate::Rel);
+                if self.joystick_recenter.is_some() {
+                    self.look_offset.0 += move_x as i32;
+                    self.look_offset.1 += move_y as i32;
+                }
+            }
+        } else if self.joystick_mode == JoystickMode::Velocity {
+            if let Some(return_time) = self.joystick_recenter {
+                self.step_recenter(dt, return_time);
+            }
+        }
+
+        // 滚轮模式：按摇杆偏移量持续滚动（上推向上滚，与 scroll_up 方向一致）
+        if self.joystick_mode == JoystickMode::Scroll && (x != 0.0 || y != 0.0) {
+            let scroll_x = x * self.joystick_scroll_rate * dt + self.velocity_remainder.0;
+            let scroll_y = -y * self.joystick_scroll_rate * dt + self.velocity_remainder.1;
+            self.velocity_remainder = (scroll_x.fract(), scroll_y.fract());
+            if scroll_x.trunc() != 0.0 {
+                let _ = self.enigo.scroll(scroll_x as i32, enigo::Axis::Horizontal);
+            }
+            if scroll_y.trunc() != 0.0 {
+                let _ = self.enigo.scroll(scroll_y as i32, enigo::Axis::Vertical);
+            }
+        }
+    }
+
+    /// 读取游戏发给虚拟手柄的最近一次震动效果
+    fn poll_rumble(&mut self) -> Option<gamepad::Rumble> {
+        self.gamepad.as_mut()?.poll_rumble()
+    }
+
+    /// 摇杆回中后把光标匀速移回推动前的位置，在 return_time 内走完，抵消视角漂移
+    fn step_recenter(&mut self, dt: f32, return_time: std::time::Duration) {
+        let (ox, oy) = self.look_offset;
+        if ox == 0 && oy == 0 {
+            self.recenter_velocity = None;
+            return;
+        }
+        let secs = return_time.as_secs_f32().max(f32::EPSILON);
+        let (vx, vy) = *self
+            .recenter_velocity
+            .get_or_insert((-ox as f32 / secs, -oy as f32 / secs));
+        let step_x = vx * dt + self.velocity_remainder.0;
+        let step_y = vy * dt + self.velocity_remainder.1;
+        self.velocity_remainder = (step_x.fract(), step_y.fract());
+        // 最后一步不越过原位
+        let move_x = if (step_x as i32).abs() >= ox.abs() { -ox } else { step_x as i32 };
+        let move_y = if (step_y as i32).abs() >= oy.abs() { -oy } else { step_y as i32 };
+        if move_x != 0 || move_y != 0 {
+            let _ = self.enigo.move_mouse(move_x, move_y, Coordinate::Rel);
+            self.look_offset = (ox + move_x, oy + move_y);
+        }
+    }
+
+    /// 暂停/恢复输入处理，暂停时先释放所有按键
+    fn set_active(&mut self, active: bool) {
+        if active != self.suspended {
+            return;
+        }
+        if active {
+            self.suspended = false;
+            info!("[暂停] 已恢复远程控制");
+        } else {
+            self.release_all();
+            self.suspended = true;
+            info!("[暂停] 已暂停远程控制，等待恢复...");
+        }
+    }
+
+    /// 释放所有按键后重新创建 enigo 实例，用于显示器休眠、切换会话后输入失效的情况
+    fn reinit_backend(&mut self) {
+        self.release_all();
+        match Enigo::new(&Settings::default()) {
+            Ok(enigo) => {
+                self.enigo = Box::new(enigo);
+                info!("[后端] 输入后端已重新初始化");
+            }
+            Err(e) => warn!("[后端] 重新初始化失败，继续使用原实例: {:?}", e),
+        }
+    }
+
+    fn release_all(&mut self) {
+        // 丢弃尚未执行的延后动作，但已按下的鼠标键必须立即松开
+        for (_, action) in std::mem::take(&mut self.scheduled) {
+            if let ScheduledAction::ButtonRelease(button) = action {
+                self.send_button(button, enigo::Direction::Release);
+            }
+        }
+        for key_str in self.pressed_keys.clone() {
+            self.release_tracked(&key_str);
+        }
+        self.pressed_keys.clear();
+        self.press_order.clear();
+        self.sync_held_mirror();
+        self.joystick_axis = (0.0, 0.0);
+        self.velocity_remainder = (0.0, 0.0);
+        self.look_offset = (0, 0);
+        self.recenter_velocity = None;
+        self.joystick_direction = (0, 0);
+        self.joystick_keys.clear();
+        self.joystick_running = false;
+        if let Some(pad) = self.gamepad.as_mut() {
+            pad.set_stick(0.0, 0.0);
+            for button in GamepadButton::ALL {
+                pad.button(button, false);
+            }
+        }
+        self.turbo.clear();
+        self.release_all_modifiers();
+        self.modifier_owners.clear();
+        self.skill_center = None;
+        self.active_skill = None;
+        self.skill_confirm = None;
+        self.pending_skill_drag = None;
+        self.smoothing = false;
+    }
+
+    /// 释放某个客户端按住的按键和连发（其他客户端的按键不受影响）
+    fn release_client(&mut self, held: &ClientKeys) {
+        for key in &held.keys {
+            self.handle_button(key, false, None);
+        }
+        for key in &held.turbo {
+            self.handle_turbo(key, false, 0);
+        }
+    }
+
+    /// 构建状态查询响应（JSON），按键按名称排序便于对比
+    fn status(&self) -> Vec<u8> {
+        let mut pressed_keys: Vec<String> = self.pressed_keys.iter().cloned().collect();
+        pressed_keys.sort();
+        let status = StatusMessage {
+            r#type: "status",
+            pressed_keys,
+            pressed_modifiers: self.pressed_modifiers,
+            active_skill: self.active_skill.clone(),
+            skill_center: self.skill_center,
+        };
+        serde_json::to_vec(&status).unwrap_or_default()
+    }
+}
+
+/// Ctrl+C / SIGTERM 时释放所有按键并注销 mDNS 服务后退出，避免按键卡在按下状态
+fn install_shutdown_handler(input_state: Arc<Mutex<InputState>>, mdns: SharedMdns) {
+    let result = ctrlc::set_handler(move || {
+        info!("[退出] 正在释放所有按键...");
+        lock_state(&input_state).release_all();
+        if let Some(mdns) = mdns.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            mdns.unregister();
+            info!("[退出] mDNS 服务已注销");
+        }
+        std::process::exit(0);
+    });
+    if let Err(e) = result {
+        error!("[退出] 无法安装退出处理: {}", e);
+    }
+}
+
+/// 获取共享输入状态（锁中毒时仍继续使用，保证按键能被释放）
+fn lock_state(state: &Mutex<InputState>) -> MutexGuard<'_, InputState> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 将输入类消息分发给 InputState 处理（UDP 与 TCP 通道共用）
+fn dispatch_input(input_state: &mut InputState, msg: InputMessage, log_enabled: bool) {
+    // 批量消息逐个分发，每个事件单独计时（解析时已保证只含可直接分发的事件）
+    if let InputMessage::Batch { events } = msg {
+        for event in events {
+            dispatch_input(input_state, event, log_enabled);
+        }
+        return;
+    }
+    let started = Instant::now();
+    // 暂停期间只响应 SetActive，其余输入一律丢弃
+    if input_state.suspended && !matches!(msg, InputMessage::SetActive { .. }) {
+        return;
+    }
+    match msg {
+        InputMessage::Joystick { x, y } => input_state.handle_joystick(x, y),
+        InputMessage::Button { key, pressed, modifiers, .. } => {
+            if log_enabled {
+                let mod_str = modifiers.as_ref().map(|m| {
+                    let mut parts = Vec::new();
+                    if m.control { parts.push("Ctrl"); }
+                    if m.alt { parts.push("Alt"); }
+                    if m.shift { parts.push("Shift"); }
+                    if m.command { parts.push("Cmd"); }
+                    if parts.is_empty() { String::new() } else { format!("[{}+]", parts.join("+")) }
+                }).unwrap_or_default();
+                debug!("[按键] {}{} {}", mod_str, key, if pressed { "按下" } else { "释放" });
+            }
+            input_state.handle_button(&key, pressed, modifiers);
+        }
+        InputMessage::SkillStart { key, offset_x, offset_y, modifiers, confirm } => {
+            input_state.handle_skill_start(&key, offset_x, offset_y, modifiers, confirm);
+        }
+        InputMessage::SkillDrag { key, dx, dy, distance, smooth } => {
+            input_state.handle_skill_drag(&key, dx, dy, distance, smooth)
+        }
+        InputMessage::SkillRelease { key, dx, dy, .. } => {
+            input_state.handle_skill_release(&key, dx, dy)
+        }
+        InputMessage::SkillCancel { key, .. } => input_state.handle_skill_cancel(&key),
+        InputMessage::SetActive { active } => input_state.set_active(active),
+        InputMessage::MousePos { x, y } => input_state.handle_mouse_pos(x, y),
+        InputMessage::MouseMove { dx, dy } => input_state.handle_mouse_move(dx, dy),
+        InputMessage::Scroll { axis, amount } => input_state.handle_scroll(&axis, amount),
+        InputMessage::SelectProfile { name } => input_state.select_profile(&name),
+        InputMessage::Turbo { key, pressed, rate_hz } => {
+            if log_enabled {
+                debug!("[连发] {} {} ({}Hz)", key, if pressed { "开始" } else { "停止" }, rate_hz);
+            }
+            input_state.handle_turbo(&key, pressed, rate_hz);
+        }
+        InputMessage::ReleaseAll => {
+            info!("[按键] 客户端请求释放所有输入");
+            input_state.release_all();
+        }
+        InputMessage::Combo { keys } => {
+            if log_enabled {
+                debug!("[组合键] {}", keys.join("+"));
+            }
+            input_state.handle_combo(&keys);
+        }
+        InputMessage::Click { button, count, interval_ms } => {
+            if log_enabled {
+                debug!("[点击] {} x{}", button, count);
+            }
+            input_state.handle_click(&button, count, interval_ms);
+        }
+        InputMessage::Drag { button, to_x, to_y, smooth } => {
+            if log_enabled {
+                debug!("[拖放] {} -> ({:.3}, {:.3})", button, to_x, to_y);
+            }
+            input_state.handle_drag(&button, to_x, to_y, smooth);
+        }
+        InputMessage::Text { content } => {
+            if log_enabled {
+                debug!("[文本] 输入 {} 个字符", content.chars().count());
+            }
+            input_state.handle_text(&content);
+        }
+        // 需要回复的控制消息由各通道自行处理
+        InputMessage::Ping { .. }
+        | InputMessage::Capabilities { .. }
+        | InputMessage::Auth { .. }
+        | InputMessage::Query
+        | InputMessage::Batch { .. } => {}
+    }
+    METRICS.record_latency(started.elapsed());
+}
+
+/// 定时线程：周期性驱动 InputState 中需要持续执行的输入（如速度模式摇杆）和延后动作
+fn spawn_ticker(input_state: Arc<Mutex<InputState>>) {
+    thread::spawn(move || loop {
+        thread::sleep(std::time::Duration::from_millis(TICK_INTERVAL_MS));
+        lock_state(&input_state).tick();
+    });
+}
+
+/// 手柄震动转发：定期读取虚拟手柄上的震动效果，推送给所有活跃客户端
+fn spawn_rumble_forwarder(socket: UdpSocket, targets: foreground::EventTargets, input_state: Arc<Mutex<InputState>>) {
+    info!("[手柄] 已启用震动转发");
+    thread::spawn(move || loop {
+        thread::sleep(std::time::Duration::from_millis(TICK_INTERVAL_MS));
+        let Some(rumble) = lock_state(&input_state).poll_rumble() else { continue };
+        let targets = targets.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        for (addr, order) in targets {
+            send_packet(&socket, &build_rumble(rumble, order), addr);
+        }
+    });
+}
+
+/// 配置文件热加载：定期检查修改时间，变化时重新读取并应用可热更新的参数
+/// （端口、认证、mDNS 等网络相关参数需重启生效）
+fn spawn_config_watcher(config: Config, input_state: Arc<Mutex<InputState>>) {
+    let Some(path) = config.config_file.clone() else { return };
+    let modified = |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    thread::spawn(move || {
+        let mut current = config;
+        let mut last_modified = modified(&path);
+        loop {
+            thread::sleep(std::time::Duration::from_millis(CONFIG_POLL_INTERVAL_MS));
+            let now = modified(&path);
+            if now == last_modified {
+                continue;
+            }
+            last_modified = now;
+            match current.reload() {
+                Ok(config) => {
+                    lock_state(&input_state).load_profiles(&config);
+                    info!("[配置] 已重新加载配置文件: {}", path.display());
+                    current = config;
+                }
+                Err(e) => warn!("[配置] 重新加载配置文件失败，保留当前配置: {}", e),
+            }
+        }
+    });
+}
+
+/// 控制台命令：在服务端终端输入命令进行手动干预
+fn spawn_console(input_state: Arc<Mutex<InputState>>) {
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            // stdin 关闭（如后台运行）时退出
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            match line.trim() {
+                "" => {}
+                "reinit" => lock_state(&input_state).reinit_backend(),
+                "release" => {
+                    lock_state(&input_state).release_all();
+                    info!("[控制台] 已释放所有按键");
+                }
+                "help" => info!("[控制台] 可用命令: reinit（重建输入后端）, release（释放所有按键）"),
+                other => info!("[控制台] 未知命令: {}（输入 help 查看帮助）", other),
+            }
+        }
+    });
+}
+
+/// 已注册的 mDNS 服务，退出时注销以便客户端及时移除
+#[derive(Clone)]
+struct MdnsService {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsService {
+    /// 注销服务并关闭守护线程（最多等待 1 秒发送注销广播）
+    fn unregister(&self) {
+        if let Ok(receiver) = self.daemon.unregister(&self.fullname) {
+            let _ = receiver.recv_timeout(std::time::Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// 当前注册的 mDNS 服务，IP 变化时由监视线程替换
+type SharedMdns = Arc<Mutex<Option<MdnsService>>>;
+
+/// 定期检查本机 IP，变化时注销旧服务并用新 IP 重新注册（IP 获取失败时保留现有注册）
+fn spawn_mdns_watcher(mdns: SharedMdns, mut advertised: Vec<IpAddr>, mode: IpMode, port: u16) {
+    thread::spawn(move || loop {
+        thread::sleep(std::time::Duration::from_secs(MDNS_IP_POLL_SECS));
+        let current = net::local_addrs(mode, None);
+        if current.is_empty() || current == advertised {
+            continue;
+        }
+        info!("[mDNS] 本机 IP 变化: {:?} -> {:?}，重新注册服务", advertised, current);
+        let mut guard = mdns.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(old) = guard.take() {
+            old.unregister();
+        }
+        *guard = register_mdns_service(&current, port);
+        // 注册失败时保留旧地址，下次轮询继续重试
+        if guard.is_none() {
+            warn!("[mDNS] 重新注册失败，客户端需手动输入IP {:?}，稍后重试", current);
+        } else {
+            advertised = current;
+        }
+    });
+}
+
+/// mDNS TXT 记录：客户端连接前即可得知服务端版本、平台和支持的协议
+fn mdns_properties(port: u16) -> HashMap<String, String> {
+    HashMap::from([
+        ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ("os".to_string(), std::env::consts::OS.to_string()),
+        ("proto".to_string(), "json,binary".to_string()),
+        (
+            "binary_version".to_string(),
+            format!("{}-{}", binary_protocol::MIN_PROTOCOL_VERSION, binary_protocol::PROTOCOL_VERSION),
+        ),
+        ("port".to_string(), port.to_string()),
+    ])
+}
+
+fn register_mdns_service(ips: &[std::net::IpAddr], port: u16) -> Option<MdnsService> {
+    let mdns = ServiceDaemon::new().ok()?;
+    
+    // 获取主机名作为服务名（去掉可能存在的 .local 后缀）
+    let raw_hostname = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "TouchServer".to_string());
+    
+    let hostname = raw_hostname
+        .trim_end_matches(".local")
+        .trim_end_matches('.');
+    
+    let instance_name = format!("TouchServer-{}", hostname);
+    let host_name = format!("{}.local.", hostname);
+    
+    // 创建服务信息（附带 TXT 记录失败时退回不带 TXT 记录注册）
+    let service_info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name,
+        &host_name,
+        ips,
+        port,
+        mdns_properties(port),
+    )
+    .or_else(|e| {
+        warn!("[mDNS] TXT 记录无效，不附带元数据注册: {:?}", e);
+        ServiceInfo::new(SERVICE_TYPE, &instance_name, &host_name, ips, port, None)
+    });
+    
+    match service_info {
+        Ok(info) => {
+            let fullname = info.get_fullname().to_string();
+            if let Err(e) = mdns.register(info) {
+                warn!("[mDNS] 注册失败: {:?}", e);
+                return None;
+            }
+            info!("[mDNS] 服务已注册: {}", instance_name);
+            info!("[mDNS] 服务类型: {}", SERVICE_TYPE);
+            info!("[mDNS] 主机名: {}", host_name);
+            Some(MdnsService { daemon: mdns, fullname })
+        }
+        Err(e) => {
+            warn!("[mDNS] 创建服务信息失败: {:?}", e);
+            None
+        }
+    }
+}
+
+fn main() {
+    logging::init();
+    install_panic_hook();
+    let config = Config::from_args();
+    if config.list_keys {
+        print_key_list();
+        return;
+    }
+    logging::configure(config.quiet, config.log_file.as_deref());
+    binary_protocol::CRC_ENABLED.store(config.binary_crc, Ordering::Relaxed);
+    if config.encrypt {
+        match config.psk.as_deref().or(config.pin.as_deref()) {
+            Some(secret) => crypto::enable(secret),
+            None => {
+                error!("[加密] --encrypt 需要同时指定 --psk 或启用 PIN（--auth / --pin）");
+                std::process::exit(1);
+            }
+        }
+    }
+    let local_ips = net::local_addrs(config.ip_mode, config.bind);
+    assert!(!local_ips.is_empty(), "Failed to get local IP");
+    
+    // 注册 mDNS 服务
+    let mdns: SharedMdns = if config.mdns {
+        let service = register_mdns_service(&local_ips, config.port);
+        if service.is_none() {
+            warn!("[mDNS] 服务注册失败，客户端需手动输入IP");
+        }
+        let mdns = Arc::new(Mutex::new(service));
+        // 固定监听地址时广播的地址不会变化
+        if net::fixed_bind(config.bind).is_none() {
+            spawn_mdns_watcher(Arc::clone(&mdns), local_ips.clone(), config.ip_mode, config.port);
+        }
+        mdns
+    } else {
+        info!("[mDNS] 已禁用服务广播，客户端需手动输入IP");
+        Arc::new(Mutex::new(None))
+    };
+    
+    // 显示检测到的显示器
+    let monitors = get_all_monitors();
+    
+    info!("========================================");
+    info!("  Touch Server - UDP 低延迟输入服务");
+    info!("========================================");
+    for ip in &local_ips {
+        info!("局域网 IP: {}", ip);
+    }
+    info!("监听端口: {}", config.port);
+    for ip in &local_ips {
+        info!("连接地址: {}", SocketAddr::new(*ip, config.port));
+    }
+    info!("----------------------------------------");
+    info!("检测到 {} 个显示器:", monitors.len());
+    for (i, m) in monitors.iter().enumerate() {
+        info!("  [{}] {}x{} @ ({}, {})", i + 1, m.width, m.height, m.x, m.y);
+    }
+    info!("----------------------------------------");
+    match config.joystick_mode {
+        JoystickMode::Keys => {
+            let mapping: Vec<String> = config::DIRECTION_LABELS
+                .iter()
+                .zip(&config.joystick.directions)
+                .map(|(label, keys)| format!("{}={}", label, keys.join("+")))
+                .collect();
+            info!("摇杆映射: {}", mapping.join(" "));
+            if let Some(threshold) = config.joystick.run_threshold {
+                info!("跑步档: 超过 {:.0}% 时按住 {}", threshold * 100.0, config.joystick.run_key);
+            }
+        }
+        JoystickMode::Velocity => {
+            info!("摇杆映射: 速度模式（最大 {:.0}px/s）", config.joystick_max_rate);
+            if let Some(return_time) = config.joystick_recenter {
+                info!("摇杆回中: {}ms 内移回原位", return_time.as_millis());
+            }
+        }
+        JoystickMode::Scroll => {
+            info!("摇杆映射: 滚轮模式（最大 {:.0} 格/s）", config.joystick_scroll_rate)
+        }
+        JoystickMode::Gamepad => info!("摇杆映射: 虚拟手柄左摇杆"),
+    }
+    if !config.gamepad_keys.is_empty() && config.joystick_mode != JoystickMode::Gamepad {
+        warn!("[手柄] 已配置 {} 个手柄按键路由，但未启用 --joystick-mode gamepad，这些按键将被忽略", config.gamepad_keys.len());
+    }
+    if !config.profiles.is_empty() {
+        let names: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+        info!("配置方案: {}（另有 {}）", names.join(", "), DEFAULT_PROFILE);
+    }
+    info!("技能鼠标半径: {}px", config.default_skill_radius);
+    for (key, radius) in &config.skill_radius {
+        info!("  技能 {}: {}px", key, radius);
+    }
+    info!(
+        "死区阈值: 按下 {:.0}% / 松开 {:.0}%（{}）",
+        config.joystick.enter * 100.0,
+        config.joystick.exit * 100.0,
+        match config.joystick.deadzone_mode {
+            DeadzoneMode::PerAxis => "按轴",
+            DeadzoneMode::Radial => "圆形",
+        }
+    );
+    info!("支持模式: 普通(JSON) / 极限(二进制)");
+    if config.binary_crc {
+        info!("二进制校验: CRC8");
+    }
+    match (config.transport, config.tcp_port) {
+        (Transport::Udp, Some(tcp_port)) => info!("可靠通道: TCP {}", tcp_port),
+        (Transport::Tcp, Some(tcp_port)) => info!("传输方式: 仅 TCP {}", tcp_port),
+        (Transport::Both, Some(tcp_port)) => info!("传输方式: UDP {} + TCP {}", config.port, tcp_port),
+        (_, None) => {}
+    }
+    if let Some(pin) = &config.pin {
+        info!("配对 PIN: {}", pin);
+    }
+    if config.encrypt {
+        info!("载荷加密: ChaCha20-Poly1305（密钥来自{}）", if config.psk.is_some() { "预共享密钥" } else { "配对 PIN" });
+    }
+    if !config.access.is_empty() {
+        info!("访问控制: 白名单 {} 条 / 黑名单 {} 条", config.access.allow.len(), config.access.deny.len());
+    }
+    info!("========================================");
+    info!("控制台命令: reinit / release / help");
+    info!("等待客户端连接...");
+
+    // 仅 TCP 模式不绑定 UDP 端口
+    let socket = if config.transport == Transport::Tcp {
+        None
+    } else {
+        match net::bind_udp(config.ip_mode, config.bind, config.port) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                match config.bind {
+                    Some(ip) => error!("[网络] 绑定 UDP {} 失败: {}", SocketAddr::new(ip, config.port), e),
+                    None => error!("[网络] 绑定 UDP 端口 {} 失败: {}", config.port, e),
+                }
+                std::process::exit(1);
+            }
+        }
+    };
+    
+    // 极限模式优化：增大接收缓冲区
+    #[cfg(unix)]
+    if let Some(socket) = &socket {
+        use std::os::unix::io::AsRawFd;
+        let fd = socket.as_raw_fd();
+        unsafe {
+            let buf_size: libc::c_int = 65536;
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &buf_size as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+        }
+    }
+
+    // 权限缺失时仍继续运行：授权后重启即可，连接流程可照常调试
+    permission::check_input_permission();
+    let input_state = match InputState::new(&config) {
+        Ok(state) => Arc::new(Mutex::new(state)),
+        Err(e) => {
+            // 不输出 panic 回溯，直接说明原因
+            error!("[后端] 无法初始化输入后端: {}", e);
+            error!("[后端] 请确认当前处于图形会话（Linux 需要 X11 或可用的 DISPLAY，macOS 需授予辅助功能权限）");
+            std::process::exit(1);
+        }
+    };
+
+    install_shutdown_handler(Arc::clone(&input_state), mdns);
+    spawn_console(Arc::clone(&input_state));
+    spawn_ticker(Arc::clone(&input_state));
+    spawn_config_watcher(config.clone(), Arc::clone(&input_state));
+
+    // 可选：Prometheus 指标端点
+    if let Some(bind) = &config.metrics_bind {
+        metrics::spawn_exporter(bind);
+    }
+
+    // 可选：TCP 可靠通道（仅 TCP 模式下是唯一的通道，监听失败直接退出）
+    if let Some(tcp_port) = config.tcp_port {
+        if let Err(e) = tcp::spawn_listener(&config, Arc::clone(&input_state)) {
+            error!("[TCP] 监听端口 {} 失败: {}", tcp_port, e);
+            if config.transport == Transport::Tcp {
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // 仅 TCP：收包和会话管理都在 TCP 线程中，主线程只需保持运行
+    let Some(socket) = socket else {
+        if config.report_foreground {
+            warn!("[前台窗口] 前台窗口上报需要 UDP，仅 TCP 模式下不可用");
+        }
+        loop {
+            thread::park();
+        }
+    };
+    socket.set_read_timeout(Some(std::time::Duration::from_secs(1))).ok();
+
+    // 服务端主动推送的事件（前台窗口、手柄震动）发给所有活跃客户端
+    let event_targets = foreground::EventTargets::default();
+    // 可选：前台窗口上报
+    if config.report_foreground {
+        match socket.try_clone() {
+            Ok(event_socket) => foreground::spawn_watcher(event_socket, Arc::clone(&event_targets)),
+            Err(e) => error!("[前台窗口] 无法启用: {}", e),
+        }
+    }
+    // 虚拟手柄：把游戏的震动效果转发给客户端
+    if cfg!(feature = "gamepad") {
+        match socket.try_clone() {
+            Ok(event_socket) => spawn_rumble_forwarder(event_socket, Arc::clone(&event_targets), Arc::clone(&input_state)),
+            Err(e) => error!("[手柄] 无法启用震动转发: {}", e),
+        }
+    }
+    // 多留 1 字节：收满缓冲区说明数据报超过上限、已被截断
+    let mut buf = vec![0u8; config.max_packet_size + 1];
+    // 多客户端：每个来源地址一个会话，输入共用同一个 InputState（同一套键盘鼠标）。
+    // 多个客户端同时操作摇杆等状态时以最后收到的消息为准（后写覆盖）
+    let mut sessions: HashMap<SocketAddr, ClientSession> = HashMap::new();
+    let mut rejected: HashSet<IpAddr> = HashSet::new();  // 已记录过拒绝日志的来源
+    let mut oversized: HashSet<SocketAddr> = HashSet::new();  // 已记录过超长数据包的来源
+    let mut refused: HashSet<IpAddr> = HashSet::new();  // 已记录过会话数超限的来源
+    let mut rate_limits = IpRateLimits::new(config.rate_limit);
+    // 因空闲被断开的已认证客户端：只发心跳时不重建会话，有实际输入时直接恢复（无需重新认证）
+    let mut idle_clients: HashSet<SocketAddr> = HashSet::new();
+
+    loop {
+        // 接收超时只是为了定期检查心跳，下面统一处理
+        if let Ok((len, src)) = socket.recv_from(&mut buf) {
+            METRICS.packets_received.fetch_add(1, Ordering::Relaxed);
+            // 不在允许范围内的来源直接丢弃，不解析、不建立会话，每个来源只记录一次
+            if !config.access.allows(src.ip()) {
+                if first_report(&mut rejected, src.ip()) {
+                    warn!("[访问控制] 拒绝来自 {} 的数据包", src.ip());
+                }
+                continue;
+            }
+            let received_at = unix_millis();
+            let Some(packet) = received_packet(&buf, len) else {
+                if first_report(&mut oversized, src) {
+                    warn!(
+                        "[网络] 客户端 {} 的数据包超过 {} 字节，已丢弃（可用 --max-packet-size 调大）",
+                        src, config.max_packet_size
+                    );
+                }
+                METRICS.packets_invalid.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+            // 启用加密时未加密或认证失败的包直接丢弃，不建立会话
+            let Some(packet) = crypto::open(packet) else {
+                METRICS.packets_invalid.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+
+            // 自动检测协议类型：二进制协议以 MAGIC (0xAB，大端序客户端为 0xBA) 开头
+            let byte_order = ByteOrder::of(&packet);
+            let is_binary = byte_order.is_some();
+            
+            // 解析消息，获取消息内容和可选的序列号
+            let (msg, ack_seq) = match parse_message(&packet) {
+                Some((m, seq)) => {
+                    METRICS.record_message(m.kind());
+                    (Some(m), seq)
+                }
+                None => {
+                    METRICS.packets_invalid.fetch_add(1, Ordering::Relaxed);
+                    (None, None)
+                }
+            };
+            let is_input = msg.as_ref().is_some_and(InputMessage::is_input);
+            // 限流在建立会话之前按来源 IP 进行，超出配额的数据包不会创建会话
+            if !rate_limits.allow(is_input, &src) {
+                continue;
+            }
+
+            // 空闲断开的客户端只发心跳时不恢复会话，有实际输入才恢复
+            let resumed = idle_clients.contains(&src);
+            if resumed && !is_input {
+                continue;
+            }
+            if !sessions.contains_key(&src) && sessions.len() >= MAX_UDP_SESSIONS {
+                if first_report(&mut refused, src.ip()) {
+                    warn!("[连接] 会话数已达上限 {}，忽略来自 {} 的新客户端", MAX_UDP_SESSIONS, src);
+                }
+                continue;
+            }
+            let session = sessions.entry(src).or_insert_with(|| {
+                if resumed {
+                    info!("[连接] 客户端 {} 恢复输入", src);
+                } else {
+                    info!("[连接] 客户端: {}", src);
+                }
+                ClientSession::new(config.pin.is_none() || resumed)
+            });
+            idle_clients.remove(&src);
+
+            // 启用 PIN 时，未认证的客户端只处理认证消息，其余静默丢弃
+            if !session.authenticated {
+                if let Some(InputMessage::Auth { pin }) = &msg {
+                    // 该来源 PIN 错误过多、仍在锁定期时静默丢弃
+                    let Some(ok) = authenticate(config.pin.as_deref(), pin, src) else {
+                        continue;
+                    };
+                    if ok {
+                        info!("[认证] 客户端 {} 认证成功", src);
+                        session.authenticated = true;
+                        session.last_heartbeat = Instant::now();
+                        session.last_input = Instant::now();
+                    }
+                    send_packet(&socket, &build_auth_result(ok, byte_order), src);
+                }
+                continue;
+            }
+            session.last_heartbeat = Instant::now();
+            if is_input {
+                session.last_input = Instant::now();
+            }
+
+            if is_binary && session.byte_order.is_none() {
+                info!("[模式] 客户端 {} 切换到极限模式 (二进制协议)", src);
+            } else if !is_binary && session.byte_order.is_some() {
+                info!("[模式] 客户端 {} 切换到普通模式 (JSON协议)", src);
+            }
+            session.byte_order = byte_order;
+            
+            // 如果有序列号，发送 ACK 并检查去重
+            let mut duplicate = false;
+            if let Some(seq) = ack_seq {
+                // 发送 ACK
+                if let Some(order) = byte_order {
+                    let ack = build_binary_ack(seq, order);
+                    send_packet(&socket, &ack, src);
+                } else {
+                    let ack = AckMessage { r#type: "ack", seq };
+                    if let Ok(data) = serde_json::to_vec(&ack) {
+                        send_packet(&socket, &data, src);
+                    }
+                }
+                
+                // 检查是否重复消息，并记录已处理的序列号（重复消息跳过处理但已发送 ACK）
+                duplicate = !session.processed_seqs.insert(seq);
+                session.reliable_stats.record(duplicate);
+            }
+
+            match msg {
+                _ if duplicate => {}
+                None => {}
+                // 已认证后重复发送的认证消息直接确认
+                Some(InputMessage::Auth { .. }) => {
+                    send_packet(&socket, &build_auth_result(true, byte_order), src);
+                }
+                Some(InputMessage::Ping { timestamp }) => {
+                    session.ping_stats.record(Instant::now());
+                    if let Some(order) = byte_order {
+                        // 极限模式：二进制 pong
+                        let pong = build_binary_pong(timestamp, order);
+                        send_packet(&socket, &pong, src);
+                    } else {
+                        // 普通模式：JSON pong
+                        let one_way_delay_ms = session
+                            .clock_offset_ms
+                            .filter(|_| config.one_way_delay)
+                            .map(|offset| estimate_one_way_delay(timestamp, offset));
+                        let pong = PongMessage {
+                            r#type: "pong",
+                            timestamp,
+                            server_time: Some(received_at),
+                            one_way_delay_ms,
+                        };
+                        if let Ok(data) = serde_json::to_vec(&pong) {
+                            send_packet(&socket, &data, src);
+                        }
+                    }
+                }
+                Some(InputMessage::Capabilities { clock_offset_ms }) => {
+                    if clock_offset_ms.is_some() {
+                        session.clock_offset_ms = clock_offset_ms;
+                    }
+                    send_packet(&socket, &build_capabilities(&config), src);
+                }
+                // 仅已认证的客户端可查询，未认证时已在上面丢弃
+                Some(InputMessage::Query) => {
+                    if config.pin.is_some() {
+                        send_packet(&socket, &lock_state(&input_state).status(), src);
+                    } else {
+                        debug!("[查询] 未启用 PIN，忽略 {} 的状态查询", src);
+                    }
+                }
+                Some(msg) => {
+                    session.held.track(&msg);
+                    let log_enabled = config.log_filter.allows(msg.kind());
+                    dispatch_input(&mut lock_state(&input_state), msg, log_enabled);
+                }
+            }
+        }
+
+        // 心跳超时、认证超时：移除会话，释放该客户端按住的按键
+        let was_active = sessions.values().any(|s| s.authenticated);
+        sessions.retain(|addr, session| {
+            if !session.authenticated {
+                let pending = session.created.elapsed().as_secs() <= AUTH_TIMEOUT_SECS;
+                if !pending {
+                    warn!("[认证] 客户端 {} 认证超时", addr);
+                }
+                return pending;
+            }
+            let alive = session.last_heartbeat.elapsed() <= config.heartbeat_timeout;
+            if !alive {
+                info!("[断开] 客户端 {} 心跳超时", addr);
+                session.report_stats(addr);
+                lock_state(&input_state).release_client(&session.held);
+            }
+            alive
+        });
+
+        // 空闲断开：连接仍在（有心跳）但长时间没有实际输入，与心跳超时一样释放按键并移除会话
+        if let Some(idle_timeout) = config.idle_timeout {
+            sessions.retain(|addr, session| {
+                let idle = session.authenticated && session.last_input.elapsed() > idle_timeout;
+                if idle {
+                    info!("[断开] 客户端 {} 空闲超过 {} 秒", addr, idle_timeout.as_secs());
+                    session.report_stats(addr);
+                    lock_state(&input_state).release_client(&session.held);
+                    idle_clients.insert(*addr);
+                }
+                !idle
+            });
+        }
+
+        // 定期输出各客户端的连接统计，便于排查 Wi-Fi 丢包和延迟抖动
+        for (addr, session) in sessions.iter_mut() {
+            if session.stats_reported.elapsed().as_secs() >= STATS_REPORT_INTERVAL_SECS {
+                session.report_stats(addr);
+                session.stats_reported = Instant::now();
+            }
+        }
+
+        // 服务端保活：客户端暂停输入时移动网络的回程映射可能过期，主动发包保持畅通
+        if let Some(interval) = config.keepalive {
+            for (addr, session) in sessions.iter_mut() {
+                if session.authenticated
+                    && session.last_input.elapsed() >= interval
+                    && session.last_keepalive.elapsed() >= interval
+                {
+                    send_packet(&socket, &build_keepalive(session.byte_order), *addr);
+                    session.last_keepalive = Instant::now();
+                }
+            }
+        }
+
+        // 只有最后一个活跃客户端离开时才释放所有按键
+        let active: Vec<(SocketAddr, Option<ByteOrder>)> = sessions
+            .iter()
+            .filter(|(_, s)| s.authenticated)
+            .map(|(addr, s)| (*addr, s.byte_order))
+            .collect();
+        METRICS.udp_clients.store(active.len() as i64, Ordering::Relaxed);
+        // both 模式下 TCP 客户端也可能按住按键，需等它们也断开
+        let tcp_active = config.transport == Transport::Both && tcp::connected_clients() > 0;
+        if was_active && active.is_empty() && !tcp_active {
+            info!("[断开] 所有客户端已断开，释放所有按键");
+            lock_state(&input_state).release_all();
+        }
+
+        *event_targets.lock().unwrap_or_else(PoisonError::into_inner) = active;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enigo::{Axis, Direction, InputResult};
+
+    /// 记录到的输出事件
+    #[derive(Debug, Clone, PartialEq)]
+    enum Event {
+        Key(Key, Direction),
+        Button(Button, Direction),
+        Move(i32, i32, Coordinate),
+        Scroll(i32, Axis),
+        Text(String),
+    }
+
+    /// 只记录调用、不产生真实输入的输出后端
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<Event>>>,
+        /// 为 true 时模拟系统拒绝输入：不记录事件并返回错误
+        failing: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Recorder {
+        fn take(&self) -> Vec<Event> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+
+        fn set_failing(&self, failing: bool) {
+            self.failing.store(failing, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        fn push(&self, event: Event) -> InputResult<()> {
+            if self.failing.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(enigo::InputError::Simulate("input rejected"));
+            }
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    impl Keyboard for Recorder {
+        fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+            self.push(Event::Text(text.to_string()))?;
+            Ok(Some(()))
+        }
+
+        fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+            self.push(Event::Key(key, direction))
+        }
+
+        fn raw(&mut self, _keycode: u16, _direction: Direction) -> InputResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Mouse for Recorder {
+        fn button(&mut self, button: Button, direction: Direction) -> InputResult<()> {
+            self.push(Event::Button(button, direction))
+        }
+
+        fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+            self.push(Event::Move(x, y, coordinate))
+        }
+
+        fn scroll(&mut self, length: i32, axis: Axis) -> InputResult<()> {
+            self.push(Event::Scroll(length, axis))
+        }
+
+        fn main_display(&self) -> InputResult<(i32, i32)> {
+            Ok((1920, 1080))
+        }
+
+        fn location(&self) -> InputResult<(i32, i32)> {
+            Ok((0, 0))
+        }
+    }
+
+    /// 可在测试中修改显示器布局和鼠标位置的显示器来源
+    #[derive(Clone, Default)]
+    struct FakeDisplay {
+        monitors: Arc<Mutex<Vec<Monitor>>>,
+        mouse: Arc<Mutex<Option<(i32, i32)>>>,
+    }
+
+    impl DisplayProvider for FakeDisplay {
+        fn monitors(&self) -> Vec<Monitor> {
+            self.monitors.lock().unwrap().clone()
+        }
+
+        fn mouse_position(&self) -> Option<(i32, i32)> {
+            *self.mouse.lock().unwrap()
+        }
+    }
+
+    fn monitor(x: i32, y: i32, width: u32, height: u32) -> Monitor {
+        Monitor { x, y, width, height }
+    }
+
+    /// 使用记录后端和单个 1920x1080 显示器创建输入状态
+    fn state_with(config: &Config) -> (InputState, Recorder, FakeDisplay) {
+        let recorder = Recorder::default();
+        let display = FakeDisplay::default();
+        *display.monitors.lock().unwrap() = vec![monitor(0, 0, 1920, 1080)];
+        *display.mouse.lock().unwrap() = Some((960, 540));
+        let state = InputState::with_backend(config, Box::new(recorder.clone()), Box::new(display.clone()));
+        (state, recorder, display)
+    }
+
+    fn test_state() -> (InputState, Recorder, FakeDisplay) {
+        let config = Config { modifier_delay: std::time::Duration::ZERO, ..Config::default() };
+        state_with(&config)
+    }
+
+    /// CRC 开关是全局的，依赖它的测试持有此锁串行执行
+    static CRC_LOCK: Mutex<()> = Mutex::new(());
+
+    /// 设置 CRC 开关，返回的锁在测试结束前保持该设置
+    fn crc_guard(enabled: bool) -> MutexGuard<'static, ()> {
+        let guard = CRC_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        binary_protocol::CRC_ENABLED.store(enabled, Ordering::Relaxed);
+        guard
+    }
+
+    /// 构建二进制摇杆消息（未附加校验和）
+    fn joystick_packet(order: ByteOrder, x: f32, y: f32) -> Vec<u8> {
+        let mut packet = vec![order.magic(), binary_protocol::MSG_JOYSTICK];
+        order.put_u32(&mut packet, x.to_bits());
+        order.put_u32(&mut packet, y.to_bits());
+        packet
+    }
+
+    /// 立即执行所有延后动作（包括执行过程中新安排的），不等待定时线程
+    fn run_all_scheduled(state: &mut InputState) {
+        while !state.scheduled.is_empty() {
+            let now = Instant::now();
+            for (at, _) in state.scheduled.iter_mut() {
+                *at = now;
+            }
+            state.run_scheduled();
+        }
+    }
+
+    #[test]
+    fn uppercase_and_symbols_parse_as_shifted() {
+        assert!(matches!(parse_key("A"), Some(ParsedInput::Shifted(Key::Unicode('a')))));
+        assert!(matches!(parse_key("!"), Some(ParsedInput::Shifted(Key::Unicode('1')))));
+        assert!(matches!(parse_key("@"), Some(ParsedInput::Shifted(Key::Unicode('2')))));
+        assert!(matches!(parse_key("?"), Some(ParsedInput::Shifted(Key::Unicode('/')))));
+        assert!(matches!(parse_key("a"), Some(ParsedInput::Keyboard(Key::Unicode('a')))));
+        assert!(matches!(parse_key("1"), Some(ParsedInput::Keyboard(Key::Unicode('1')))));
+    }
+
+    #[test]
+    fn shifted_character_is_wrapped_in_shift() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("!", true, None);
+        state.handle_button("!", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('1'), Direction::Press),
+                Event::Key(Key::Unicode('1'), Direction::Release),
+                Event::Key(Key::Shift, Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty());
+    }
+
+    #[test]
+    fn uppercase_letter_is_wrapped_in_shift_but_lowercase_is_not() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("A", true, None);
+        state.handle_button("A", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Release),
+                Event::Key(Key::Shift, Direction::Release),
+            ]
+        );
+        state.handle_button("a", true, None);
+        state.handle_button("a", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Unicode('a'), Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty());
+    }
+
+    #[test]
+    fn rumble_event_encodes_intensity_and_duration() {
+        let _crc = crc_guard(false);
+        let rumble = gamepad::Rumble { strong: 0x8000, weak: 0xFFFF, duration_ms: 300 };
+        assert_eq!(
+            build_rumble(rumble, Some(ByteOrder::Little)),
+            binary_protocol::seal(vec![binary_protocol::MAGIC, binary_protocol::MSG_RUMBLE, 0xFF, 0x2C, 0x01])
+        );
+        assert_eq!(
+            build_rumble(rumble, Some(ByteOrder::Big)),
+            binary_protocol::seal(vec![binary_protocol::MAGIC_BE, binary_protocol::MSG_RUMBLE, 0xFF, 0x01, 0x2C])
+        );
+        assert_eq!(
+            build_auth_result(true, Some(ByteOrder::Big)),
+            binary_protocol::seal(vec![binary_protocol::MAGIC_BE, binary_protocol::MSG_AUTH_RESULT, 1])
+        );
+        let json: serde_json::Value = serde_json::from_slice(&build_rumble(rumble, None)).unwrap();
+        assert_eq!(json["type"], "rumble");
+        assert_eq!(json["intensity"], 255);
+        assert_eq!(json["duration_ms"], 300);
+        assert_eq!(gamepad::Rumble::default().intensity(), 0);
+    }
+
+    #[test]
+    fn skill_release_confirms_with_mouse_click_at_target() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_skill_start("q", 0, 0, None, None);
+        recorder.take();
+        state.handle_skill_release("q", 0.5, 0.0);
+        run_all_scheduled(&mut state);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Move(1360, 540, Coordinate::Abs),
+                Event::Button(Button::Left, Direction::Press),
+                Event::Button(Button::Left, Direction::Release),
+                Event::Move(960, 540, Coordinate::Abs),
+            ]
+        );
+    }
+
+    #[test]
+    fn skill_release_confirms_with_key_click_at_target() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_skill_start("q", 0, 0, None, Some("e".to_string()));
+        recorder.take();
+        state.handle_skill_release("q", 0.0, -0.5);
+        run_all_scheduled(&mut state);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Move(960, 140, Coordinate::Abs),
+                Event::Key(Key::Unicode('e'), Direction::Click),
+                Event::Move(960, 540, Coordinate::Abs),
+            ]
+        );
+    }
+
+    #[test]
+    fn velocity_stick_recenters_cursor_over_return_time() {
+        let config = Config {
+            joystick_mode: JoystickMode::Velocity,
+            joystick_max_rate: 1000.0,
+            joystick_recenter: Some(std::time::Duration::from_millis(100)),
+            ..Config::default()
+        };
+        let (mut state, recorder, _) = state_with(&config);
+        let tick_after = |state: &mut InputState, ms: u64| {
+            state.last_tick = Instant::now() - std::time::Duration::from_millis(ms);
+            state.tick();
+        };
+        let moved = |events: Vec<Event>| {
+            events.iter().fold((0, 0), |(x, y), e| match e {
+                Event::Move(dx, dy, Coordinate::Rel) => (x + dx, y + dy),
+                _ => (x, y),
+            })
+        };
+
+        state.handle_joystick(1.0, -0.5);
+        tick_after(&mut state, 100);
+        let (out_x, out_y) = moved(recorder.take());
+        assert!(out_x >= 99 && out_y <= -49, "moved ({}, {})", out_x, out_y);
+
+        // 回中后约一半时间走完一半，到时后正好回到原位
+        state.handle_joystick(0.0, 0.0);
+        tick_after(&mut state, 50);
+        let (half_x, half_y) = moved(recorder.take());
+        assert!((-out_x / 2 - 1..=-out_x / 2 + 1).contains(&half_x), "half step {}", half_x);
+        tick_after(&mut state, 60);
+        tick_after(&mut state, 10);
+        let (rest_x, rest_y) = moved(recorder.take());
+        assert_eq!((half_x + rest_x, half_y + rest_y), (-out_x, -out_y));
+        assert_eq!(state.look_offset, (0, 0));
+    }
+
+    /// 只记录按键的虚拟手柄
+    #[derive(Clone, Default)]
+    struct FakePad {
+        buttons: Arc<Mutex<Vec<(GamepadButton, bool)>>>,
+    }
+
+    impl GamepadOutput for FakePad {
+        fn set_stick(&mut self, _x: f32, _y: f32) {}
+
+        fn button(&mut self, button: GamepadButton, pressed: bool) {
+            self.buttons.lock().unwrap().push((button, pressed));
+        }
+    }
+
+    #[test]
+    fn routed_keys_go_to_gamepad_and_others_to_keyboard() {
+        let mut config = Config::default();
+        config.gamepad_keys.insert("space".to_string(), GamepadButton::A);
+        let (mut state, recorder, _) = state_with(&config);
+        let pad = FakePad::default();
+        state.gamepad = Some(Box::new(pad.clone()));
+
+        state.handle_button("Space", true, None);
+        state.handle_button("Space", false, None);
+        state.handle_button("pad_b", true, None);
+        assert_eq!(
+            *pad.buttons.lock().unwrap(),
+            vec![(GamepadButton::A, true), (GamepadButton::A, false), (GamepadButton::B, true)]
+        );
+        assert!(recorder.take().is_empty());
+
+        state.handle_button("e", true, None);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Unicode('e'), Direction::Press)]);
+        assert_eq!(pad.buttons.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn special_keys_map_where_the_platform_has_them() {
+        let names = [
+            "menu", "apps", "contextmenu", "printscreen", "prtsc", "prtscr", "scrolllock", "pause",
+            "break", "insert", "ins", "numlock",
+        ];
+        for name in names {
+            let parsed = parse_key(name);
+            if cfg!(target_os = "macos") {
+                assert!(parsed.is_none(), "{} should be unavailable on macOS", name);
+            } else {
+                assert!(matches!(parsed, Some(ParsedInput::Keyboard(_))), "{} should be a key", name);
+            }
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            assert!(matches!(parse_key("menu"), Some(ParsedInput::Keyboard(Key::LMenu))));
+            assert!(matches!(parse_key("PrtSc"), Some(ParsedInput::Keyboard(Key::PrintScr))));
+            assert!(matches!(parse_key("scrolllock"), Some(ParsedInput::Keyboard(Key::ScrollLock))));
+            assert!(matches!(parse_key("break"), Some(ParsedInput::Keyboard(Key::Pause))));
+        }
+        #[cfg(target_os = "windows")]
+        {
+            assert!(matches!(parse_key("apps"), Some(ParsedInput::Keyboard(Key::Apps))));
+            assert!(matches!(parse_key("scrolllock"), Some(ParsedInput::Keyboard(Key::Scroll))));
+        }
+        assert!(special_key("bogus").is_none());
+    }
+
+    #[test]
+    fn f13_to_f24_parse_as_function_keys() {
+        for n in 13..=24 {
+            let name = format!("f{}", n);
+            let parsed = parse_key(&name);
+            if cfg!(target_os = "macos") && n > 20 {
+                assert!(parsed.is_none(), "{} should be unavailable on macOS", name);
+            } else {
+                assert!(matches!(parsed, Some(ParsedInput::Keyboard(_))), "{} should be a key", name);
+            }
+        }
+        assert!(matches!(parse_key("F13"), Some(ParsedInput::Keyboard(Key::F13))));
+        #[cfg(not(target_os = "macos"))]
+        assert!(matches!(parse_key("f24"), Some(ParsedInput::Keyboard(Key::F24))));
+        assert!(parse_key("f25").is_none());
+    }
+
+    #[test]
+    fn every_parse_key_name_is_listed_in_key_names() {
+        // 从源码中取出 parse_key 各分支的按键名称，防止新增按键时忘记更新 --list-keys
+        let source = include_str!("main.rs");
+        let body = &source[source.find("fn parse_key(").unwrap()..];
+        let body = &body[..body.find("\n}\n").unwrap()];
+        let listed: HashSet<&str> = KEY_NAMES
+            .iter()
+            .flat_map(|(_, groups)| groups.iter().flat_map(|aliases| aliases.iter().copied()))
+            .collect();
+        let mut checked = 0;
+        for line in body.lines().map(str::trim).filter(|l| l.starts_with('"')) {
+            let Some(end) = line.find("=>") else { continue };
+            for name in line[..end].split('|').map(|n| n.trim().trim_matches('"')) {
+                assert!(listed.contains(name), "{} 未列入 KEY_NAMES", name);
+                checked += 1;
+            }
+        }
+        assert!(checked > 100, "只从 parse_key 中找到 {} 个名称", checked);
+    }
+
+    #[test]
+    fn special_key_names_keep_the_single_character_fallthrough() {
+        #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+        {
+            assert!(matches!(parse_key("insert"), Some(ParsedInput::Keyboard(Key::Insert))));
+            assert!(matches!(parse_key("ins"), Some(ParsedInput::Keyboard(Key::Insert))));
+            assert!(matches!(parse_key("numlock"), Some(ParsedInput::Keyboard(Key::Numlock))));
+            assert!(matches!(parse_key("pause"), Some(ParsedInput::Keyboard(Key::Pause))));
+            assert!(matches!(parse_key("break"), Some(ParsedInput::Keyboard(Key::Pause))));
+            assert!(matches!(parse_key("printscreen"), Some(ParsedInput::Keyboard(Key::PrintScr))));
+            assert!(matches!(parse_key("prtsc"), Some(ParsedInput::Keyboard(Key::PrintScr))));
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            assert!(matches!(parse_key("menu"), Some(ParsedInput::Keyboard(Key::LMenu))));
+            assert!(matches!(parse_key("apps"), Some(ParsedInput::Keyboard(Key::LMenu))));
+            assert!(matches!(parse_key("scrolllock"), Some(ParsedInput::Keyboard(Key::ScrollLock))));
+        }
+        #[cfg(target_os = "windows")]
+        {
+            assert!(matches!(parse_key("menu"), Some(ParsedInput::Keyboard(Key::Apps))));
+            assert!(matches!(parse_key("apps"), Some(ParsedInput::Keyboard(Key::Apps))));
+            assert!(matches!(parse_key("scrolllock"), Some(ParsedInput::Keyboard(Key::Scroll))));
+        }
+        #[cfg(target_os = "macos")]
+        for name in ["insert", "printscreen", "prtsc", "menu", "apps", "pause", "break", "scrolllock", "numlock"] {
+            assert!(parse_key(name).is_none(), "{} should be unavailable on macOS", name);
+        }
+        // 新增的键名不影响单字符按键
+        for c in ['i', 'p', 'm', 'b', 's', 'n'] {
+            assert!(
+                matches!(parse_key(&c.to_string()), Some(ParsedInput::Keyboard(Key::Unicode(k))) if k == c),
+                "{} should still be a character key",
+                c
+            );
+        }
+    }
+
+    fn button(key: &str, pressed: bool) -> InputMessage {
+        InputMessage::Button { key: key.to_string(), pressed, modifiers: None, seq: None }
+    }
+
+    #[test]
+    fn client_keys_follow_presses_releases_and_batches() {
+        let mut held = ClientKeys::default();
+        held.track(&button("w", true));
+        held.track(&InputMessage::Batch { events: vec![button("e", true), button("w", false)] });
+        held.track(&InputMessage::Turbo { key: "f".to_string(), pressed: true, rate_hz: 10 });
+        assert_eq!(held.keys, HashSet::from(["e".to_string()]));
+        assert_eq!(held.turbo, HashSet::from(["f".to_string()]));
+        held.track(&InputMessage::ReleaseAll);
+        assert!(held.keys.is_empty() && held.turbo.is_empty());
+    }
+
+    #[test]
+    fn disconnecting_client_releases_only_its_own_keys() {
+        let (mut state, recorder, _) = test_state();
+        let (mut first, mut second) = (ClientKeys::default(), ClientKeys::default());
+        for (held, msg) in [(&mut first, button("w", true)), (&mut second, button("e", true))] {
+            held.track(&msg);
+            dispatch_input(&mut state, msg, false);
+        }
+        let turbo = InputMessage::Turbo { key: "f".to_string(), pressed: true, rate_hz: 10 };
+        first.track(&turbo);
+        dispatch_input(&mut state, turbo, false);
+        recorder.take();
+
+        state.release_client(&first);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Unicode('w'), Direction::Release)]);
+        assert_eq!(state.pressed_keys, HashSet::from(["e".to_string()]));
+        assert!(state.turbo.is_empty());
+    }
+
+    #[test]
+    fn turbo_taps_until_stopped_or_released() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_turbo("f", true, 10);
+        let start = state.turbo["f"].1;
+        state.run_turbo(start);
+        let tap = vec![Event::Key(Key::Unicode('f'), Direction::Click)];
+        assert_eq!(recorder.take(), tap);
+        // 重复的开始消息只更新频率，不重新计时
+        state.handle_turbo("f", true, 20);
+        assert_eq!(state.turbo["f"], (std::time::Duration::from_millis(50), start + std::time::Duration::from_millis(100)));
+
+        state.handle_turbo("f", false, 0);
+        state.run_turbo(start + std::time::Duration::from_secs(1));
+        assert!(state.turbo.is_empty() && recorder.take().is_empty());
+
+        state.handle_turbo("f", true, 10);
+        state.release_all();
+        assert!(state.turbo.is_empty());
+        state.run_turbo(Instant::now() + std::time::Duration::from_secs(1));
+        assert!(!recorder.take().contains(&tap[0]));
+    }
+
+    #[test]
+    fn turbo_keys_are_capped_at_max_held_keys() {
+        let config = Config { max_held_keys: 2, ..Config::default() };
+        let (mut state, _, _) = state_with(&config);
+        for key in ["a", "b", "c"] {
+            state.handle_turbo(key, true, 10);
+        }
+        assert_eq!(state.turbo.len(), 2);
+        assert!(!state.turbo.contains_key("c"));
+        // 已在连发的按键仍可更新频率，松开后腾出名额
+        state.handle_turbo("a", true, 5);
+        state.handle_turbo("b", false, 0);
+        state.handle_turbo("c", true, 10);
+        assert!(state.turbo.contains_key("c"));
+    }
+
+    #[test]
+    fn seq_dedup_remembers_only_the_newest_seqs() {
+        let mut dedup = SeqDedup::default();
+        for seq in 0..200 {
+            assert!(dedup.insert(seq));
+        }
+        assert_eq!(dedup.order.len(), MAX_PROCESSED_SEQS);
+        assert_eq!(dedup.seen.len(), MAX_PROCESSED_SEQS);
+        // 最新的 100 个仍被视为重复
+        for seq in 100..200 {
+            assert!(!dedup.insert(seq), "seq {} should be a duplicate", seq);
+        }
+        // 最早的 100 个已被淘汰，可以再次处理
+        for seq in 0..100 {
+            assert!(dedup.insert(seq), "seq {} should have been forgotten", seq);
+        }
+        assert!(dedup.order.iter().all(|seq| dedup.seen.contains(seq)));
+    }
+
+    #[test]
+    fn auth_failures_lock_out_the_source_ip_with_backoff() {
+        let mut guard = AuthGuard::new();
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let other: IpAddr = "192.168.1.21".parse().unwrap();
+        let start = Instant::now();
+        for attempt in 1..MAX_AUTH_ATTEMPTS {
+            assert_eq!(guard.fail(ip, start), (attempt, None));
+        }
+        let first = std::time::Duration::from_secs(AUTH_LOCKOUT_SECS);
+        assert_eq!(guard.fail(ip, start), (MAX_AUTH_ATTEMPTS, Some(first)));
+        assert!(guard.locked(ip, start).is_some());
+        assert!(guard.locked(other, start).is_none());
+
+        // 锁定结束后计数重新开始，再次达到上限时锁定时长翻倍
+        let later = start + first;
+        assert!(guard.locked(ip, later).is_none());
+        for _ in 1..MAX_AUTH_ATTEMPTS {
+            guard.fail(ip, later);
+        }
+        assert_eq!(guard.fail(ip, later), (MAX_AUTH_ATTEMPTS, Some(first * 2)));
+
+        guard.succeed(ip);
+        assert!(guard.locked(ip, later).is_none());
+        assert_eq!(guard.fail(ip, later), (1, None));
+    }
+
+    #[test]
+    fn auth_lockout_is_capped() {
+        let mut guard = AuthGuard::new();
+        let ip: IpAddr = "10.0.0.5".parse().unwrap();
+        let mut now = Instant::now();
+        let mut lockout = None;
+        for _ in 0..(MAX_AUTH_ATTEMPTS * 20) {
+            if let (_, Some(l)) = guard.fail(ip, now) {
+                lockout = Some(l);
+                now += l;
+            }
+        }
+        assert_eq!(lockout, Some(std::time::Duration::from_secs(MAX_AUTH_LOCKOUT_SECS)));
+    }
+
+    #[test]
+    fn arrow_key_joystick_binding_presses_up_arrow() {
+        let mut config = Config::default();
+        config.joystick.set_keys("up", "left", "down", "right");
+        let (mut state, recorder, _) = state_with(&config);
+        state.handle_joystick(0.0, -0.8);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::UpArrow, Direction::Press)]);
+        state.handle_joystick(0.0, 0.0);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::UpArrow, Direction::Release)]);
+    }
+
+    /// 死区 0.2、不带滞回（按下阈值与松开阈值相同）的摇杆配置
+    fn deadzone_config(mode: DeadzoneMode) -> Config {
+        let mut config = Config::default();
+        config.joystick.deadzone_mode = mode;
+        config.joystick.enter = 0.2;
+        config.joystick.exit = 0.2;
+        config
+    }
+
+    #[test]
+    fn radial_deadzone_engages_shallow_diagonal() {
+        let (mut radial, _, _) = state_with(&deadzone_config(DeadzoneMode::Radial));
+        radial.handle_joystick(0.15, 0.15);
+        assert_eq!(radial.joystick_keys, HashSet::from(["s".to_string(), "d".to_string()]));
+
+        let (mut per_axis, recorder, _) = state_with(&deadzone_config(DeadzoneMode::PerAxis));
+        per_axis.handle_joystick(0.15, 0.15);
+        assert!(per_axis.joystick_keys.is_empty());
+        assert!(recorder.take().is_empty());
+    }
+
+    #[test]
+    fn skill_target_is_clamped_to_the_monitor() {
+        let config = Config { default_skill_radius: 800, ..Config::default() };
+        let (mut state, recorder, _) = state_with(&config);
+        state.handle_skill_start("q", 0, 0, None, Some("none".to_string()));
+        recorder.take();
+        state.handle_skill_release("q", 2.0, -2.0);
+        assert_eq!(recorder.take(), vec![Event::Move(1919, 0, Coordinate::Abs)]);
+
+        let second = monitor(1920, 0, 1280, 1024);
+        assert_eq!(second.clamp(5000.0, -10.0), (3199.0, 0.0));
+        assert_eq!(second.clamp(1000.0, 2000.0), (1920.0, 1023.0));
+    }
+
+    #[test]
+    fn crc_rejects_any_single_bit_flip() {
+        let _crc = crc_guard(true);
+        let packet = binary_protocol::seal(joystick_packet(ByteOrder::Little, 0.5, -0.25));
+        assert_eq!(packet.len(), 11);
+        assert!(matches!(
+            parse_message(&packet),
+            Some((InputMessage::Joystick { x, y }, None)) if x == 0.5 && y == -0.25
+        ));
+        for bit in 0..packet.len() * 8 {
+            let mut corrupted = packet.clone();
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            assert!(parse_message(&corrupted).is_none(), "bit {} flip was accepted", bit);
+        }
+        // 缺少校验和的消息同样被拒绝
+        assert!(parse_message(&packet[..10]).is_none());
+    }
+
+    /// 构建带长度前缀技能键的二进制消息：[magic][type][前缀...][key_len][key][浮点数...][尾部...]
+    fn skill_packet(msg_type: u8, prefix: &[u8], key: &str, floats: &[f32], tail: &[u8]) -> Vec<u8> {
+        let mut packet = vec![binary_protocol::MAGIC, msg_type];
+        packet.extend_from_slice(prefix);
+        packet.push(key.len() as u8);
+        packet.extend_from_slice(key.as_bytes());
+        for value in floats {
+            ByteOrder::Little.put_u32(&mut packet, value.to_bits());
+        }
+        packet.extend_from_slice(tail);
+        packet
+    }
+
+    #[test]
+    fn skill_drag_release_cancel_parse_multi_character_keys() {
+        for key in ["q", "space"] {
+            let drag = skill_packet(binary_protocol::MSG_SKILL_DRAG, &[], key, &[0.5, -0.5, 0.7], &[0]);
+            assert!(matches!(
+                parse_binary_message(&drag),
+                Some((InputMessage::SkillDrag { key: k, dx, dy, distance, smooth: false }, None))
+                    if k == key && dx == 0.5 && dy == -0.5 && distance == 0.7
+            ));
+
+            let release = skill_packet(binary_protocol::MSG_SKILL_RELEASE, &[], key, &[0.25, 1.0], &[]);
+            assert!(matches!(
+                parse_binary_message(&release),
+                Some((InputMessage::SkillRelease { key: k, dx, dy, seq: None }, None))
+                    if k == key && dx == 0.25 && dy == 1.0
+            ));
+
+            let seq = 7u32.to_le_bytes();
+            let release = skill_packet(binary_protocol::MSG_RELIABLE_SKILL_RELEASE, &seq, key, &[0.25, 1.0], &[]);
+            assert!(matches!(
+                parse_binary_message(&release),
+                Some((InputMessage::SkillRelease { key: k, seq: Some(7), .. }, Some(7))) if k == key
+            ));
+
+            let cancel = skill_packet(binary_protocol::MSG_SKILL_CANCEL, &[], key, &[], &[]);
+            assert!(matches!(
+                parse_binary_message(&cancel),
+                Some((InputMessage::SkillCancel { key: k, seq: None }, None)) if k == key
+            ));
+        }
+    }
+
+    #[test]
+    fn skill_messages_still_accept_the_single_byte_key_layout() {
+        let mut release = vec![binary_protocol::MAGIC, binary_protocol::MSG_SKILL_RELEASE, b'q'];
+        ByteOrder::Little.put_u32(&mut release, 0.5f32.to_bits());
+        ByteOrder::Little.put_u32(&mut release, 0.0f32.to_bits());
+        assert!(matches!(
+            parse_binary_message(&release),
+            Some((InputMessage::SkillRelease { key, dx, .. }, None)) if key == "q" && dx == 0.5
+        ));
+        // 旧格式的 smooth 字节可省略，默认平滑
+        let mut drag = vec![binary_protocol::MAGIC, binary_protocol::MSG_SKILL_DRAG, b'q'];
+        for value in [0.5f32, -0.5, 0.7] {
+            ByteOrder::Little.put_u32(&mut drag, value.to_bits());
+        }
+        assert!(matches!(
+            parse_binary_message(&drag),
+            Some((InputMessage::SkillDrag { key, dy, smooth: true, .. }, None)) if key == "q" && dy == -0.5
+        ));
+        let cancel = [binary_protocol::MAGIC, binary_protocol::MSG_SKILL_CANCEL, b'e'];
+        assert!(matches!(
+            parse_binary_message(&cancel),
+            Some((InputMessage::SkillCancel { key, .. }, None)) if key == "e"
+        ));
+    }
+
+    /// 测试用的伪随机数（线性同余），保证每次运行的输入相同
+    fn lcg(seed: &mut u64) -> u8 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 56) as u8
+    }
+
+    #[test]
+    fn random_short_buffers_never_panic() {
+        let mut seed = 0x5EED;
+        for _ in 0..20_000 {
+            let len = lcg(&mut seed) as usize % 40;
+            let mut buf: Vec<u8> = (0..len).map(|_| lcg(&mut seed)).collect();
+            // 大多数包使用合法魔数和已知消息类型，才能进入各分支的长度检查
+            if len >= 2 && lcg(&mut seed) < 224 {
+                buf[0] = if lcg(&mut seed) < 128 { binary_protocol::MAGIC } else { binary_protocol::MAGIC_BE };
+                buf[1] = lcg(&mut seed) % (binary_protocol::MSG_RUMBLE + 1);
+            }
+            let _ = parse_binary_message(&buf);
+        }
+    }
+
+    #[test]
+    fn truncated_or_padded_button_packets_are_not_misparsed() {
+        let mut packet = vec![binary_protocol::MAGIC, binary_protocol::MSG_BUTTON, 5];
+        packet.extend_from_slice(b"space");
+        packet.extend_from_slice(&[1, 0]);
+        assert!(matches!(
+            parse_binary_message(&packet),
+            Some((InputMessage::Button { key, pressed: true, .. }, None)) if key == "space"
+        ));
+        for len in 5..packet.len() {
+            assert!(parse_binary_message(&packet[..len]).is_none(), "truncated to {} bytes", len);
+        }
+        let mut padded = packet.clone();
+        padded.push(0);
+        assert!(parse_binary_message(&padded).is_none());
+
+        // key_len 声明超过包尾时不读取任何越界字节
+        let mut reliable = vec![binary_protocol::MAGIC, binary_protocol::MSG_RELIABLE_BUTTON];
+        reliable.extend_from_slice(&1u32.to_le_bytes());
+        reliable.extend_from_slice(&[200, b'w', 1, 0]);
+        assert!(parse_binary_message(&reliable).is_none());
+    }
+
+    #[test]
+    fn short_packet_after_long_one_does_not_read_stale_bytes() {
+        let _crc = crc_guard(false);
+        // 与接收循环相同：缓冲区跨包复用
+        let mut buf = [0u8; 1024];
+        let mut long = joystick_packet(ByteOrder::Little, 0.9, -0.9);
+        long.resize(64, b'w');
+        buf[..long.len()].copy_from_slice(&long);
+        let packet = received_packet(&buf, long.len()).unwrap();
+        assert!(matches!(parse_message(packet), Some((InputMessage::Joystick { .. }, None))));
+
+        // 声明 key_len = 5 但只带了 1 个字节，残留的 'w' 不能被当作按键内容
+        let short = [binary_protocol::MAGIC, binary_protocol::MSG_BUTTON, 5, b'q', 1];
+        buf[..short.len()].copy_from_slice(&short);
+        let packet = received_packet(&buf, short.len()).unwrap();
+        assert_eq!(packet, short);
+        assert!(parse_message(packet).is_none());
+
+        let old = [binary_protocol::MAGIC, binary_protocol::MSG_BUTTON, b'q', 1];
+        buf[..old.len()].copy_from_slice(&old);
+        assert!(matches!(
+            parse_message(received_packet(&buf, old.len()).unwrap()),
+            Some((InputMessage::Button { key, pressed: true, modifiers: None, .. }, None)) if key == "q"
+        ));
+    }
+
+    #[test]
+    fn rate_limiter_keeps_half_the_quota_for_input() {
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let mut limiter = RateLimiter::new(10);
+        // 非输入消息只在剩余配额不少于一半时放行
+        let pings = (0..20).filter(|_| limiter.allow(false, &addr)).count();
+        assert_eq!(pings, 6);
+        // 心跳被限流后输入仍可使用剩余配额
+        let inputs = (0..20).filter(|_| limiter.allow(true, &addr)).count();
+        assert_eq!(inputs, 4);
+        assert!(!limiter.allow(true, &addr));
+    }
+
+    #[test]
+    fn rate_limit_is_shared_by_every_port_of_an_ip() {
+        let mut limits = IpRateLimits::new(4);
+        let allowed = (0..8u16)
+            .filter(|port| limits.allow(true, &SocketAddr::from(([192, 0, 2, 1], 4000 + port))))
+            .count();
+        assert_eq!(allowed, 4);
+        assert!(limits.allow(true, &"192.0.2.2:4000".parse().unwrap()));
+        assert_eq!(limits.limiters.len(), 2);
+    }
+
+    #[test]
+    fn reported_sources_are_bounded() {
+        let mut seen = HashSet::new();
+        assert!(first_report(&mut seen, 0usize));
+        assert!(!first_report(&mut seen, 0usize));
+        for source in 1..MAX_LOGGED_SOURCES {
+            assert!(first_report(&mut seen, source));
+        }
+        assert_eq!(seen.len(), MAX_LOGGED_SOURCES);
+        // 已满时新来源清空记录后重新开始
+        assert!(first_report(&mut seen, MAX_LOGGED_SOURCES));
+        assert_eq!(seen.len(), 1);
+    }
+
+    #[test]
+    fn batches_accept_only_plain_input_events() {
+        let batch = |events: &str| parse_message(format!(r#"{{"type":"batch","events":[{}]}}"#, events).as_bytes());
+        let press = r#"{"type":"button","key":"a","pressed":true}"#;
+        assert!(matches!(
+            batch(&format!("{},{}", press, r#"{"type":"joystick","x":0.5,"y":0.0}"#)),
+            Some((InputMessage::Batch { events }, None)) if events.len() == 2
+        ));
+        // 嵌套、控制消息和带序列号的可靠消息都会让整条批量消息被丢弃（单独发送时均可解析）
+        assert!(batch(&format!(r#"{{"type":"batch","events":[{}]}}"#, press)).is_none());
+        for event in [
+            r#"{"type":"ping","timestamp":1}"#,
+            r#"{"type":"auth","pin":"123456"}"#,
+            r#"{"type":"query"}"#,
+            r#"{"type":"button","key":"a","pressed":true,"seq":7}"#,
+        ] {
+            assert!(parse_message(event.as_bytes()).is_some());
+            assert!(batch(event).is_none());
+        }
+        // 事件数超过上限
+        let events = vec![press; MAX_BATCH_EVENTS + 1].join(",");
+        assert!(batch(&events).is_none());
+        let events = vec![press; MAX_BATCH_EVENTS].join(",");
+        assert!(batch(&events).is_some());
+    }
+
+    #[test]
+    fn datagram_filling_the_buffer_is_treated_as_truncated() {
+        let buf = [b'x'; 65];
+        assert!(received_packet(&buf, buf.len()).is_none());
+        assert_eq!(received_packet(&buf, 64).map(<[u8]>::len), Some(64));
+        assert_eq!(received_packet(&buf, 0), Some(&[][..]));
+    }
+
+    #[test]
+    fn radial_diagonal_presses_exactly_the_up_right_pair() {
+        let (mut state, recorder, _) = state_with(&deadzone_config(DeadzoneMode::Radial));
+        state.handle_joystick(0.5, -0.5);
+        assert_eq!(state.joystick_keys, HashSet::from(["w".to_string(), "d".to_string()]));
+        let mut pressed: Vec<Event> = recorder.take();
+        pressed.sort_by_key(|e| format!("{:?}", e));
+        assert_eq!(
+            pressed,
+            vec![
+                Event::Key(Key::Unicode('d'), Direction::Press),
+                Event::Key(Key::Unicode('w'), Direction::Press),
+            ]
+        );
+
+        // 转到正右方的扇区时只松开 w
+        state.handle_joystick(0.7, -0.1);
+        assert_eq!(state.joystick_keys, HashSet::from(["d".to_string()]));
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Unicode('w'), Direction::Release)]);
+    }
+
+    #[test]
+    fn keepalive_uses_the_client_byte_order_magic() {
+        let _crc = crc_guard(false);
+        assert_eq!(
+            build_keepalive(Some(ByteOrder::Big)),
+            vec![binary_protocol::MAGIC_BE, binary_protocol::MSG_KEEPALIVE]
+        );
+        assert_eq!(
+            build_keepalive(Some(ByteOrder::Little)),
+            vec![binary_protocol::MAGIC, binary_protocol::MSG_KEEPALIVE]
+        );
+        let json: serde_json::Value = serde_json::from_slice(&build_keepalive(None)).unwrap();
+        assert_eq!(json["type"], "keepalive");
+    }
+
+    /// 100Hz 摇杆流（1 秒 100 个包，带轻微抖动）推向右上方：
+    /// 只有首包按下 w、d 两次调用，其余 99 个包量化方向不变，不调用 enigo；回中时再松开两次
+    #[test]
+    fn steady_100hz_joystick_stream_calls_enigo_only_on_direction_changes() {
+        let (mut state, recorder, _) = test_state();
+        for i in 0..100 {
+            let jitter = (i % 5) as f32 * 0.01;
+            state.handle_joystick(0.8 + jitter, -0.8 + jitter);
+        }
+        assert_eq!(recorder.take().len(), 2);
+        for _ in 0..100 {
+            state.handle_joystick(0.0, 0.0);
+        }
+        assert_eq!(recorder.take().len(), 2);
+        assert!(state.joystick_keys.is_empty());
+    }
+
+    #[test]
+    fn skill_center_is_recomputed_after_a_resolution_change() {
+        let (mut state, recorder, display) = test_state();
+        state.handle_skill_start("q", 0, 0, None, Some("none".to_string()));
+        assert_eq!(state.skill_center, Some((960, 540)));
+        recorder.take();
+
+        // 技能进行中分辨率降为 1280x720，原中心仍在范围内但显示器已不同
+        *display.monitors.lock().unwrap() = vec![monitor(0, 0, 1280, 720)];
+        *display.mouse.lock().unwrap() = Some((500, 300));
+        state.handle_skill_release("q", 0.0, 0.0);
+        assert_eq!(recorder.take(), vec![Event::Move(640, 360, Coordinate::Abs)]);
+        run_all_scheduled(&mut state);
+        assert_eq!(recorder.take(), vec![Event::Move(640, 360, Coordinate::Abs)]);
+    }
+
+    #[test]
+    fn current_monitor_follows_the_cursor_and_falls_back_to_the_first() {
+        let layout = vec![monitor(0, 0, 1920, 1080), monitor(1920, 0, 2560, 1440)];
+        let on_second = monitor_at(layout.clone(), Some((2500, 700))).unwrap();
+        assert_eq!(on_second, layout[1]);
+        assert_eq!(on_second.center(), (3200, 720));
+        // 边界：x = 1920 属于第二个显示器
+        assert_eq!(monitor_at(layout.clone(), Some((1920, 0))), Some(layout[1].clone()));
+
+        assert_eq!(monitor_at(layout.clone(), Some((-100, 5000))), Some(layout[0].clone()));
+        assert_eq!(monitor_at(layout.clone(), None), Some(layout[0].clone()));
+        assert_eq!(monitor_at(Vec::new(), Some((0, 0))), None);
+
+        // 技能中心取鼠标所在显示器的中心
+        let (mut state, _, display) = test_state();
+        *display.monitors.lock().unwrap() = layout;
+        *display.mouse.lock().unwrap() = Some((2500, 700));
+        state.handle_skill_start("q", 0, 0, None, None);
+        assert_eq!(state.skill_center, Some((3200, 720)));
+    }
+
+    fn ctrl() -> Option<Modifiers> {
+        Some(Modifiers { control: true, ..Modifiers::default() })
+    }
+
+    #[test]
+    fn release_without_modifiers_still_releases_ctrl() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("c", true, ctrl());
+        state.handle_button("c", false, Some(Modifiers::default()));
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Control, Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Release),
+                Event::Key(Key::Control, Direction::Release),
+            ]
+        );
+        assert!(!state.pressed_modifiers.control);
+
+        // 省略 modifiers 字段时同样释放
+        state.handle_button("c", true, ctrl());
+        state.handle_button("c", false, None);
+        assert!(!state.pressed_modifiers.control);
+        assert_eq!(recorder.take().last(), Some(&Event::Key(Key::Control, Direction::Release)));
+    }
+
+    #[test]
+    fn self_cast_uses_the_button_path_and_keeps_modifiers_owned_by_held_keys() {
+        let config = Config {
+            self_cast: HashSet::from(["q".to_string()]),
+            modifier_delay: std::time::Duration::from_millis(10),
+            ..Config::default()
+        };
+        let (mut state, recorder, _) = state_with(&config);
+        // 技能键等修饰键生效后才按下，松开后再释放修饰键
+        state.handle_skill_start("q", 0, 0, ctrl(), None);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Control, Direction::Press)]);
+        run_all_scheduled(&mut state);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Unicode('q'), Direction::Press),
+                Event::Key(Key::Unicode('q'), Direction::Release),
+                Event::Key(Key::Control, Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty() && state.modifier_owners.is_empty());
+
+        // 其他按键仍在使用的 Ctrl 不被自施法松开
+        state.handle_button("ctrl", true, None);
+        recorder.take();
+        state.handle_skill_start("q", 0, 0, ctrl(), None);
+        run_all_scheduled(&mut state);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Unicode('q'), Direction::Press),
+                Event::Key(Key::Unicode('q'), Direction::Release),
+            ]
+        );
+        assert!(state.pressed_modifiers.control);
+    }
+
+    #[test]
+    fn pending_modifier_actions_finish_before_the_next_key() {
+        let config = Config { modifier_delay: std::time::Duration::from_millis(10), ..Config::default() };
+        let (mut state, recorder, _) = state_with(&config);
+        // 延后的 c 先按下，x 不带 Ctrl
+        state.handle_button("c", true, ctrl());
+        state.handle_button("c", false, None);
+        state.handle_button("x", true, None);
+        state.handle_button("x", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Control, Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Release),
+                Event::Key(Key::Control, Direction::Release),
+                Event::Key(Key::Unicode('x'), Direction::Press),
+                Event::Key(Key::Unicode('x'), Direction::Release),
+            ]
+        );
+
+        // 主键尚未按下时到来的文本同样排在其后
+        state.handle_button("c", true, ctrl());
+        state.handle_text("x");
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Control, Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Press),
+                Event::Text("x".to_string()),
+            ]
+        );
+        assert!(state.scheduled.is_empty());
+    }
+
+    #[test]
+    fn big_endian_joystick_decodes_to_the_same_floats() {
+        let little = joystick_packet(ByteOrder::Little, 0.25, -0.75);
+        let big = joystick_packet(ByteOrder::Big, 0.25, -0.75);
+        assert_eq!(big[0], binary_protocol::MAGIC_BE);
+        assert_eq!(&big[2..6], &0.25f32.to_be_bytes());
+        for packet in [little, big] {
+            assert!(matches!(
+                parse_binary_message(&packet),
+                Some((InputMessage::Joystick { x, y }, None)) if x == 0.25 && y == -0.75
+            ));
+        }
+    }
+
+    #[test]
+    fn held_ctrl_button_stays_down_while_c_is_pressed() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("ctrl", true, None);
+        state.handle_button("c", true, None);
+        state.handle_button("c", false, None);
+        state.handle_button("ctrl", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Control, Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Release),
+                Event::Key(Key::Control, Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty());
+        assert!(!state.pressed_modifiers.control);
+    }
+
+    #[test]
+    fn combo_keeps_a_held_ctrl_button_down() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("ctrl", true, None);
+        recorder.take();
+        state.handle_combo(&["ctrl".to_string(), "c".to_string()]);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Unicode('c'), Direction::Press),
+                Event::Key(Key::Unicode('c'), Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.contains("ctrl"));
+        assert!(state.pressed_modifiers.control);
+
+        // 未按住时组合键自行按下并按相反顺序松开，不留下按住记录
+        state.handle_button("ctrl", false, None);
+        recorder.take();
+        state.handle_combo(&["ctrl".to_string(), "shift".to_string(), "t".to_string()]);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Control, Direction::Press),
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('t'), Direction::Press),
+                Event::Key(Key::Unicode('t'), Direction::Release),
+                Event::Key(Key::Shift, Direction::Release),
+                Event::Key(Key::Control, Direction::Release),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty());
+        assert!(state.modifier_owners.is_empty());
+    }
+
+    #[test]
+    fn failed_presses_are_not_recorded_as_held() {
+        let (mut state, recorder, _) = test_state();
+        recorder.set_failing(true);
+        state.handle_button("a", true, None);
+        state.handle_button("A", true, None);
+        state.handle_joystick(0.0, -1.0);
+        assert!(state.pressed_keys.is_empty());
+        assert!(state.joystick_keys.is_empty());
+        assert!(state.modifier_owners.is_empty());
+
+        // 恢复后下一次摇杆更新重新按下方向键
+        recorder.set_failing(false);
+        state.handle_joystick(0.0, -1.0);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Unicode('w'), Direction::Press)]);
+        assert!(state.joystick_keys.contains("w"));
+    }
+
+    #[test]
+    fn drag_button_is_tracked_and_finished_before_new_pointer_input() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_drag("mouse_left", 0.0, 0.0, false);
+        assert_eq!(recorder.take(), vec![Event::Button(Button::Left, Direction::Press)]);
+        assert!(state.pressed_keys.contains("mouse_left"));
+
+        // 新的光标输入前先完成拖放：移到终点、松开，再执行本次移动
+        state.handle_mouse_move(5.0, 0.0);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Move(0, 0, Coordinate::Abs),
+                Event::Button(Button::Left, Direction::Release),
+                Event::Move(5, 0, Coordinate::Rel),
+            ]
+        );
+        assert!(state.pressed_keys.is_empty());
+
+        // 拖放途中断开时由 release_all 松开
+        state.handle_drag("mouse_left", 1.0, 1.0, true);
+        recorder.take();
+        state.release_all();
+        assert!(recorder.take().contains(&Event::Button(Button::Left, Direction::Release)));
+        assert!(state.pressed_keys.is_empty());
+        assert!(state.scheduled.is_empty());
+    }
+
+    #[test]
+    fn middle_button_is_held_and_released_by_release_all() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("mouse_middle", true, None);
+        assert_eq!(recorder.take(), vec![Event::Button(Button::Middle, Direction::Press)]);
+        assert!(state.pressed_keys.contains("mouse_middle"));
+
+        // 按住中键时滚轮仍可独立使用
+        state.handle_button("scroll_down", true, None);
+        let scrolled = recorder.take();
+        assert!(!scrolled.is_empty() && scrolled.iter().all(|e| matches!(e, Event::Scroll(..))));
+        assert!(state.pressed_keys.contains("mouse_middle"));
+
+        state.release_all();
+        assert_eq!(recorder.take(), vec![Event::Button(Button::Middle, Direction::Release)]);
+        assert!(state.pressed_keys.is_empty());
+    }
+
+    #[test]
+    fn new_skill_flushes_the_previous_confirm_before_moving() {
+        let config = Config { skill_click_delay: std::time::Duration::from_secs(1), ..Config::default() };
+        let (mut state, recorder, _) = state_with(&config);
+        state.handle_skill_start("q", 0, 0, None, None);
+        state.handle_skill_release("q", 0.5, 0.0);
+        recorder.take();
+
+        // 确认点击尚未到期就开始下一个技能：先在上一个瞄准位置完成点击和回中，再移到新的中心
+        state.handle_skill_start("e", 100, 0, None, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Button(Button::Left, Direction::Press),
+                Event::Button(Button::Left, Direction::Release),
+                Event::Move(960, 540, Coordinate::Abs),
+                Event::Key(Key::Unicode('e'), Direction::Click),
+                Event::Move(1060, 540, Coordinate::Abs),
+            ]
+        );
+        assert!(state.scheduled.is_empty());
+    }
+
+    #[test]
+    fn scheduled_actions_run_in_due_time_order() {
+        let (mut state, recorder, _) = test_state();
+        state.schedule(std::time::Duration::from_millis(2), ScheduledAction::MoveMouse((2, 2)));
+        state.schedule(std::time::Duration::ZERO, ScheduledAction::MoveMouse((1, 1)));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        state.run_scheduled();
+        assert_eq!(
+            recorder.take(),
+            vec![Event::Move(1, 1, Coordinate::Abs), Event::Move(2, 2, Coordinate::Abs)]
+        );
+    }
+
+    #[test]
+    fn shift_is_shared_by_held_shifted_characters() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("A", true, None);
+        state.handle_button("!", true, None);
+        // 重复的按下不会再次按下 Shift
+        state.handle_button("A", true, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Press),
+                Event::Key(Key::Unicode('1'), Direction::Press),
+            ]
+        );
+
+        // 按住 "A" 时输入的 "b" 不带 Shift
+        state.handle_button("b", true, None);
+        state.handle_button("b", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Shift, Direction::Release),
+                Event::Key(Key::Unicode('b'), Direction::Press),
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('b'), Direction::Release),
+            ]
+        );
+
+        // 松开其中一个时 Shift 仍然按住，全部松开后才释放
+        state.handle_button("A", false, None);
+        assert_eq!(recorder.take(), vec![Event::Key(Key::Unicode('a'), Direction::Release)]);
+        assert!(state.pressed_modifiers.shift);
+        state.handle_button("!", false, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Unicode('1'), Direction::Release),
+                Event::Key(Key::Shift, Direction::Release),
+            ]
+        );
+        assert!(state.modifier_owners.is_empty());
+    }
+
+    #[test]
+    fn held_shift_key_is_kept_when_a_shifted_character_is_released() {
+        let (mut state, recorder, _) = test_state();
+        state.handle_button("shift", true, None);
+        state.handle_button("A", true, None);
+        state.handle_button("A", false, None);
+        state.handle_button("b", true, None);
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Key(Key::Shift, Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Press),
+                Event::Key(Key::Unicode('a'), Direction::Release),
+                Event::Key(Key::Unicode('b'), Direction::Press),
+            ]
+        );
+        assert!(state.pressed_modifiers.shift);
     }
 }
@@ -0,0 +1,145 @@
+//! 运行指标
+//!
+//! 全局共享的原子计数器，收包循环和 TCP 通道都会更新它们；
+//! 启用 `--metrics` 后以 Prometheus 文本格式通过 HTTP 导出，供监控系统抓取。
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+
+pub struct Metrics {
+    /// 收到的数据包总数（UDP + TCP 帧）
+    pub packets_received: AtomicU64,
+    /// 无法解析的数据包数
+    pub packets_invalid: AtomicU64,
+    /// 因客户端发送过快被限流丢弃的数据包数
+    pub packets_rate_limited: AtomicU64,
+    /// 当前已连接的 UDP 客户端数（TCP 连接数由 tcp 模块统计）
+    pub udp_clients: AtomicI64,
+    /// 输入处理耗时累计（微秒）
+    latency_us_sum: AtomicU64,
+    latency_count: AtomicU64,
+    /// 各消息类型的计数
+    messages: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            packets_received: AtomicU64::new(0),
+            packets_invalid: AtomicU64::new(0),
+            packets_rate_limited: AtomicU64::new(0),
+            udp_clients: AtomicI64::new(0),
+            latency_us_sum: AtomicU64::new(0),
+            latency_count: AtomicU64::new(0),
+            messages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 记录一条已解析的消息
+    pub fn record_message(&self, kind: &'static str) {
+        let mut messages = self.messages.lock().unwrap_or_else(PoisonError::into_inner);
+        *messages.entry(kind).or_insert(0) += 1;
+    }
+
+    /// 记录一次输入处理耗时
+    pub fn record_latency(&self, elapsed: Duration) {
+        self.latency_us_sum
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 以 Prometheus 文本格式输出所有指标
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP touch_server_packets_received_total Packets received from clients.");
+        let _ = writeln!(out, "# TYPE touch_server_packets_received_total counter");
+        let _ = writeln!(out, "touch_server_packets_received_total {}", self.packets_received.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP touch_server_packets_invalid_total Packets that failed to parse.");
+        let _ = writeln!(out, "# TYPE touch_server_packets_invalid_total counter");
+        let _ = writeln!(out, "touch_server_packets_invalid_total {}", self.packets_invalid.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP touch_server_packets_rate_limited_total Packets dropped by per-client rate limiting.");
+        let _ = writeln!(out, "# TYPE touch_server_packets_rate_limited_total counter");
+        let _ = writeln!(out, "touch_server_packets_rate_limited_total {}", self.packets_rate_limited.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP touch_server_messages_total Parsed messages by type.");
+        let _ = writeln!(out, "# TYPE touch_server_messages_total counter");
+        let messages = self.messages.lock().unwrap_or_else(PoisonError::into_inner);
+        for (kind, count) in messages.iter() {
+            let _ = writeln!(out, "touch_server_messages_total{{type=\"{}\"}} {}", kind, count);
+        }
+        drop(messages);
+
+        let _ = writeln!(out, "# HELP touch_server_input_latency_seconds Time spent applying input messages.");
+        let _ = writeln!(out, "# TYPE touch_server_input_latency_seconds summary");
+        let sum = self.latency_us_sum.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "touch_server_input_latency_seconds_sum {}", sum);
+        let _ = writeln!(out, "touch_server_input_latency_seconds_count {}", self.latency_count.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP touch_server_connected_clients Currently connected clients by transport.");
+        let _ = writeln!(out, "# TYPE touch_server_connected_clients gauge");
+        let _ = writeln!(out, "touch_server_connected_clients{{transport=\"udp\"}} {}", self.udp_clients.load(Ordering::Relaxed));
+        let _ = writeln!(out, "touch_server_connected_clients{{transport=\"tcp\"}} {}", crate::tcp::connected_clients());
+
+        out
+    }
+}
+
+/// 启动 Prometheus 指标导出端点（任意路径均返回指标）
+pub fn spawn_exporter(bind: &str) {
+    let listener = match TcpListener::bind(bind) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("[指标] 监听 {} 失败: {}", bind, e);
+            return;
+        }
+    };
+    info!("[指标] Prometheus 端点: http://{}/metrics", bind);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            // 只需读掉请求头，内容无关紧要
+            let mut request = [0u8; 1024];
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+            let _ = stream.read(&mut request);
+
+            let body = METRICS.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connected_clients_gauge_covers_both_transports() {
+        let metrics = Metrics::new();
+        metrics.udp_clients.store(2, Ordering::Relaxed);
+        let out = metrics.render();
+        assert!(out.contains("touch_server_connected_clients{transport=\"udp\"} 2\n"));
+        assert!(out.contains(&format!(
+            "touch_server_connected_clients{{transport=\"tcp\"}} {}\n",
+            crate::tcp::connected_clients()
+        )));
+    }
+}
@@ -0,0 +1,159 @@
+//! 监听套接字与地址族
+//!
+//! 默认只监听 IPv4。`--ip-mode ipv6` 只监听 IPv6；`dual` 监听 `[::]` 并关闭 IPV6_V6ONLY，
+//! 同一个套接字同时接受 IPv6 和 IPv4 客户端（IPv4 地址以 `::ffff:a.b.c.d` 形式出现）。
+//! `--bind` 指定具体地址时只在该地址上监听，mDNS 也只广播该地址，避免暴露在 VPN、公网等其他网卡上。
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+
+use local_ip_address::{local_ip, local_ipv6};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::IpMode;
+
+/// 监听地址：指定了 bind 时使用它，否则为对应地址族的任意地址
+fn listen_addr(mode: IpMode, bind: Option<IpAddr>, port: u16) -> SocketAddr {
+    let ip = bind.unwrap_or(match mode {
+        IpMode::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpMode::V6 | IpMode::Dual => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    });
+    SocketAddr::new(ip, port)
+}
+
+/// 按地址族创建并绑定套接字
+fn bind_socket(mode: IpMode, bind: Option<IpAddr>, port: u16, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let addr = listen_addr(mode, bind, port);
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        // 各平台默认值不同（Linux 默认双栈，Windows 默认仅 IPv6），显式设置
+        socket.set_only_v6(mode != IpMode::Dual)?;
+    }
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// 绑定 UDP 输入端口
+pub fn bind_udp(mode: IpMode, bind: Option<IpAddr>, port: u16) -> io::Result<UdpSocket> {
+    Ok(bind_socket(mode, bind, port, Type::DGRAM, Protocol::UDP)?.into())
+}
+
+/// 绑定 TCP 可靠通道端口
+pub fn bind_tcp(mode: IpMode, bind: Option<IpAddr>, port: u16) -> io::Result<TcpListener> {
+    let socket = bind_socket(mode, bind, port, Type::STREAM, Protocol::TCP)?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// 指定了具体监听地址（非任意地址）时返回它
+pub fn fixed_bind(bind: Option<IpAddr>) -> Option<IpAddr> {
+    bind.filter(|ip| !ip.is_unspecified())
+}
+
+/// 本机局域网地址（用于显示连接地址和 mDNS 广播），获取失败的地址族会被跳过；
+/// 指定了具体监听地址时只返回该地址
+pub fn local_addrs(mode: IpMode, bind: Option<IpAddr>) -> Vec<IpAddr> {
+    if let Some(ip) = fixed_bind(bind) {
+        return vec![ip];
+    }
+    let v4 = || local_ip().ok();
+    let v6 = || local_ipv6().ok();
+    match mode {
+        IpMode::V4 => v4().into_iter().collect(),
+        IpMode::V6 => v6().into_iter().collect(),
+        IpMode::Dual => v4().into_iter().chain(v6()).collect(),
+    }
+}
+
+/// 单个地址或网段（`192.168.1.20`、`192.168.1.0/24`、`fe80::/10`）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRule {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRule {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+        // `::ffff:a.b.c.d/n` 按 IPv4 规则保存，前缀去掉映射部分的 96 位；短于 96 位的前缀跨出了映射范围，无法换算
+        match (addr, addr.to_canonical()) {
+            (IpAddr::V6(_), canonical @ IpAddr::V4(_)) => {
+                Some(Self { addr: canonical, prefix: prefix.checked_sub(96)? })
+            }
+            _ => Some(Self { addr, prefix }),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 客户端地址白名单/黑名单：黑名单优先，白名单为空表示不限制。
+/// 双栈模式下 `::ffff:a.b.c.d` 按对应的 IPv4 地址匹配
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    pub allow: Vec<IpRule>,
+    pub deny: Vec<IpRule>,
+}
+
+impl AccessList {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|rule| rule.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| rule.contains(ip))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn v4_mapped_rules_match_only_their_ipv4_range() {
+        let single = IpRule::parse("::ffff:192.168.1.20").unwrap();
+        assert_eq!(single, IpRule::parse("192.168.1.20/32").unwrap());
+        assert!(single.contains(ip("192.168.1.20")));
+        assert!(single.contains(ip("::ffff:192.168.1.20")));
+        assert!(!single.contains(ip("192.168.1.21")));
+        assert!(!single.contains(ip("10.0.0.1")));
+
+        let subnet = IpRule::parse("::ffff:192.168.1.0/120").unwrap();
+        assert!(subnet.contains(ip("192.168.1.200")));
+        assert!(!subnet.contains(ip("192.168.2.1")));
+
+        // 前缀短于 96 位时跨出了映射范围，不能换算为 IPv4 网段
+        assert!(IpRule::parse("::ffff:192.168.1.0/95").is_none());
+        assert!(IpRule::parse("192.168.1.0/33").is_none());
+    }
+}
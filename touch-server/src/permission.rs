@@ -0,0 +1,42 @@
+//! 系统权限预检
+//!
+//! macOS 未授予“辅助功能”权限时 Enigo 仍能创建成功，但模拟的按键和鼠标事件会被系统静默丢弃，
+//! 启动时提前检查并给出设置路径，避免用户连接后“什么都没发生”。
+//! Linux 的 Wayland 会话通常不允许全局注入输入，同样在启动时提示。
+
+#[cfg(target_os = "macos")]
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXIsProcessTrusted() -> bool;
+}
+
+/// 检查当前进程能否模拟输入，缺少权限时输出指引并返回 false
+#[cfg(target_os = "macos")]
+pub fn check_input_permission() -> bool {
+    // SAFETY: 无参数的只读查询
+    if unsafe { AXIsProcessTrusted() } {
+        return true;
+    }
+    log::error!("[权限] 未获得“辅助功能”权限，按键和鼠标事件将被系统忽略");
+    log::error!("[权限] 请打开 系统设置 → 隐私与安全性 → 辅助功能，允许运行本程序的终端或 touch-server，然后重启本程序");
+    false
+}
+
+/// Wayland 会话下输入注入可能失败或无效，提示改用 X11 会话
+#[cfg(target_os = "linux")]
+pub fn check_input_permission() -> bool {
+    let session = std::env::var("XDG_SESSION_TYPE").unwrap_or_default();
+    let wayland = session.eq_ignore_ascii_case("wayland") || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    if !wayland {
+        return true;
+    }
+    log::warn!("[权限] 检测到 Wayland 会话，多数合成器不允许全局模拟输入，按键和鼠标可能无效");
+    log::warn!("[权限] 如遇无响应，请在登录界面切换到 X11（Xorg）会话后重新运行");
+    false
+}
+
+/// 其他平台无需额外授权
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub fn check_input_permission() -> bool {
+    true
+}
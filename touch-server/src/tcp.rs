@@ -0,0 +1,309 @@
+//! TCP 可靠通道
+//!
+//! 丢包严重的网络下，按键、技能释放/取消等关键事件可以改走 TCP 保证送达，
+//! 摇杆和技能拖拽仍走 UDP。两条通道共用同一套消息解析和 InputState 处理逻辑。
+//! `--transport tcp|both` 时 TCP 作为完整的传输通道，接受所有消息（UDP 被屏蔽的网络下使用），
+//! 最后一个 TCP 客户端断开且没有 UDP 客户端时释放所有按键。
+//! 每个连接与 UDP 客户端一样按 `--rate-limit` 限流，同时打开的连接数有上限。
+//!
+//! 帧格式：`[len:u16 LE][payload]`，payload 为一条 JSON 或二进制协议消息（启用加密时为加密后的数据）。
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::config::{Config, Transport};
+use crate::binary_protocol::ByteOrder;
+use crate::crypto;
+use crate::metrics::METRICS;
+use crate::net;
+use crate::{
+    authenticate, build_auth_result, build_binary_pong, build_capabilities,
+    dispatch_input, lock_state, parse_message, unix_millis, ClientKeys, InputMessage, InputState,
+    PongMessage, RateLimiter, AUTH_TIMEOUT_SECS,
+};
+
+/// 允许通过 TCP 通道发送的消息类型（ping 作为保活）
+pub const RELIABLE_KINDS: &[&str] = &[
+    "button",
+    "skill_release",
+    "skill_cancel",
+    "set_active",
+    "text",
+    "combo",
+    "click",
+    "drag",
+    "select_profile",
+    "release_all",
+    "turbo",
+    "batch",
+    "auth",
+    "ping",
+    "query",
+];
+
+/// 同时打开的 TCP 连接上限（含未认证的连接），超出时直接关闭新连接
+const MAX_TCP_CONNECTIONS: usize = 32;
+
+/// 当前已认证的 TCP 连接数
+static TCP_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+/// 当前打开的 TCP 连接数（含未认证的连接）
+static TCP_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// 占用一个连接名额，已达上限时返回 false
+fn reserve_connection() -> bool {
+    TCP_CONNECTIONS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < MAX_TCP_CONNECTIONS).then_some(n + 1))
+        .is_ok()
+}
+
+/// 当前已认证的 TCP 连接数
+pub fn connected_clients() -> usize {
+    TCP_CLIENTS.load(Ordering::Relaxed)
+}
+
+/// 绑定 TCP 端口并在后台线程中接受连接（启用 PIN 时每个连接需先认证），绑定失败时返回错误
+pub fn spawn_listener(config: &Config, input_state: Arc<Mutex<InputState>>) -> io::Result<()> {
+    let port = config.tcp_port.unwrap_or(config.port);
+    let listener = net::bind_tcp(config.ip_mode, config.bind, port)?;
+    let config = Arc::new(config.clone());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            if let Ok(peer) = stream.peer_addr() {
+                if !config.access.allows(peer.ip()) {
+                    warn!("[访问控制] 拒绝来自 {} 的 TCP 连接", peer.ip());
+                    continue;
+                }
+            }
+            if !reserve_connection() {
+                warn!("[TCP] 连接数已达上限 {}，拒绝新连接", MAX_TCP_CONNECTIONS);
+                continue;
+            }
+            let input_state = Arc::clone(&input_state);
+            let config = Arc::clone(&config);
+            thread::spawn(move || {
+                let Ok(peer) = stream.peer_addr() else { return };
+                info!("[TCP] 已连接: {}", peer);
+                let mut counted = false;
+                let mut held = ClientKeys::default();
+                match handle_connection(stream, peer, &input_state, &config, &mut counted, &mut held) {
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                        info!("[TCP] 客户端 {} 超时未发送消息，视为断开", peer);
+                    }
+                    _ => info!("[TCP] 断开: {}", peer),
+                }
+                // 连接异常断开时松开该连接按住的按键，其他客户端按住的按键不受影响
+                lock_state(&input_state).release_client(&held);
+                if counted {
+                    let remaining = TCP_CLIENTS.fetch_sub(1, Ordering::Relaxed) - 1;
+                    // 完整传输模式下由 TCP 客户端按住的按键需要在断开时释放
+                    if remaining == 0
+                        && config.transport != Transport::Udp
+                        && METRICS.udp_clients.load(Ordering::Relaxed) == 0
+                    {
+                        info!("[TCP] 所有客户端已断开，释放所有按键");
+                        lock_state(&input_state).release_all();
+                    }
+                }
+                TCP_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+    Ok(())
+}
+
+/// 读取一帧数据，连接关闭时返回 Ok(None)
+fn read_frame(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 2];
+    match stream.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut payload = vec![0u8; u16::from_le_bytes(len_buf) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// 写入一帧数据（启用加密时先加密），超过帧长度上限时返回 InvalidInput 且不写入任何数据
+fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let payload = crypto::seal(payload);
+    let len = u16::try_from(payload.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("帧长度 {} 超过 {} 字节", payload.len(), u16::MAX))
+    })?;
+    stream.write_all(&len.to_le_bytes())?;
+    stream.write_all(&payload)
+}
+
+/// 连接的读超时：未认证时为认证时限；tcp/both 模式下客户端的 ping 兼作保活，
+/// 超过心跳超时没有任何消息视为断开；只传关键事件时连接可以长时间空闲，不设超时
+fn read_timeout(config: &Config, authenticated: bool) -> Option<Duration> {
+    if !authenticated {
+        return Some(Duration::from_secs(AUTH_TIMEOUT_SECS));
+    }
+    (config.transport != Transport::Udp).then_some(config.heartbeat_timeout)
+}
+
+/// 处理一个连接；认证通过后 counted 置为 true（计入 TCP_CLIENTS，由调用方在断开时扣减），
+/// 该连接按住的按键记录在 held 中，由调用方在断开时释放
+fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    input_state: &Mutex<InputState>,
+    config: &Config,
+    counted: &mut bool,
+    held: &mut ClientKeys,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let pin = config.pin.as_deref();
+    // tcp/both 模式下 TCP 是完整的传输通道，否则只接受关键事件
+    let all_kinds = config.transport != Transport::Udp;
+    // 未认证的半开连接在超时后断开，认证通过后改用心跳超时
+    let mut authenticated = pin.is_none();
+    if authenticated {
+        TCP_CLIENTS.fetch_add(1, Ordering::Relaxed);
+        *counted = true;
+    }
+    let connected_at = Instant::now();
+    let mut rate_limiter = RateLimiter::new(config.rate_limit);
+    stream.set_read_timeout(read_timeout(config, authenticated))?;
+
+    while let Some(payload) = read_frame(&mut stream)? {
+        METRICS.packets_received.fetch_add(1, Ordering::Relaxed);
+        let Some(payload) = crypto::open(&payload) else {
+            METRICS.packets_invalid.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+        let byte_order = ByteOrder::of(&payload);
+        // TCP 本身可靠，不需要 ACK 和去重
+        let Some((msg, _seq)) = parse_message(&payload) else {
+            METRICS.packets_invalid.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+
+        let kind = msg.kind();
+        METRICS.record_message(kind);
+        if !rate_limiter.allow(msg.is_input(), &peer) {
+            continue;
+        }
+
+        if let InputMessage::Auth { pin: attempt } = &msg {
+            // PIN 错误按来源 IP 累计，重新连接不会清零；锁定期间直接断开
+            let ok = if authenticated {
+                true
+            } else {
+                match authenticate(pin, attempt, peer) {
+                    Some(ok) => ok,
+                    None => {
+                        info!("[TCP] 客户端 {} PIN 错误次数过多，仍在锁定期，断开连接", peer);
+                        return Ok(());
+                    }
+                }
+            };
+            write_frame(&mut stream, &build_auth_result(ok, byte_order))?;
+            if ok && !authenticated {
+                info!("[TCP] 客户端 {} 认证成功", peer);
+                authenticated = true;
+                TCP_CLIENTS.fetch_add(1, Ordering::Relaxed);
+                *counted = true;
+                stream.set_read_timeout(read_timeout(config, authenticated))?;
+            }
+            continue;
+        }
+        // 未认证的连接静默丢弃其他消息，超过认证时限则断开
+        if !authenticated {
+            if connected_at.elapsed().as_secs() > AUTH_TIMEOUT_SECS {
+                return Ok(());
+            }
+            continue;
+        }
+        if !all_kinds && !RELIABLE_KINDS.contains(&kind) {
+            debug!("[TCP] 忽略非可靠消息类型 {} ({})", kind, peer);
+            continue;
+        }
+
+        match msg {
+            InputMessage::Ping { timestamp } => {
+                if let Some(order) = byte_order {
+                    write_frame(&mut stream, &build_binary_pong(timestamp, order))?;
+                } else {
+                    let pong = PongMessage {
+                        r#type: "pong",
+                        timestamp,
+                        server_time: Some(unix_millis()),
+                        one_way_delay_ms: None,
+                    };
+                    if let Ok(data) = serde_json::to_vec(&pong) {
+                        write_frame(&mut stream, &data)?;
+                    }
+                }
+            }
+            InputMessage::Capabilities { .. } => write_frame(&mut stream, &build_capabilities(config))?,
+            InputMessage::Query if config.pin.is_none() => {
+                debug!("[TCP] 未启用 PIN，忽略 {} 的状态查询", peer);
+            }
+            InputMessage::Query => {
+                let status = lock_state(input_state).status();
+                write_frame(&mut stream, &status)?;
+            }
+            msg => {
+                held.track(&msg);
+                dispatch_input(&mut lock_state(input_state), msg, config.log_filter.allows(kind));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_with_a_length_prefix() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"{\"type\":\"ping\"}").unwrap();
+        assert_eq!(&wire[..2], &15u16.to_le_bytes());
+        let mut reader = wire.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap().as_deref(), Some(&b"{\"type\":\"ping\"}"[..]));
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected_without_writing() {
+        let mut wire = Vec::new();
+        let err = write_frame(&mut wire, &vec![0u8; u16::MAX as usize + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(wire.is_empty());
+        write_frame(&mut wire, &vec![0u8; u16::MAX as usize]).unwrap();
+        assert_eq!(wire.len(), 2 + u16::MAX as usize);
+    }
+
+    #[test]
+    fn authenticated_connections_time_out_only_as_a_full_transport() {
+        let mut config = Config { heartbeat_timeout: Duration::from_secs(7), ..Config::default() };
+        assert_eq!(read_timeout(&config, false), Some(Duration::from_secs(AUTH_TIMEOUT_SECS)));
+        assert_eq!(read_timeout(&config, true), None);
+        for transport in [Transport::Tcp, Transport::Both] {
+            config.transport = transport;
+            assert_eq!(read_timeout(&config, true), Some(Duration::from_secs(7)));
+        }
+    }
+
+    #[test]
+    fn connections_beyond_the_cap_are_refused() {
+        let reserved = (0..MAX_TCP_CONNECTIONS + 5).filter(|_| reserve_connection()).count();
+        assert_eq!(reserved, MAX_TCP_CONNECTIONS);
+        TCP_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+        assert!(reserve_connection());
+        TCP_CONNECTIONS.store(0, Ordering::Relaxed);
+    }
+}